@@ -1,37 +1,219 @@
 use anyhow::{Context, Result};
-use cpal::traits::{DeviceTrait, StreamTrait};
-use crossbeam_channel::Sender;
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use crossbeam_channel::{Receiver, Sender};
 use rubato::Resampler;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use space_tts_common::warn;
+use space_tts_common::{info, warn};
 
 pub struct CaptureConfig {
     pub sample_rate: u32,
     pub channels: u16,
+    /// Return processed chunks here so the audio callback can reuse them.
+    pub buffer_pool: BufferPool,
+    /// Set when the device is gone or the stream must be rebuilt.
+    pub failed: Arc<AtomicBool>,
+}
+
+/// Recycles capture buffers between the main loop and the audio callback,
+/// so the steady-state capture path doesn't allocate.
+#[derive(Clone)]
+pub struct BufferPool {
+    tx: Sender<Vec<i16>>,
+    rx: Receiver<Vec<i16>>,
+}
+
+impl BufferPool {
+    fn new(capacity: usize) -> Self {
+        let (tx, rx) = crossbeam_channel::bounded(capacity);
+        Self { tx, rx }
+    }
+
+    /// Take an empty buffer, reusing a recycled one when available.
+    fn take(&self) -> Vec<i16> {
+        self.rx.try_recv().unwrap_or_default()
+    }
+
+    pub fn recycle(&self, mut buf: Vec<i16>) {
+        buf.clear();
+        let _ = self.tx.try_send(buf); // pool full: let it drop
+    }
+}
+
+/// Rates worth asking for instead of a 96k/192k default; everything ends up at 16kHz.
+const PREFERRED_RATES: [u32; 2] = [48000, 44100];
+
+/// Pick a lower capture rate when the default is above what we need.
+/// `supported` holds the (min, max) rate ranges matching the default channel count.
+fn preferred_sample_rate(default_rate: u32, supported: &[(u32, u32)]) -> Option<u32> {
+    if default_rate <= PREFERRED_RATES[0] {
+        return None;
+    }
+    PREFERRED_RATES.into_iter().find(|&rate| {
+        supported
+            .iter()
+            .any(|&(min, max)| min <= rate && rate <= max)
+    })
+}
+
+/// The device's default input config, lowered to 48k/44.1k when it defaults higher.
+/// `capture_rate` (`--capture-rate`) replaces that choice, requested from the
+/// device when it advertises the rate.
+fn capture_config(
+    device: &cpal::Device,
+    capture_rate: Option<u32>,
+) -> Result<cpal::SupportedStreamConfig> {
+    let default = device
+        .default_input_config()
+        .context("Failed to get default input config")?;
+    if let Some(rate) = capture_rate
+        && rate != default.sample_rate()
+    {
+        warn!(
+            "--capture-rate {rate} Hz differs from the device's reported default ({} Hz)",
+            default.sample_rate()
+        );
+    }
+
+    let Ok(configs) = device.supported_input_configs() else {
+        return Ok(default);
+    };
+    let ranges: Vec<_> = configs
+        .filter(|c| c.channels() == default.channels())
+        .collect();
+    let rates: Vec<(u32, u32)> = ranges
+        .iter()
+        .map(|c| (c.min_sample_rate(), c.max_sample_rate()))
+        .collect();
+
+    let rate = match capture_rate {
+        Some(rate) if rates.iter().any(|&(min, max)| min <= rate && rate <= max) => rate,
+        Some(rate) => {
+            warn!(
+                "Device doesn't advertise {rate} Hz: capturing at its default, resampled as {rate} Hz"
+            );
+            return Ok(default);
+        }
+        None => match preferred_sample_rate(default.sample_rate(), &rates) {
+            Some(rate) => rate,
+            None => return Ok(default),
+        },
+    };
+    let range = ranges
+        .into_iter()
+        .find(|c| c.min_sample_rate() <= rate && rate <= c.max_sample_rate())
+        .expect("rate comes from one of the ranges");
+    Ok(range.with_sample_rate(rate))
+}
+
+/// The cpal host called `requested` (case-insensitive, e.g. "alsa", "jack"),
+/// or the default host, with a warning when the requested one is unavailable.
+pub fn host(requested: Option<&str>) -> cpal::Host {
+    let Some(requested) = requested else {
+        return cpal::default_host();
+    };
+    let available = cpal::available_hosts();
+    let Some(&id) = available
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(requested))
+    else {
+        let names: Vec<&str> = available.iter().map(|id| id.name()).collect();
+        warn!(
+            "Audio host '{requested}' not available (have: {}), using the default",
+            names.join(", ")
+        );
+        return cpal::default_host();
+    };
+    cpal::host_from_id(id).unwrap_or_else(|e| {
+        warn!("Audio host '{requested}' unavailable ({e}), using the default");
+        cpal::default_host()
+    })
+}
+
+/// Print every input device's supported configs (for bug reports).
+pub fn print_input_configs(audio_host: Option<&str>) -> Result<()> {
+    let host = host(audio_host);
+    let devices = host
+        .input_devices()
+        .context("Failed to enumerate input devices")?;
+
+    println!("Audio host: {:?}", host.id());
+    for device in devices {
+        let name = device
+            .description()
+            .map(|d| d.name().to_string())
+            .unwrap_or_else(|_| "Unknown".to_string());
+        println!("\n{name}");
+
+        match device.default_input_config() {
+            Ok(c) => println!(
+                "  default: {}, {} Hz, {} ch",
+                c.sample_format(),
+                c.sample_rate(),
+                c.channels()
+            ),
+            Err(e) => println!("  default: unavailable ({e})"),
+        }
+        match device.supported_input_configs() {
+            Ok(configs) => {
+                for c in configs {
+                    println!(
+                        "  {}, {}-{} Hz, {} ch",
+                        c.sample_format(),
+                        c.min_sample_rate(),
+                        c.max_sample_rate(),
+                        c.channels()
+                    );
+                }
+            }
+            Err(e) => println!("  supported configs unavailable ({e})"),
+        }
+    }
+    Ok(())
 }
 
 pub fn start_capture(
     device: &cpal::Device,
     sender: Sender<Vec<i16>>,
+    capture_rate: Option<u32>,
 ) -> Result<(cpal::Stream, CaptureConfig)> {
-    let config = device
-        .default_input_config()
-        .context("Failed to get default input config")?;
+    let config = capture_config(device, capture_rate)?;
 
-    let sample_rate = config.sample_rate();
+    // An override is trusted over what the driver reports (for resampling)
+    let sample_rate = capture_rate.unwrap_or(config.sample_rate());
+    info!("  Capture:  {sample_rate} Hz, {} ch", config.channels());
     let channels = config.channels();
 
     let stream_config: cpal::StreamConfig = config.into();
 
-    let err_fn = |err: cpal::StreamError| {
+    // Enough buffers to cover a full audio channel plus the one being processed
+    let buffer_pool = BufferPool::new(sender.capacity().unwrap_or(64) + 4);
+    let callback_pool = buffer_pool.clone();
+
+    let failed = Arc::new(AtomicBool::new(false));
+    let callback_failed = failed.clone();
+    let err_fn = move |err: cpal::StreamError| {
         warn!("Audio stream error: {err}");
+        if matches!(
+            err,
+            cpal::StreamError::DeviceNotAvailable | cpal::StreamError::StreamInvalidated
+        ) {
+            callback_failed.store(true, Ordering::SeqCst);
+        }
     };
 
     let stream = device
         .build_input_stream(
             &stream_config,
             move |data: &[i16], _: &cpal::InputCallbackInfo| {
-                let _ = sender.try_send(data.to_vec());
+                let mut buf = callback_pool.take();
+                buf.extend_from_slice(data);
+                if let Err(e) = sender.try_send(buf) {
+                    callback_pool.recycle(e.into_inner());
+                }
             },
             err_fn,
             None,
@@ -45,22 +227,169 @@ pub fn start_capture(
         CaptureConfig {
             sample_rate,
             channels,
+            buffer_pool,
+            failed,
         },
     ))
 }
 
-pub type ResamplerFn = Box<dyn FnMut(&[i16]) -> Vec<i16>>;
+/// `--server-resample`: the recent capture audio, downmixed to mono but at its
+/// native rate, so a 16 kHz segment from the VAD can be swapped for the
+/// native-rate audio it was cut from.
+pub struct NativeHistory {
+    samples: VecDeque<i16>,
+    /// Frames pushed since the last clear, `samples` holding the latest ones.
+    pushed: u64,
+    rate: u32,
+    channels: usize,
+    capacity: usize,
+    /// How far the 16 kHz stream lags the capture (the resampler's delay), in 16 kHz samples.
+    delay: u64,
+}
+
+impl NativeHistory {
+    /// Keep enough history for segments of up to `max_segment_samples` at 16 kHz,
+    /// cut from a stream `delay` samples behind the capture.
+    pub fn new(rate: u32, channels: u16, max_segment_samples: usize, delay: usize) -> Self {
+        let capacity = native_len(max_segment_samples, rate);
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            pushed: 0,
+            rate,
+            channels: channels.max(1) as usize,
+            capacity,
+            delay: delay as u64,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Add an interleaved capture chunk.
+    pub fn push(&mut self, interleaved: &[i16]) {
+        self.samples
+            .extend(interleaved.chunks(self.channels).map(|frame| {
+                let sum: i32 = frame.iter().map(|&s| s as i32).sum();
+                (sum / frame.len() as i32) as i16
+            }));
+        self.pushed += (interleaved.len() / self.channels) as u64;
+        let excess = self.samples.len().saturating_sub(self.capacity);
+        self.samples.drain(..excess);
+    }
+
+    /// The native-rate audio matching the 16 kHz samples `start..end` of the
+    /// stream resampled since the last clear.
+    pub fn matching(&self, start: u64, end: u64) -> Vec<i16> {
+        let first = self.pushed - self.samples.len() as u64;
+        let native = |at: u64| {
+            (at.saturating_sub(self.delay) * self.rate as u64 / 16000).clamp(first, self.pushed)
+        };
+        let (start, end) = (native(start), native(end));
+        self.samples
+            .range((start - first) as usize..(end.max(start) - first) as usize)
+            .copied()
+            .collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.pushed = 0;
+    }
+}
+
+/// The last few moments of capture while not listening (`--lookback-ms`), put
+/// before the first chunk once listening starts so a word begun just before
+/// the hotkey press isn't cut. Interleaved samples at the capture rate.
+pub struct Lookback {
+    samples: VecDeque<i16>,
+    capacity: usize,
+}
+
+impl Lookback {
+    pub fn new(rate: u32, channels: u16, ms: u32) -> Self {
+        let channels = channels.max(1) as usize;
+        let capacity = (rate as u64 * ms as u64 / 1000) as usize * channels;
+        Self {
+            samples: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Add an interleaved capture chunk, forgetting the oldest audio past capacity.
+    pub fn push(&mut self, interleaved: &[i16]) {
+        self.samples.extend(interleaved);
+        // capacity is whole frames, so this keeps channels aligned
+        let excess = self.samples.len().saturating_sub(self.capacity);
+        self.samples.drain(..excess);
+    }
+
+    /// The buffered audio followed by `chunk`, leaving the lookback empty.
+    pub fn seed(&mut self, chunk: &[i16]) -> Vec<i16> {
+        let mut seeded: Vec<i16> = self.samples.drain(..).collect();
+        seeded.extend_from_slice(chunk);
+        seeded
+    }
+}
+
+/// Length at `rate` of `len` samples at 16 kHz.
+fn native_len(len: usize, rate: u32) -> usize {
+    (len as u64 * rate as u64 / 16000) as usize
+}
+
+/// Streaming resampler from the capture format to 16 kHz mono, keeping the
+/// filter state across calls.
+pub struct StreamResampler {
+    /// None when the capture is already 16 kHz mono.
+    sinc: Option<SincStream>,
+}
+
+impl StreamResampler {
+    /// Resample an interleaved capture buffer, of any length.
+    pub fn process(&mut self, samples: &[i16]) -> Vec<i16> {
+        match &mut self.sinc {
+            Some(sinc) => sinc.process(samples),
+            None => samples.to_vec(),
+        }
+    }
+
+    /// How many 16 kHz samples the output lags the input by.
+    pub fn delay(&self) -> usize {
+        self.sinc
+            .as_ref()
+            .map_or(0, |sinc| sinc.resampler.output_delay())
+    }
+
+    /// Emit the samples still held in the filter (its delay's worth) and start
+    /// afresh, at the end of a listening session.
+    pub fn flush(&mut self) -> Vec<i16> {
+        match &mut self.sinc {
+            Some(sinc) => sinc.flush(),
+            None => Vec::new(),
+        }
+    }
+}
+
+struct SincStream {
+    resampler: rubato::Async<f64>,
+    channels: usize,
+    ratio: f64,
+    chunk_size: usize,
+    // Scratch buffers reused across calls to keep the hot path allocation-free
+    mono: Vec<f64>,
+    input_data: Vec<Vec<f64>>,
+    output_data: Vec<Vec<f64>>,
+}
 
 pub fn create_resampler(
     source_rate: u32,
     target_rate: u32,
     channels: u16,
-) -> Result<ResamplerFn> {
+) -> Result<StreamResampler> {
     if source_rate == target_rate && channels == 1 {
-        return Ok(Box::new(|samples: &[i16]| samples.to_vec()));
+        return Ok(StreamResampler { sinc: None });
     }
 
-    let ch = channels as usize;
     let ratio = target_rate as f64 / source_rate as f64;
 
     use rubato::{
@@ -76,59 +405,85 @@ pub fn create_resampler(
     };
 
     let chunk_size = 1024;
-    let mut resampler =
-        Async::<f64>::new_sinc(ratio, 1.1, &params, chunk_size, 1, FixedAsync::Input)
-            .map_err(|e| anyhow::anyhow!("Failed to create resampler: {e}"))?;
+    let resampler = Async::<f64>::new_sinc(ratio, 1.1, &params, chunk_size, 1, FixedAsync::Input)
+        .map_err(|e| anyhow::anyhow!("Failed to create resampler: {e}"))?;
+    let output_frames = resampler.output_frames_max();
 
-    Ok(Box::new(move |samples: &[i16]| {
+    Ok(StreamResampler {
+        sinc: Some(SincStream {
+            resampler,
+            channels: channels as usize,
+            ratio,
+            chunk_size,
+            mono: Vec::with_capacity(chunk_size * 4),
+            input_data: vec![vec![0.0; chunk_size]],
+            output_data: vec![vec![0.0; output_frames]],
+        }),
+    })
+}
+
+impl SincStream {
+    fn process(&mut self, samples: &[i16]) -> Vec<i16> {
         // Convert to mono f64 normalized [-1.0, 1.0]
-        let mono: Vec<f64> = if ch == 1 {
-            samples.iter().map(|&s| s as f64 / 32768.0).collect()
+        let ch = self.channels;
+        self.mono.clear();
+        if ch == 1 {
+            self.mono
+                .extend(samples.iter().map(|&s| s as f64 / 32768.0));
         } else {
-            samples
-                .chunks(ch)
-                .map(|frame| {
-                    let sum: f64 = frame.iter().map(|&s| s as f64).sum();
-                    (sum / ch as f64) / 32768.0
-                })
-                .collect()
-        };
+            self.mono.extend(samples.chunks(ch).map(|frame| {
+                let sum: f64 = frame.iter().map(|&s| s as f64).sum();
+                (sum / ch as f64) / 32768.0
+            }));
+        }
+        self.resample_mono()
+    }
+
+    fn flush(&mut self) -> Vec<i16> {
+        // Silence pushes the last real input through: the filter's delay is what's owed
+        let owed = self.resampler.output_delay();
+        let zeros = (owed as f64 / self.ratio).ceil() as usize + 1;
+        self.mono.clear();
+        self.mono.resize(zeros, 0.0);
+        let mut tail = self.resample_mono();
+        tail.truncate(owed);
+        self.resampler.reset();
+        tail
+    }
 
-        // Process in chunk_size frames, collect all output
-        let mut output_all: Vec<i16> = Vec::new();
+    /// Resample `self.mono`, chunk by chunk.
+    fn resample_mono(&mut self) -> Vec<i16> {
+        use audioadapter_buffers::direct::SequentialSliceOfVecs;
+
+        let mut output_all: Vec<i16> =
+            Vec::with_capacity((self.mono.len() as f64 * self.ratio).ceil() as usize + 1);
         let mut offset = 0;
 
-        while offset < mono.len() {
-            let end = (offset + chunk_size).min(mono.len());
-            let chunk = &mono[offset..end];
-
-            // Pad to chunk_size if needed (last partial chunk)
-            let padded: Vec<f64>;
-            let input_slice: &[f64] = if chunk.len() < chunk_size {
-                padded = {
-                    let mut v = chunk.to_vec();
-                    v.resize(chunk_size, 0.0);
-                    v
-                };
-                &padded
-            } else {
-                chunk
-            };
-
-            let input_data: Vec<Vec<f64>> = vec![input_slice.to_vec()];
-            use audioadapter_buffers::direct::SequentialSliceOfVecs;
-            let adapter = SequentialSliceOfVecs::new(&input_data, 1, chunk_size).unwrap();
-
-            match resampler.process(&adapter, 0, None) {
-                Ok(output) => {
-                    let samples: Vec<f64> = output.take_data();
-                    let actual_out = if chunk.len() < chunk_size {
-                        let expected = (chunk.len() as f64 * ratio).ceil() as usize;
-                        &samples[..expected.min(samples.len())]
-                    } else {
-                        &samples[..]
-                    };
-                    for &s in actual_out {
+        while offset < self.mono.len() {
+            let end = (offset + self.chunk_size).min(self.mono.len());
+            let chunk = &self.mono[offset..end];
+
+            // The last partial chunk is resampled at its own size: padding it with
+            // zeros would put silence in the stream and cut the real samples around it
+            if chunk.len() != self.resampler.input_frames_next()
+                && let Err(e) = self.resampler.set_chunk_size(chunk.len())
+            {
+                warn!("Resample error: {e}");
+                break;
+            }
+            self.input_data[0][..chunk.len()].copy_from_slice(chunk);
+
+            let adapter = SequentialSliceOfVecs::new(&self.input_data, 1, chunk.len()).unwrap();
+            let out_frames = self.output_data[0].len();
+            let mut out_adapter =
+                SequentialSliceOfVecs::new_mut(&mut self.output_data, 1, out_frames).unwrap();
+
+            match self
+                .resampler
+                .process_into_buffer(&adapter, &mut out_adapter, None)
+            {
+                Ok((_, produced)) => {
+                    for &s in &self.output_data[0][..produced] {
                         let clamped = s.clamp(-1.0, 1.0);
                         output_all.push((clamped * 32767.0) as i16);
                     }
@@ -142,18 +497,102 @@ pub fn create_resampler(
         }
 
         output_all
-    }))
+    }
+}
+
+/// Levels of a segment about to be transcribed (`--print-segment-audio-stats`):
+/// tells a garbled result caused by clipping or a too-quiet signal from a model issue.
+pub struct SegmentStats {
+    pub seconds: f64,
+    pub rms: f64,
+    pub peak: u16,
+    /// Share of samples at full scale, in percent.
+    pub clipped_percent: f64,
+}
+
+impl SegmentStats {
+    pub fn new(samples: &[i16], rate: u32) -> Self {
+        let count = samples.len().max(1) as f64;
+        let sum_sq: f64 = samples.iter().map(|&s| (s as f64) * (s as f64)).sum();
+        let clipped = samples
+            .iter()
+            .filter(|s| s.unsigned_abs() >= i16::MAX as u16)
+            .count();
+        Self {
+            seconds: samples.len() as f64 / rate.max(1) as f64,
+            rms: (sum_sq / count).sqrt(),
+            peak: samples.iter().map(|s| s.unsigned_abs()).max().unwrap_or(0),
+            clipped_percent: clipped as f64 * 100.0 / count,
+        }
+    }
+}
+
+impl fmt::Display for SegmentStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let dbfs = |level: f64| 20.0 * (level / 32768.0).log10();
+        write!(
+            f,
+            "{:.2}s, RMS {:.0} ({:.1} dBFS), peak {} ({:.1} dBFS), clipped {:.2}%",
+            self.seconds,
+            self.rms,
+            dbfs(self.rms),
+            self.peak,
+            dbfs(self.peak as f64),
+            self.clipped_percent
+        )
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn preferred_rate_lowers_high_defaults() {
+        assert_eq!(
+            preferred_sample_rate(192000, &[(8000, 192000)]),
+            Some(48000)
+        );
+        assert_eq!(
+            preferred_sample_rate(96000, &[(44100, 44100), (96000, 96000)]),
+            Some(44100)
+        );
+        // No lower rate on offer: keep the default
+        assert_eq!(preferred_sample_rate(192000, &[(192000, 192000)]), None);
+        // Defaults at or below 48k are left alone
+        assert_eq!(preferred_sample_rate(48000, &[(8000, 192000)]), None);
+        assert_eq!(preferred_sample_rate(44100, &[(8000, 192000)]), None);
+    }
+
+    /// Timing harness for the capture hot path, to compare before and after
+    /// a change to it along with `--profile`'s resample stage:
+    /// `cargo test -p space_tts_client --release hot_path_timing -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn hot_path_timing() {
+        let mut resample = create_resampler(48000, 16000, 2).unwrap();
+        let pool = BufferPool::new(4);
+        // 10ms of stereo at 48 kHz, a typical callback buffer
+        let buffer: Vec<i16> = (0..960).map(|i| (i * 37 % 2000) as i16 - 1000).collect();
+        let runs = 10_000;
+        let started = std::time::Instant::now();
+        for _ in 0..runs {
+            let mut buf = pool.take();
+            buf.extend_from_slice(&buffer);
+            std::hint::black_box(resample.process(&buf));
+            pool.recycle(buf);
+        }
+        println!(
+            "48 kHz stereo to 16 kHz: {:?} per 10ms buffer",
+            started.elapsed() / runs
+        );
+    }
+
     #[test]
     fn resampler_noop_mono() {
         let mut resample = create_resampler(16000, 16000, 1).unwrap();
         let input: Vec<i16> = (0..1600).collect();
-        let output = resample(&input);
+        let output = resample.process(&input);
         assert_eq!(output, input);
     }
 
@@ -162,7 +601,7 @@ mod tests {
         let mut resample = create_resampler(48000, 16000, 1).unwrap();
         // 100ms at 48kHz = 4800 samples
         let input: Vec<i16> = vec![0; 4800];
-        let output = resample(&input);
+        let output = resample.process(&input);
         // Expected ~1600 samples (100ms at 16kHz), allow some margin
         let expected = 1600;
         let margin = 200;
@@ -172,4 +611,166 @@ mod tests {
             output.len()
         );
     }
+
+    #[test]
+    fn resampler_stereo_48k_monitor_source() {
+        // Monitor sources are typically stereo 48kHz: 100ms = 4800 frames = 9600 samples
+        let mut resample = create_resampler(48000, 16000, 2).unwrap();
+        let input: Vec<i16> = vec![0; 9600];
+        let mut total = 0;
+        for _ in 0..10 {
+            total += resample.process(&input).len();
+        }
+        // 1s of stereo audio downmixed to 1s of 16kHz mono
+        assert!(
+            (total as i64 - 16000).abs() <= 1,
+            "Expected 16000 mono samples, got {total}"
+        );
+    }
+
+    /// Feed `calls` buffers of `call_len` samples and check the total output
+    /// stays within one sample of `input_len * ratio`.
+    fn assert_stream_length(source_rate: u32, call_len: usize, calls: usize) {
+        let mut resample = create_resampler(source_rate, 16000, 1).unwrap();
+        let input: Vec<i16> = vec![0; call_len];
+        let mut total_out = 0usize;
+        for _ in 0..calls {
+            total_out += resample.process(&input).len();
+        }
+        let expected = (call_len * calls) as f64 * 16000.0 / source_rate as f64;
+        assert!(
+            (total_out as f64 - expected).abs() <= 1.0,
+            "{source_rate}Hz: expected {expected} samples, got {total_out}"
+        );
+    }
+
+    #[test]
+    fn resampler_44100_to_16k_stream_length() {
+        assert_stream_length(44100, 441, 500); // 10ms callbacks
+        assert_stream_length(44100, 4410, 50); // 100ms callbacks (full + partial chunks)
+        assert_stream_length(44100, 1000, 300);
+    }
+
+    #[test]
+    fn resampler_22050_to_16k_stream_length() {
+        assert_stream_length(22050, 441, 500); // 20ms callbacks
+        assert_stream_length(22050, 2205, 100); // 100ms callbacks (full + partial chunks)
+        assert_stream_length(22050, 777, 300);
+    }
+
+    #[test]
+    fn resampler_short_buffer_keeps_its_duration() {
+        for (rate, len) in [(48000, 480), (44100, 441), (22050, 300)] {
+            let mut resample = create_resampler(rate, 16000, 1).unwrap();
+            let output = resample.process(&vec![1000; len]).len();
+            let expected = len as f64 * 16000.0 / rate as f64;
+            assert!(
+                (output as f64 - expected).abs() <= 1.0,
+                "{rate}Hz: {len} samples gave {output}, expected ~{expected}"
+            );
+        }
+    }
+
+    #[test]
+    fn resampler_partial_chunks_stay_continuous() {
+        // 10ms callbacks never fill a whole chunk: a steady level must come out
+        // steady, with no silence slipped in at call boundaries
+        let mut resample = create_resampler(44100, 16000, 1).unwrap();
+        let mut output = Vec::new();
+        for _ in 0..50 {
+            output.extend(resample.process(&[10000; 441]));
+        }
+        // Past the filter's ramp-up from its initial silence
+        for (i, &s) in output.iter().enumerate().skip(200) {
+            assert!((9000..=11000).contains(&s), "Sample {i} is {s}");
+        }
+    }
+
+    #[test]
+    fn resampler_flush_emits_the_residual_samples() {
+        let mut resample = create_resampler(44100, 16000, 1).unwrap();
+        let output = resample.process(&[10000; 4410]);
+        let tail = resample.flush();
+        // The filter's delay: under a millisecond and a half at 16 kHz
+        assert!((10..=30).contains(&tail.len()), "{} samples", tail.len());
+        assert!(
+            tail[0] > 9000,
+            "The tail is the end of the speech: {tail:?}"
+        );
+        assert!(
+            (output.len() + tail.len()) as f64 > 1600.0,
+            "Everything fed in came out"
+        );
+
+        // Afresh: the next session doesn't start with the old tail
+        assert!(resample.flush().iter().all(|&s| s == 0));
+        assert_eq!(create_resampler(16000, 16000, 1).unwrap().flush(), []);
+    }
+
+    #[test]
+    fn native_history_matches_segments_at_capture_rate() {
+        let mut history = NativeHistory::new(48000, 2, 16000, 0);
+        // Stereo frames (n, n) downmix to n
+        let chunk: Vec<i16> = (0..4800).flat_map(|n| [n as i16, n as i16]).collect();
+        history.push(&chunk);
+
+        // 100ms at 16 kHz = 4800 samples at 48 kHz
+        let native = history.matching(0, 1600);
+        assert_eq!(native.len(), 4800);
+        assert_eq!(native[0], 0);
+        assert_eq!(native[4799], 4799);
+        // A segment in the middle of the stream, not just its end
+        assert_eq!(history.matching(100, 200), (300..600).collect::<Vec<i16>>());
+    }
+
+    #[test]
+    fn native_history_accounts_for_the_resampler_delay() {
+        let mut history = NativeHistory::new(32000, 1, 16000, 20);
+        history.push(&(0..3200).collect::<Vec<i16>>());
+        // 16 kHz sample 20 is the first one carrying capture audio
+        assert_eq!(history.matching(20, 120), (0..200).collect::<Vec<i16>>());
+        assert_eq!(history.matching(1620, 1700).len(), 0, "Past the capture");
+    }
+
+    #[test]
+    fn native_history_is_capped() {
+        let mut history = NativeHistory::new(32000, 1, 1600, 0);
+        history.push(&[1; 10000]);
+        assert_eq!(history.matching(0, 16000).len(), 3200);
+        assert_eq!(history.matching(0, 100), [], "Forgotten already");
+    }
+
+    #[test]
+    fn lookback_keeps_the_last_moments() {
+        // 10 ms of stereo at 16 kHz: 160 frames
+        let mut lookback = Lookback::new(16000, 2, 10);
+        lookback.push(&[1; 300]);
+        lookback.push(&[2; 200]);
+        let seeded = lookback.seed(&[3, 3]);
+        assert_eq!(seeded.len(), 322);
+        assert_eq!(seeded[..120], [1; 120]);
+        assert_eq!(seeded[120..320], [2; 200]);
+        assert_eq!(seeded[320..], [3, 3]);
+        assert_eq!(lookback.seed(&[]), [], "Seeding empties it");
+    }
+
+    #[test]
+    fn segment_stats_report_levels_and_clipping() {
+        // Half a second at 16 kHz: a quarter at full scale, the rest at -6 dBFS
+        let mut samples = vec![16384i16; 8000];
+        samples[..1000].fill(i16::MAX);
+        samples[1000..2000].fill(i16::MIN);
+        let stats = SegmentStats::new(&samples, 16000);
+        assert_eq!(stats.seconds, 0.5);
+        assert_eq!(stats.peak, 32768);
+        assert_eq!(stats.clipped_percent, 25.0);
+        assert!((21000.0..22000.0).contains(&stats.rms), "{}", stats.rms);
+
+        let silence = SegmentStats::new(&[0; 480], 48000);
+        assert_eq!(
+            (silence.rms, silence.peak, silence.clipped_percent),
+            (0.0, 0, 0.0)
+        );
+        assert!(silence.to_string().starts_with("0.01s, RMS 0 (-inf dBFS)"));
+    }
 }
@@ -0,0 +1,166 @@
+use crate::vad::{self, Segment};
+
+/// Overlap between consecutive windows, so a word cut at a boundary is heard whole once.
+const OVERLAP_SAMPLES: usize = 16000; // 1s
+/// Longest run of repeated words looked for when merging overlapping texts.
+const MAX_OVERLAP_WORDS: usize = 20;
+
+/// `--chunk-mode`: instead of the VAD, cut the audio into fixed windows that
+/// overlap by one second, regardless of pauses. Windows of pure silence are
+/// not worth a transcription (whisper hallucinates on them) and are skipped.
+pub struct Chunker {
+    window_samples: usize,
+    /// Frame RMS below which a window counts as silent.
+    min_rms: f64,
+    buffer: Vec<i16>,
+    /// Samples in `buffer` not yet part of an emitted window.
+    fresh: usize,
+    /// Samples added since the last reset.
+    position: u64,
+}
+
+impl Chunker {
+    pub fn new(window_samples: usize, min_rms: f64) -> Self {
+        Self {
+            // The window must advance past its own overlap
+            window_samples: window_samples.max(2 * OVERLAP_SAMPLES),
+            min_rms,
+            buffer: Vec::new(),
+            fresh: 0,
+            position: 0,
+        }
+    }
+
+    /// Add audio, returning every window completed by it.
+    pub fn process_samples(&mut self, samples: &[i16]) -> Vec<Segment> {
+        self.buffer.extend_from_slice(samples);
+        self.fresh += samples.len();
+        self.position += samples.len() as u64;
+
+        let mut windows = Vec::new();
+        while self.buffer.len() >= self.window_samples {
+            let end = self.position - (self.buffer.len() - self.window_samples) as u64;
+            let tail = self.buffer[self.window_samples - OVERLAP_SAMPLES..].to_vec();
+            let mut window = std::mem::replace(&mut self.buffer, tail);
+            window.truncate(self.window_samples);
+            if !vad::is_silent(&window, self.min_rms) {
+                windows.push(Segment { audio: window, end });
+            }
+            self.fresh = self.buffer.len() - OVERLAP_SAMPLES;
+        }
+        windows
+    }
+
+    /// The last, partial window when listening stops (`None` if it holds only
+    /// audio already sent, or silence).
+    pub fn finish(&mut self) -> Option<Segment> {
+        let window =
+            (self.fresh > 0 && !vad::is_silent(&self.buffer, self.min_rms)).then(|| Segment {
+                audio: std::mem::take(&mut self.buffer),
+                end: self.position,
+            });
+        self.reset();
+        window
+    }
+
+    pub fn reset(&mut self) {
+        self.buffer.clear();
+        self.fresh = 0;
+        self.position = 0;
+    }
+}
+
+/// Drop the start of `next` that repeats the end of `previous` (the audio both
+/// windows share): the longest run of up to `MAX_OVERLAP_WORDS` words, compared
+/// ignoring case and punctuation.
+pub fn strip_overlap(previous: &str, next: &str) -> String {
+    let prev: Vec<String> = previous.split_whitespace().map(normalize).collect();
+    let words: Vec<&str> = next.split_whitespace().collect();
+    let max = MAX_OVERLAP_WORDS.min(prev.len()).min(words.len());
+
+    let repeated = (1..=max)
+        .rev()
+        .find(|&k| {
+            prev[prev.len() - k..]
+                .iter()
+                .zip(&words[..k])
+                .all(|(p, w)| *p == normalize(w))
+        })
+        .unwrap_or(0);
+    words[repeated..].join(" ")
+}
+
+fn normalize(word: &str) -> String {
+    word.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_overlap_by_one_second() {
+        let window = 3 * OVERLAP_SAMPLES;
+        let mut chunker = Chunker::new(window, 0.0);
+        let audio: Vec<i16> = (0..window + OVERLAP_SAMPLES)
+            .map(|i| (i / 1000) as i16)
+            .collect();
+
+        let windows = chunker.process_samples(&audio);
+        assert_eq!(windows.len(), 1);
+        assert_eq!(windows[0].audio.len(), window);
+        assert_eq!(windows[0].end, window as u64);
+
+        let windows = chunker.process_samples(&[0; 16000]);
+        assert_eq!(windows.len(), 1);
+        assert_eq!(
+            windows[0].audio[..OVERLAP_SAMPLES],
+            audio[window - OVERLAP_SAMPLES..window],
+            "Each window starts with the end of the previous one"
+        );
+        assert_eq!(windows[0].start(), (window - OVERLAP_SAMPLES) as u64);
+    }
+
+    #[test]
+    fn finish_returns_unsent_audio_only() {
+        let mut chunker = Chunker::new(3 * OVERLAP_SAMPLES, 0.0);
+        chunker.process_samples(&[1; 5000]);
+        assert_eq!(chunker.finish().map(|w| w.audio.len()), Some(5000));
+        assert_eq!(chunker.finish(), None);
+
+        chunker.process_samples(&[1; 3 * OVERLAP_SAMPLES]);
+        assert_eq!(chunker.finish(), None, "Only the overlap is left");
+    }
+
+    #[test]
+    fn silent_windows_are_skipped() {
+        let window = 3 * OVERLAP_SAMPLES;
+        let mut chunker = Chunker::new(window, 100.0);
+        assert!(chunker.process_samples(&vec![10; window]).is_empty());
+        let mut speech = vec![10; window - OVERLAP_SAMPLES];
+        speech[5000] = 20000;
+        let windows = chunker.process_samples(&speech);
+        assert_eq!(windows.len(), 1, "One loud frame is enough");
+        assert_eq!(windows[0].end, (2 * window - OVERLAP_SAMPLES) as u64);
+
+        chunker.process_samples(&[10; 5000]);
+        assert_eq!(chunker.finish(), None);
+    }
+
+    #[test]
+    fn strip_overlap_removes_repeated_words() {
+        assert_eq!(
+            strip_overlap("we went to the market", "The market, and then home"),
+            "and then home"
+        );
+        assert_eq!(
+            strip_overlap("hello there", "general kenobi"),
+            "general kenobi"
+        );
+        assert_eq!(strip_overlap("", "first words"), "first words");
+        assert_eq!(strip_overlap("say it again", "again"), "");
+    }
+}
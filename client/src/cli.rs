@@ -0,0 +1,522 @@
+use anyhow::{Context, Result, bail};
+use std::path::PathBuf;
+
+use space_tts_common::args::{
+    check_positive_secs, find_arg_value, find_optional_arg_value, has_flag, parse_arg_value,
+    parse_positive_secs,
+};
+use space_tts_common::languages;
+use space_tts_common::transcriber::FilterLevel;
+
+use crate::hotkey::{DeviceId, HotkeyDevice};
+use crate::keymap::KeyLanguages;
+use crate::queue::QueuePolicy;
+use crate::remote::Reconnect;
+use crate::triggers::LanguageTriggers;
+use crate::vad::{Aggressiveness, VadConfig};
+
+/// Command-line options for the client (everything not chosen in the TUI).
+pub struct Options {
+    pub debug: bool,
+    /// Skip the startup summary of the keyboards the hotkey is read from.
+    pub no_keyboard_summary: bool,
+    /// Check uinput, groups, dotool, audio, keyboards and models, then exit.
+    pub doctor: bool,
+    /// Print every input device's supported audio configs and exit.
+    pub list_audio_configs: bool,
+    /// Print the language codes whisper supports and exit.
+    pub list_languages: bool,
+    /// Type this text after a short countdown and exit (checks injection alone).
+    pub test_inject: Option<String>,
+    /// cpal host to capture from (e.g. "alsa", "jack"); asked in the TUI when unset.
+    pub audio_host: Option<String>,
+    /// Capture sample rate (Hz) to trust instead of the device's, for drivers that misreport it.
+    pub capture_rate: Option<u32>,
+    /// SSH targets to try in order (first reachable wins, failover on drops); skips the TUI SSH screen.
+    pub ssh_targets: Vec<String>,
+    /// Remote model to use ("small", "ggml-small.bin" or a path); skips the TUI model screen.
+    pub model: Option<String>,
+    /// Language code (any whisper supports, validated); skips the TUI language screen.
+    pub language: Option<String>,
+    /// Time each pipeline stage and print periodic summaries.
+    pub profile: bool,
+    /// With `--debug`, log each segment's duration, RMS, peak and clipping before sending it.
+    pub segment_stats: bool,
+    /// Ask the server to skip its warm-up transcription (faster startup).
+    pub no_warmup: bool,
+    /// Length of the server's warm-up transcription (ms, server default 1000).
+    pub warmup_ms: Option<u32>,
+    /// What the server warms up on: "silence" (default) or "speech".
+    pub warmup_audio: Option<String>,
+    /// Read keyboards and drive dotool through `sudo` instead of needing the 'input' group.
+    pub sudo_helper: bool,
+    /// Internal: run as the privileged hotkey reader for `--sudo-helper` (evdev key codes).
+    pub hotkey_helper: Option<Vec<u16>>,
+    /// Read push-to-talk from the device with this USB ID only (foot pedals),
+    /// with any of its keys toggling under `--hotkey-any-key`.
+    pub hotkey_device: Option<HotkeyDevice>,
+    /// Extra hotkeys that start listening in a given language (and model).
+    pub hotkey_languages: Option<KeyLanguages>,
+    /// Stop the capture stream while not listening (saves power, adds a little
+    /// latency to the first utterance after toggling on).
+    pub idle_pause: bool,
+    /// Have the server send its logs in-band (shown with a `[remote]` prefix).
+    pub remote_logs: bool,
+    /// External STT command the server runs instead of whisper (see the README).
+    pub backend_cmd: Option<String>,
+    /// Models directory on the server (and for `--doctor`'s local check).
+    pub models_dir: Option<String>,
+    /// Hallucination filter level passed to the server (off, conservative, aggressive).
+    pub filter_level: Option<String>,
+    /// XKB layout dotool types with ("us+altgr-intl"), instead of the detected one.
+    pub xkb_layout: Option<String>,
+    /// How text is typed: "dotool" (default, uinput), "tmux" or "tty".
+    pub inject_backend: Option<String>,
+    /// tmux pane `--inject-backend tmux` types into (current pane when unset).
+    pub tmux_target: Option<String>,
+    /// Console `--inject-backend tty` types into (`/dev/tty0`, the foreground one, when unset).
+    pub tty: Option<String>,
+    /// Window class/title substrings injection is restricted to (empty = always inject).
+    pub inject_allow: Vec<String>,
+    /// Extra window substrings never injected into (on top of the password-manager defaults).
+    pub inject_block: Vec<String>,
+    /// Drop the default password-manager classes from the block list.
+    pub no_default_block: bool,
+    /// Replace the VAD with fixed overlapping windows of this many seconds (`--chunk-mode`).
+    pub chunk_secs: Option<f64>,
+    /// Force-split segments longer than this (seconds).
+    pub max_segment_secs: Option<f64>,
+    /// Overlap carried into the next segment after a forced split (ms).
+    pub segment_overlap_ms: Option<u32>,
+    /// Transcribe and inject a single utterance, then exit (no hotkey).
+    pub once: bool,
+    /// Give up on `--once` if nothing was transcribed after this many seconds.
+    pub once_timeout_secs: f64,
+    /// Segments kept while the remote is unreachable, sent once it reconnects.
+    pub reconnect_buffer: usize,
+    /// Reconnect to a dropped remote on a timer, or only when the hotkey is pressed.
+    pub reconnect: Reconnect,
+    /// What to drop (or whether to wait) when the transcription queue is full.
+    pub queue_policy: QueuePolicy,
+    /// Read `l` (toggle), `m <model>` (switch model) and `q` (quit) commands from stdin.
+    pub stdin_control: bool,
+    /// Rebuild the pipeline after a crash (audio device lost, transcription thread dead).
+    pub auto_restart: bool,
+    /// Require a second Ctrl+C within a few seconds to quit (avoids stray presses).
+    pub confirm_quit: bool,
+    /// dotool types longer text in several `type` commands of at most this many characters.
+    pub max_type_chars: usize,
+    /// Type with a random delay around this many ms between keys, varying per word.
+    pub human_typing_ms: Option<u32>,
+    /// Type characters outside the keyboard layout with GTK/IBus Ctrl+Shift+U.
+    pub unicode_input: bool,
+    /// Skip a result identical to the previous one within this many ms (0 = off).
+    pub dedup_window_ms: u64,
+    /// Hold back results for this many ms after listening starts, so stray
+    /// hotkey events reach the focused app before any typed text (0 = off).
+    pub inject_delay_ms: u64,
+    /// Hold results and type them as one block when listening stops, or after
+    /// this many seconds without a new result.
+    pub commit_on_pause: Option<f64>,
+    /// Spoken phrases that switch the transcription language instead of being typed.
+    pub language_triggers: Option<LanguageTriggers>,
+    /// Audio kept from before listening starts, put ahead of the first chunk (0 = off, the default).
+    pub lookback_ms: u32,
+    /// Join results with spaces and capitalize sentence starts (language-aware).
+    pub auto_format: bool,
+    /// End each result with sentence punctuation if missing (implies `auto_format`).
+    pub auto_punctuate: bool,
+    /// Collapse whitespace runs in results to single spaces (line breaks kept).
+    pub normalize_spaces: bool,
+    /// Segments shorter than this (ms) are dropped instead of transcribed.
+    pub min_segment_ms: Option<u32>,
+    /// Have the server pad shorter segments with leading silence up to this (ms).
+    pub pad_to_ms: Option<u32>,
+    /// Send segments at the capture rate and let the server resample them to 16 kHz.
+    pub server_resample: bool,
+    /// Have the server abandon a segment whose transcription takes longer (seconds).
+    pub transcribe_timeout_secs: Option<f64>,
+    /// Have the server prompt whisper with the last results, reset when listening stops.
+    pub context_carryover: bool,
+    /// With `--language auto`, transcribe in this language when whisper isn't sure.
+    pub language_fallback: Option<String>,
+    /// How sure whisper must be of a detected language (0-1) to skip the fallback.
+    pub min_language_confidence: Option<f32>,
+    /// Cores to pin the server's transcription to, e.g. `2,3` or `0-3` (Linux servers).
+    pub cpu_affinity: Option<String>,
+    /// VAD aggressiveness (`None` = auto, from the noise floor); unset is auto.
+    pub vad_mode: Option<Option<Aggressiveness>>,
+    /// Minimum frame RMS (i16 units) before the VAD is consulted.
+    pub min_rms: Option<f64>,
+    /// Drop segments flatter than this spectral flatness (clicks, `--click-filter`).
+    pub click_filter: Option<f64>,
+    /// Light the Scroll Lock LED while listening (Scroll Lock hotkey only).
+    pub led_indicator: bool,
+    /// Write the listening state (`1`/`0`) to this file on every toggle.
+    pub state_file: Option<PathBuf>,
+}
+
+impl Options {
+    pub fn parse(args: &[String]) -> Result<Self> {
+        let chunk_secs = match find_optional_arg_value(args, "--chunk-mode") {
+            Some(Some(secs)) => Some(check_positive_secs(
+                "--chunk-mode",
+                secs.parse()
+                    .with_context(|| format!("Invalid value for --chunk-mode: '{secs}'"))?,
+            )?),
+            Some(None) => Some(10.0),
+            None => None,
+        };
+        let commit_on_pause = match find_optional_arg_value(args, "--commit-on-pause") {
+            Some(Some(secs)) => Some(check_positive_secs(
+                "--commit-on-pause",
+                secs.parse()
+                    .with_context(|| format!("Invalid value for --commit-on-pause: '{secs}'"))?,
+            )?),
+            Some(None) => Some(5.0),
+            None => None,
+        };
+        let human_typing_ms = match find_optional_arg_value(args, "--human-typing") {
+            Some(Some(ms)) => Some(
+                ms.parse()
+                    .with_context(|| format!("Invalid value for --human-typing: '{ms}'"))?,
+            ),
+            Some(None) => Some(80),
+            None => None,
+        };
+        let click_filter = match find_optional_arg_value(args, "--click-filter") {
+            Some(Some(flatness)) => Some(
+                flatness
+                    .parse()
+                    .with_context(|| format!("Invalid value for --click-filter: '{flatness}'"))?,
+            ),
+            Some(None) => Some(0.35),
+            None => None,
+        };
+        let any_key = has_flag(args, "--hotkey-any-key");
+        let hotkey_device = match find_arg_value(args, "--hotkey-device-id") {
+            Some(id) => Some(HotkeyDevice {
+                id: DeviceId::parse(&id)?,
+                any_key,
+            }),
+            None if any_key => bail!("--hotkey-any-key needs --hotkey-device-id"),
+            None => None,
+        };
+        let language_fallback = find_arg_value(args, "--language-fallback")
+            .map(|code| languages::validate_fallback(&code))
+            .transpose()?;
+        let min_language_confidence = parse_arg_value(args, "--min-language-confidence")?
+            .map(languages::validate_confidence)
+            .transpose()?;
+        if min_language_confidence.is_some() && language_fallback.is_none() {
+            bail!("--min-language-confidence only applies with --language-fallback");
+        }
+        let inject_backend = find_arg_value(args, "--inject-backend");
+        if human_typing_ms.is_some()
+            && let Some(backend) = inject_backend.as_deref().filter(|b| *b != "dotool")
+        {
+            bail!("--human-typing only works with the dotool inject backend, not {backend}");
+        }
+        let capture_rate: Option<u32> = parse_arg_value(args, "--capture-rate")?;
+        if capture_rate == Some(0) {
+            bail!("Invalid value for --capture-rate: '0' (expected a sample rate in Hz)");
+        }
+        let state_file = find_optional_arg_value(args, "--state-file").map(|path| {
+            path.map(PathBuf::from)
+                .unwrap_or_else(crate::indicator::default_path)
+        });
+
+        Ok(Self {
+            debug: has_flag(args, "--debug"),
+            no_keyboard_summary: has_flag(args, "--no-keyboard-summary"),
+            doctor: has_flag(args, "--doctor"),
+            list_audio_configs: has_flag(args, "--list-audio-configs"),
+            list_languages: has_flag(args, "--list-languages"),
+            audio_host: find_arg_value(args, "--audio-host"),
+            ssh_targets: find_arg_value(args, "--ssh-target")
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            model: find_arg_value(args, "--model"),
+            language: find_arg_value(args, "--language")
+                .map(|code| languages::validate(&code))
+                .transpose()?,
+            language_fallback,
+            min_language_confidence,
+            capture_rate,
+            test_inject: find_arg_value(args, "--test-inject"),
+            profile: has_flag(args, "--profile"),
+            segment_stats: has_flag(args, "--print-segment-audio-stats"),
+            no_warmup: has_flag(args, "--no-warmup"),
+            warmup_ms: parse_arg_value(args, "--warmup-ms")?,
+            warmup_audio: find_arg_value(args, "--warmup-audio"),
+            remote_logs: has_flag(args, "--remote-logs"),
+            idle_pause: has_flag(args, "--idle-pause"),
+            sudo_helper: has_flag(args, "--sudo-helper"),
+            hotkey_helper: find_arg_value(args, "--hotkey-helper")
+                .map(|codes| {
+                    split_list(&codes)
+                        .iter()
+                        .map(|code| {
+                            code.parse()
+                                .with_context(|| format!("Invalid key code '{code}'"))
+                        })
+                        .collect::<Result<Vec<u16>>>()
+                })
+                .transpose()?,
+            hotkey_device,
+            hotkey_languages: find_arg_value(args, "--hotkey-languages")
+                .map(|v| KeyLanguages::parse(&v))
+                .transpose()?,
+            filter_level: find_arg_value(args, "--filter-level")
+                .map(|level| FilterLevel::parse(&level).map(|_| level))
+                .transpose()?,
+            backend_cmd: find_arg_value(args, "--backend-cmd"),
+            models_dir: find_arg_value(args, "--models-dir"),
+            xkb_layout: find_arg_value(args, "--xkb-layout"),
+            inject_backend,
+            tmux_target: find_arg_value(args, "--tmux-target"),
+            tty: find_arg_value(args, "--tty"),
+            inject_allow: find_arg_value(args, "--inject-allow")
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            inject_block: find_arg_value(args, "--inject-block")
+                .map(|v| split_list(&v))
+                .unwrap_or_default(),
+            no_default_block: has_flag(args, "--no-default-block"),
+            chunk_secs,
+            max_segment_secs: parse_positive_secs(args, "--max-segment")?,
+            segment_overlap_ms: parse_arg_value(args, "--segment-overlap")?,
+            min_rms: parse_arg_value(args, "--min-rms")?,
+            click_filter,
+            vad_mode: find_arg_value(args, "--vad-mode")
+                .map(|v| Aggressiveness::parse(&v))
+                .transpose()?,
+            min_segment_ms: parse_arg_value(args, "--min-segment-ms")?,
+            pad_to_ms: parse_arg_value(args, "--pad-to-ms")?,
+            transcribe_timeout_secs: parse_positive_secs(args, "--transcribe-timeout")?,
+            context_carryover: has_flag(args, "--context-carryover"),
+            cpu_affinity: find_arg_value(args, "--cpu-affinity"),
+            server_resample: has_flag(args, "--server-resample"),
+            once: has_flag(args, "--once"),
+            once_timeout_secs: parse_positive_secs(args, "--once-timeout")?.unwrap_or(30.0),
+            reconnect_buffer: parse_arg_value(args, "--reconnect-buffer")?.unwrap_or(20),
+            reconnect: find_arg_value(args, "--reconnect")
+                .map(|v| Reconnect::parse(&v))
+                .transpose()?
+                .unwrap_or(Reconnect::Auto),
+            queue_policy: find_arg_value(args, "--queue-policy")
+                .map(|v| QueuePolicy::parse(&v))
+                .transpose()?
+                .unwrap_or_default(),
+            auto_restart: has_flag(args, "--auto-restart"),
+            confirm_quit: has_flag(args, "--confirm-quit"),
+            stdin_control: has_flag(args, "--stdin-control"),
+            max_type_chars: parse_arg_value(args, "--max-type-chars")?
+                .unwrap_or(crate::inject::DEFAULT_MAX_TYPE_CHARS),
+            human_typing_ms,
+            unicode_input: has_flag(args, "--unicode-input"),
+            dedup_window_ms: parse_arg_value(args, "--dedup-window")?.unwrap_or(0),
+            inject_delay_ms: parse_arg_value(args, "--inject-delay-ms")?.unwrap_or(0),
+            commit_on_pause,
+            language_triggers: find_arg_value(args, "--language-triggers")
+                .map(|v| LanguageTriggers::parse(&v))
+                .transpose()?,
+            lookback_ms: parse_arg_value(args, "--lookback-ms")?.unwrap_or(0),
+            auto_format: has_flag(args, "--auto-format"),
+            auto_punctuate: has_flag(args, "--auto-punctuate"),
+            normalize_spaces: has_flag(args, "--normalize-spaces"),
+            led_indicator: has_flag(args, "--led-indicator"),
+            state_file,
+        })
+    }
+
+    pub fn vad_config(&self) -> VadConfig {
+        let mut config = VadConfig::default();
+        if let Some(secs) = self.max_segment_secs {
+            config.max_segment_samples = (secs * 16000.0) as usize;
+            // The safety net stays well above a long --max-segment
+            config.max_buffer_samples = config
+                .max_buffer_samples
+                .max(config.max_segment_samples.saturating_mul(2));
+        }
+        if let Some(ms) = self.segment_overlap_ms {
+            config.overlap_samples = ms as usize * 16;
+        }
+        if let Some(rms) = self.min_rms {
+            config.min_rms = rms;
+        }
+        if let Some(ms) = self.min_segment_ms {
+            config.min_segment_samples = ms as usize * 16;
+        }
+        if let Some(mode) = self.vad_mode {
+            config.mode = mode;
+        }
+        config.max_flatness = self.click_filter;
+        config
+    }
+
+    /// Extra arguments forwarded to `space_tts_server` on the remote side.
+    pub fn server_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.no_warmup {
+            args.push("--no-warmup".to_string());
+        }
+        if let Some(ms) = self.warmup_ms {
+            args.extend(["--warmup-ms".to_string(), ms.to_string()]);
+        }
+        if let Some(audio) = &self.warmup_audio {
+            args.extend(["--warmup-audio".to_string(), audio.clone()]);
+        }
+        if self.remote_logs {
+            args.push("--forward-logs".to_string());
+        }
+        if let Some(level) = &self.filter_level {
+            args.extend(["--filter-level".to_string(), level.clone()]);
+        }
+        if let Some(ms) = self.min_segment_ms {
+            args.extend(["--min-segment-ms".to_string(), ms.to_string()]);
+        }
+        if let Some(ms) = self.pad_to_ms {
+            args.extend(["--pad-to-ms".to_string(), ms.to_string()]);
+        }
+        if let Some(secs) = self.transcribe_timeout_secs {
+            args.extend(["--transcribe-timeout".to_string(), secs.to_string()]);
+        }
+        if self.context_carryover {
+            args.push("--context-carryover".to_string());
+        }
+        if let Some(code) = &self.language_fallback {
+            args.extend(["--language-fallback".to_string(), code.clone()]);
+        }
+        if let Some(p) = self.min_language_confidence {
+            args.extend(["--min-language-confidence".to_string(), p.to_string()]);
+        }
+        if let Some(cores) = &self.cpu_affinity {
+            // Quoted too: "0, 2" would otherwise reach the server as two words
+            let quoted = space_tts_common::transcriber::shell_quote(cores);
+            args.extend(["--cpu-affinity".to_string(), quoted]);
+        }
+        if let Some(cmd) = &self.backend_cmd {
+            // ssh joins its arguments into one remote shell command line
+            let quoted = space_tts_common::transcriber::shell_quote(cmd);
+            args.extend(["--backend-cmd".to_string(), quoted]);
+        }
+        args.extend(self.models_dir_args());
+        args
+    }
+
+    /// `--models-dir` for the server, quoted for ssh (also used to list models).
+    pub fn models_dir_args(&self) -> Vec<String> {
+        self.models_dir
+            .iter()
+            .flat_map(|dir| {
+                [
+                    "--models-dir".to_string(),
+                    space_tts_common::transcriber::shell_quote(dir),
+                ]
+            })
+            .collect()
+    }
+}
+
+/// Split a comma-separated flag value, dropping empty entries.
+pub fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Options> {
+        let args: Vec<String> = std::iter::once("space_tts_client")
+            .chain(line.split_whitespace())
+            .map(String::from)
+            .collect();
+        Options::parse(&args)
+    }
+
+    /// The error `line` is rejected with.
+    fn error(line: &str) -> String {
+        match parse(line) {
+            Ok(_) => panic!("'{line}' should be rejected"),
+            Err(e) => format!("{e:#}"),
+        }
+    }
+
+    #[test]
+    fn defaults() {
+        let options = parse("").unwrap();
+        assert_eq!(options.lookback_ms, 0, "Lookback is opt-in");
+        assert_eq!(options.once_timeout_secs, 30.0);
+        assert_eq!(options.chunk_secs, None);
+        assert_eq!(parse("--chunk-mode").unwrap().chunk_secs, Some(10.0));
+        assert_eq!(
+            parse("--commit-on-pause").unwrap().commit_on_pause,
+            Some(5.0)
+        );
+    }
+
+    #[test]
+    fn durations_must_be_positive_and_finite() {
+        for line in [
+            "--max-segment 0",
+            "--transcribe-timeout nan",
+            "--transcribe-timeout inf",
+            "--once-timeout -5",
+            "--commit-on-pause -1",
+            "--chunk-mode 0",
+            "--chunk-mode 1e300",
+        ] {
+            let flag = line.split_whitespace().next().unwrap();
+            assert!(error(line).contains(flag), "{line}");
+        }
+        assert!(error("--once-timeout soon").contains("soon"));
+        assert_eq!(
+            parse("--max-segment 0.5").unwrap().max_segment_secs,
+            Some(0.5)
+        );
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        assert!(error("--capture-rate 0").contains("--capture-rate"));
+        for p in ["1.5", "-0.1", "nan"] {
+            let line = format!("--language-fallback en --min-language-confidence {p}");
+            assert!(error(&line).contains("between 0 and 1"), "{p}");
+        }
+        let options = parse("--language-fallback en --min-language-confidence 0.8").unwrap();
+        assert_eq!(options.min_language_confidence, Some(0.8));
+    }
+
+    #[test]
+    fn flags_needing_another_flag_are_rejected() {
+        assert!(error("--min-language-confidence 0.5").contains("--language-fallback"));
+        assert!(error("--hotkey-any-key").contains("--hotkey-device-id"));
+        assert!(error("--human-typing --inject-backend tmux").contains("tmux"));
+        assert!(parse("--human-typing 50 --inject-backend dotool").is_ok());
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(error("--filter-level max").contains("max"));
+        assert!(error("--vad-mode loud").contains("loud"));
+        assert!(error("--reconnect sometimes").contains("sometimes"));
+        assert!(error("--language xx").contains("xx"));
+        assert!(error("--human-typing fast").contains("fast"));
+    }
+
+    #[test]
+    fn malformed_lists_are_rejected() {
+        assert!(error("--hotkey-helper 58,abc").contains("abc"));
+        assert!(error("--hotkey-languages f9").contains("key=language"));
+        assert!(error("--hotkey-languages ,").contains("at least one key"));
+        assert!(error("--language-triggers français").contains("code=phrase"));
+        assert!(error("--language-triggers fr=|").contains("at least one phrase"));
+    }
+}
@@ -0,0 +1,60 @@
+use std::time::{Duration, Instant};
+
+/// `--commit-on-pause`: results held back and typed as one block when
+/// listening stops, or once no new result came for `idle`.
+pub struct CommitBuffer {
+    texts: Vec<String>,
+    last: Instant,
+    idle: Duration,
+}
+
+impl CommitBuffer {
+    pub fn new(idle: Duration) -> Self {
+        Self {
+            texts: Vec::new(),
+            last: Instant::now(),
+            idle,
+        }
+    }
+
+    /// Hold `text` (empty results are dropped).
+    pub fn push(&mut self, text: String, now: Instant) {
+        if text.is_empty() {
+            return;
+        }
+        self.texts.push(text);
+        self.last = now;
+    }
+
+    /// Whether held results have waited `idle` since the last one.
+    pub fn idle(&self, now: Instant) -> bool {
+        !self.texts.is_empty() && now.duration_since(self.last) >= self.idle
+    }
+
+    /// The held results, in order, leaving the buffer empty.
+    pub fn take(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.texts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn holds_results_until_idle() {
+        let start = Instant::now();
+        let mut buffer = CommitBuffer::new(Duration::from_secs(5));
+        buffer.push("Hello".to_string(), start);
+        buffer.push(String::new(), start);
+        buffer.push("world.".to_string(), start + Duration::from_secs(3));
+
+        assert!(!buffer.idle(start + Duration::from_secs(7)));
+        assert!(buffer.idle(start + Duration::from_secs(8)));
+        assert_eq!(buffer.take(), ["Hello", "world."]);
+        assert!(
+            !buffer.idle(start + Duration::from_secs(60)),
+            "Nothing held"
+        );
+    }
+}
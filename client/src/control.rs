@@ -0,0 +1,86 @@
+use anyhow::Result;
+use crossbeam_channel::Sender;
+use std::io::BufRead;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+use space_tts_common::{info, warn};
+
+/// A command typed in the terminal with `--stdin-control` (letter + Enter).
+#[derive(Debug, PartialEq)]
+enum Command {
+    /// `l`: toggle listening, like the hotkey.
+    Toggle,
+    /// `m <model>`: reconnect with another remote model ("small", a file name or a path).
+    Model(String),
+    /// `q`: quit, like Ctrl+C (without --confirm-quit's second press).
+    Quit,
+}
+
+fn parse(line: &str) -> Option<Command> {
+    let line = line.trim();
+    let (cmd, arg) = line.split_once(' ').unwrap_or((line, ""));
+    match (cmd, arg.trim()) {
+        ("l", "") => Some(Command::Toggle),
+        ("q", "") => Some(Command::Quit),
+        ("m", model) if !model.is_empty() => Some(Command::Model(model.to_string())),
+        _ => None,
+    }
+}
+
+/// Read commands from stdin and drive the same state the hotkey and Ctrl+C do.
+pub fn spawn(
+    is_listening: Arc<AtomicBool>,
+    quit_presses: Arc<AtomicU32>,
+    model_tx: Sender<String>,
+) -> Result<()> {
+    info!("Terminal commands: l = toggle listening, m <model> = switch model, q = quit");
+    std::thread::Builder::new()
+        .name("stdin-control".into())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                let Ok(line) = line else { break };
+                match parse(&line) {
+                    Some(Command::Toggle) => {
+                        is_listening.fetch_xor(true, Ordering::SeqCst);
+                    }
+                    Some(Command::Model(model)) => {
+                        if model_tx.send(model).is_err() {
+                            break;
+                        }
+                    }
+                    Some(Command::Quit) => {
+                        // Two presses at once: also confirms under --confirm-quit
+                        quit_presses.fetch_add(2, Ordering::SeqCst);
+                        break;
+                    }
+                    None if line.trim().is_empty() => {}
+                    None => warn!("Unknown command '{}' (l, m <model>, q)", line.trim()),
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("l"), Some(Command::Toggle));
+        assert_eq!(parse(" q \n"), Some(Command::Quit));
+        assert_eq!(
+            parse("m  large-v3-turbo"),
+            Some(Command::Model("large-v3-turbo".to_string()))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_commands() {
+        assert_eq!(parse("m"), None);
+        assert_eq!(parse("l now"), None);
+        assert_eq!(parse("listen"), None);
+        assert_eq!(parse(""), None);
+    }
+}
@@ -0,0 +1,173 @@
+use anyhow::{Result, bail};
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use space_tts_common::models;
+
+use crate::{audio, hotkey, inject};
+
+#[derive(Clone, Copy)]
+enum Status {
+    Pass,
+    /// Worth knowing, but the client can still work.
+    Warn,
+    /// The client cannot work until this is fixed.
+    Fail,
+}
+
+struct Report {
+    failures: usize,
+}
+
+impl Report {
+    fn check(&mut self, status: Status, what: &str, fix: &str) {
+        let tag = match status {
+            Status::Pass => "PASS",
+            Status::Warn => "WARN",
+            Status::Fail => {
+                self.failures += 1;
+                "FAIL"
+            }
+        };
+        println!("[{tag}] {what}");
+        if !matches!(status, Status::Pass) && !fix.is_empty() {
+            println!("       fix: {fix}");
+        }
+    }
+}
+
+/// Whether the current user is in the 'input' group (`None` if `id` failed).
+pub fn in_input_group() -> Option<bool> {
+    let output = std::process::Command::new("id").arg("-Gn").output().ok()?;
+    let groups = String::from_utf8_lossy(&output.stdout);
+    Some(groups.split_whitespace().any(|g| g == "input"))
+}
+
+/// `--doctor`: run every setup check, print a pass/fail report (meant to be
+/// pasted into issues) and fail if anything critical is broken. With `sudo`,
+/// group and uinput problems are only warnings since `--sudo-helper` bypasses them.
+/// `xkb_layout` and `models_dir` are the `--xkb-layout` and `--models-dir` overrides, if any.
+pub fn run(
+    audio_host: Option<&str>,
+    sudo: bool,
+    xkb_layout: Option<&str>,
+    models_dir: Option<&str>,
+) -> Result<()> {
+    let mut report = Report { failures: 0 };
+    let access = if sudo { Status::Warn } else { Status::Fail };
+    println!("space_tts doctor (client {})\n", env!("CARGO_PKG_VERSION"));
+
+    match in_input_group() {
+        Some(true) => report.check(Status::Pass, "user is in the 'input' group", ""),
+        Some(false) => report.check(
+            access,
+            "user is not in the 'input' group",
+            "sudo usermod -aG input $USER && log out/in (or use --sudo-helper)",
+        ),
+        None => report.check(Status::Warn, "could not check groups (id failed)", ""),
+    }
+
+    match inject::open_uinput() {
+        Ok(()) => report.check(Status::Pass, "/dev/uinput is writable", ""),
+        Err(e) => report.check(
+            access,
+            &format!("cannot open /dev/uinput: {e}"),
+            "join the 'input' group (see above), or `sudo modprobe uinput` if it is missing",
+        ),
+    }
+
+    if inject::dotool_in_path() {
+        report.check(Status::Pass, "dotool found in PATH", "");
+    } else {
+        report.check(
+            Status::Fail,
+            "dotool not found in PATH",
+            "install it: https://git.sr.ht/~geb/dotool",
+        );
+    }
+
+    report.check(
+        Status::Pass,
+        &format!("XKB layout: {}", inject::xkb_layout(xkb_layout)),
+        "",
+    );
+
+    let host = audio::host(audio_host);
+    let inputs = host.input_devices().map(|d| d.count()).unwrap_or(0);
+    match host.default_input_device() {
+        Some(device) => {
+            let name = device
+                .description()
+                .map(|d| d.name().to_string())
+                .unwrap_or_else(|_| "Unknown".to_string());
+            report.check(
+                Status::Pass,
+                &format!(
+                    "audio host {:?}: default input {name} ({inputs} inputs)",
+                    host.id()
+                ),
+                "",
+            );
+        }
+        None => report.check(
+            Status::Fail,
+            &format!("audio host {:?}: no default input device", host.id()),
+            "plug in a microphone, check PipeWire/PulseAudio, or try --audio-host",
+        ),
+    }
+
+    let keyboards = hotkey::find_keyboards();
+    if keyboards.is_empty() {
+        report.check(
+            access,
+            "no readable keyboard for the push-to-talk hotkey",
+            "join the 'input' group, or use --sudo-helper",
+        );
+    } else {
+        let names: Vec<&str> = keyboards.iter().map(|(_, name)| name.as_str()).collect();
+        report.check(
+            Status::Pass,
+            &format!("keyboards: {}", names.join(", ")),
+            "",
+        );
+    }
+
+    // Models live on the server: a local check only matters when it runs here
+    let dir = models::models_dir(models_dir.map(std::path::Path::new));
+    let found = if dir.exists() {
+        models::scan_models(&dir)
+    } else {
+        Ok(Vec::new())
+    };
+    match found {
+        Ok(found) if found.is_empty() => report.check(
+            Status::Warn,
+            &format!("no local models in {}", dir.display()),
+            "fine if the server is another machine; otherwise ./setup.sh model",
+        ),
+        Ok(found) => {
+            for (name, path) in found {
+                if models::is_incomplete_file(&name, &path) {
+                    report.check(
+                        Status::Warn,
+                        &format!("local model {name} looks incomplete"),
+                        "download it again (./setup.sh model)",
+                    );
+                } else {
+                    report.check(Status::Pass, &format!("local model {name}"), "");
+                }
+            }
+        }
+        Err(e) => report.check(
+            Status::Warn,
+            &format!("cannot scan {}: {e}", dir.display()),
+            "",
+        ),
+    }
+
+    println!();
+    if report.failures > 0 {
+        bail!("{} critical check(s) failed", report.failures);
+    }
+    println!("All critical checks passed.");
+    Ok(())
+}
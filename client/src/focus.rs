@@ -0,0 +1,254 @@
+use std::process::{Command, Stdio};
+
+/// The currently focused window, as far as we can tell.
+#[derive(Debug, Default, PartialEq)]
+pub struct FocusedWindow {
+    pub class: String,
+    pub title: String,
+}
+
+/// Query the focused window: Hyprland via `hyprctl`, then X11 (and XWayland) via `xprop`.
+/// Other Wayland compositors don't expose this, so `None` is common there.
+pub fn focused_window() -> Option<FocusedWindow> {
+    if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some()
+        && let Some(out) = run("hyprctl", &["activewindow"])
+        && let Some(window) = parse_hyprctl_active_window(&out)
+    {
+        return Some(window);
+    }
+
+    let root = run("xprop", &["-root", "_NET_ACTIVE_WINDOW"])?;
+    let id = parse_xprop_active_window(&root)?;
+    let props = run("xprop", &["-id", &id, "WM_CLASS", "_NET_WM_NAME"])?;
+    Some(parse_xprop_window(&props))
+}
+
+fn run(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program)
+        .args(args)
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn parse_xprop_active_window(output: &str) -> Option<String> {
+    // _NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007
+    let id = output.split('#').nth(1)?.trim();
+    let id = id.split([',', ' ']).next()?;
+    if id.is_empty() || id == "0x0" {
+        None
+    } else {
+        Some(id.to_string())
+    }
+}
+
+fn parse_xprop_window(output: &str) -> FocusedWindow {
+    // WM_CLASS(STRING) = "code", "Code"
+    // _NET_WM_NAME(UTF8_STRING) = "main.rs - Visual Studio Code"
+    let mut window = FocusedWindow::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(" = ") else {
+            continue;
+        };
+        if key.starts_with("WM_CLASS") {
+            window.class = value
+                .split(", ")
+                .map(|v| v.trim().trim_matches('"'))
+                .collect::<Vec<_>>()
+                .join(" ");
+        } else if key.starts_with("_NET_WM_NAME") {
+            window.title = value.trim().trim_matches('"').to_string();
+        }
+    }
+    window
+}
+
+fn parse_hyprctl_active_window(output: &str) -> Option<FocusedWindow> {
+    let mut window = FocusedWindow::default();
+    let mut found = false;
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(class) = trimmed.strip_prefix("class: ") {
+            window.class = class.to_string();
+            found = true;
+        } else if let Some(title) = trimmed.strip_prefix("title: ") {
+            window.title = title.to_string();
+            found = true;
+        }
+    }
+    found.then_some(window)
+}
+
+/// Window classes that never receive dictation: password managers, credential
+/// prompts and lock screens. Only the class is checked: a title mentioning
+/// "password" is as likely an article or a reset email as a prompt.
+/// Users can extend this with `--inject-block`, or drop it with `--no-default-block`.
+pub const DEFAULT_BLOCK_CLASSES: &[&str] = &[
+    "keepass",
+    "bitwarden",
+    "1password",
+    "enpass",
+    "pinentry",
+    "gcr-prompter",
+    "polkit",
+    "ssh-askpass",
+    "ksshaskpass",
+    "swaylock",
+    "i3lock",
+    "hyprlock",
+    "xscreensaver",
+    "kscreenlocker",
+];
+
+/// Decides whether text may be injected into the focused window.
+/// Matching is a case-insensitive substring test against class and title
+/// (class only for `DEFAULT_BLOCK_CLASSES`).
+#[derive(Default)]
+pub struct WindowFilter {
+    /// When non-empty, only inject into windows matching one of these.
+    allow: Vec<String>,
+    /// Never inject into windows matching one of these (`--inject-block`).
+    block: Vec<String>,
+    /// Never inject into windows whose class matches one of these.
+    block_classes: Vec<String>,
+}
+
+impl WindowFilter {
+    /// Allow-list as given; block-list is `extra_block`, plus the default
+    /// classes when `default_block` is set.
+    pub fn new(allow: &[String], extra_block: &[String], default_block: bool) -> Self {
+        let defaults: &[&str] = if default_block {
+            DEFAULT_BLOCK_CLASSES
+        } else {
+            &[]
+        };
+        Self {
+            allow: allow.iter().map(|p| p.to_lowercase()).collect(),
+            block: extra_block.iter().map(|p| p.to_lowercase()).collect(),
+            block_classes: defaults.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    /// True when the filter needs to know the focused window at all.
+    pub fn is_active(&self) -> bool {
+        !self.allow.is_empty() || !self.block.is_empty() || !self.block_classes.is_empty()
+    }
+
+    /// Unknown focus can't be blocked (the allow-list still applies).
+    pub fn blocks(&self, window: Option<&FocusedWindow>) -> bool {
+        window.is_some_and(|w| {
+            let class = w.class.to_lowercase();
+            self.block_classes
+                .iter()
+                .any(|p| class.contains(p.as_str()))
+                || matches_any(w, &self.block)
+        })
+    }
+
+    /// Unknown focus never satisfies an allow-list.
+    pub fn permits(&self, window: Option<&FocusedWindow>) -> bool {
+        if self.allow.is_empty() {
+            return true;
+        }
+        window.is_some_and(|w| matches_any(w, &self.allow))
+    }
+}
+
+fn matches_any(window: &FocusedWindow, patterns: &[String]) -> bool {
+    let class = window.class.to_lowercase();
+    let title = window.title.to_lowercase();
+    patterns
+        .iter()
+        .any(|p| class.contains(p.as_str()) || title.contains(p.as_str()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(class: &str, title: &str) -> FocusedWindow {
+        FocusedWindow {
+            class: class.to_string(),
+            title: title.to_string(),
+        }
+    }
+
+    #[test]
+    fn parse_xprop_root() {
+        let output = "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007\n";
+        assert_eq!(
+            parse_xprop_active_window(output),
+            Some("0x3a00007".to_string())
+        );
+        assert_eq!(
+            parse_xprop_active_window("_NET_ACTIVE_WINDOW(WINDOW): window id # 0x0\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn parse_xprop_class_and_title() {
+        let output = "WM_CLASS(STRING) = \"code\", \"Code\"\n_NET_WM_NAME(UTF8_STRING) = \"main.rs - Visual Studio Code\"\n";
+        assert_eq!(
+            parse_xprop_window(output),
+            window("code Code", "main.rs - Visual Studio Code")
+        );
+    }
+
+    #[test]
+    fn parse_hyprctl_output() {
+        let output = "Window 55d0f8a0 -> ~:\n\tmapped: 1\n\tclass: kitty\n\ttitle: ~\n";
+        assert_eq!(
+            parse_hyprctl_active_window(output),
+            Some(window("kitty", "~"))
+        );
+        assert_eq!(parse_hyprctl_active_window("Invalid\n"), None);
+    }
+
+    #[test]
+    fn empty_allow_list_always_injects() {
+        let filter = WindowFilter::default();
+        assert!(filter.permits(None));
+        assert!(filter.permits(Some(&window("firefox", "Inbox"))));
+    }
+
+    #[test]
+    fn allow_list_matches_class_or_title() {
+        let filter = WindowFilter::new(&["Code".to_string(), "element".to_string()], &[], true);
+        assert!(filter.permits(Some(&window("code", "main.rs"))));
+        assert!(filter.permits(Some(&window("firefox", "Element | Room"))));
+        assert!(!filter.permits(Some(&window("firefox", "Inbox"))));
+        assert!(!filter.permits(None));
+    }
+
+    #[test]
+    fn default_block_list_catches_password_prompts() {
+        let filter = WindowFilter::new(&[], &[], true);
+        assert!(filter.blocks(Some(&window("KeePassXC", "Passwords.kdbx"))));
+        assert!(filter.blocks(Some(&window("pinentry-gtk-2", ""))));
+        assert!(
+            !filter.blocks(Some(&window("firefox", "Password reset - Inbox"))),
+            "Titles alone don't block"
+        );
+        assert!(!filter.blocks(Some(&window("kitty", "vim notes.md"))));
+        assert!(!filter.blocks(None));
+    }
+
+    #[test]
+    fn block_list_is_extendable() {
+        let filter = WindowFilter::new(&[], &["Banking".to_string()], true);
+        assert!(filter.blocks(Some(&window("firefox", "My banking portal"))));
+        assert!(filter.blocks(Some(&window("keepassxc", ""))));
+    }
+
+    #[test]
+    fn inactive_without_any_list() {
+        assert!(!WindowFilter::new(&[], &[], false).is_active());
+        assert!(WindowFilter::new(&[], &[], true).is_active());
+        assert!(WindowFilter::new(&[], &["vault".to_string()], false).is_active());
+    }
+}
@@ -0,0 +1,194 @@
+use space_tts_common::languages;
+
+/// Output formatting applied to results before injection (`--auto-format`,
+/// `--auto-punctuate`).
+///
+/// Consecutive results are joined with a space and the first word of each
+/// sentence is capitalized. Languages written without spaces or letter case
+/// (Japanese, Chinese) get neither, and auto-punctuation ends them with a
+/// full-width `。` instead of `.`.
+pub struct Formatter {
+    language: String,
+    punctuate: bool,
+    /// Whether something was already injected this session.
+    started: bool,
+    /// Whether the last injected text ended a sentence.
+    sentence_ended: bool,
+}
+
+impl Formatter {
+    pub fn new(language: &str, punctuate: bool) -> Self {
+        Self {
+            language: language.to_string(),
+            punctuate,
+            started: false,
+            sentence_ended: true,
+        }
+    }
+
+    /// Whether `text` is in a language written without inter-word spaces or
+    /// letter case. With `--language auto`, judged from its script.
+    fn is_cjk(&self, text: &str) -> bool {
+        match self.language.as_str() {
+            languages::AUTO => text.chars().any(is_cjk_char),
+            code => languages::is_unspaced(code),
+        }
+    }
+
+    /// Format `text` (already sanitized) and return it with the separator to
+    /// type before it. Records the text as injected.
+    pub fn format(&mut self, text: &str) -> (&'static str, String) {
+        let cjk = self.is_cjk(text);
+        let mut out = if !cjk && self.sentence_ended {
+            capitalize(text)
+        } else {
+            text.to_string()
+        };
+        if self.punctuate && !ends_sentence(&out) {
+            out.push(if cjk { '。' } else { '.' });
+        }
+
+        let separator = if self.started && !cjk { " " } else { "" };
+        self.started = true;
+        self.sentence_ended = ends_sentence(&out);
+        (separator, out)
+    }
+
+    /// Format results typed as one block (`--commit-on-pause`), joined as if
+    /// injected one by one; returns the separator to type before the block.
+    pub fn format_all(&mut self, texts: &[String]) -> (&'static str, String) {
+        let mut block = String::new();
+        let mut first_separator = "";
+        for (i, text) in texts.iter().enumerate() {
+            let (separator, text) = self.format(text);
+            if i == 0 {
+                first_separator = separator;
+            } else {
+                block.push_str(separator);
+            }
+            block.push_str(&text);
+        }
+        (first_separator, block)
+    }
+}
+
+/// `--normalize-spaces`: collapse each run of whitespace to a single space and
+/// trim the ends, so whisper's stray spaces can't double up with the separator.
+/// Runs containing line breaks keep just the line breaks.
+pub fn collapse_spaces(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut run = String::new();
+    for c in text.chars() {
+        if c.is_whitespace() {
+            run.push(c);
+            continue;
+        }
+        if !run.is_empty() && !out.is_empty() {
+            let newlines: String = run.chars().filter(|&c| c == '\n').collect();
+            out.push_str(if newlines.is_empty() { " " } else { &newlines });
+        }
+        run.clear();
+        out.push(c);
+    }
+    out
+}
+
+/// Kana or a CJK ideograph.
+fn is_cjk_char(c: char) -> bool {
+    matches!(c, '\u{3040}'..='\u{30ff}' | '\u{3400}'..='\u{4dbf}' | '\u{4e00}'..='\u{9fff}')
+}
+
+/// Uppercase the first character.
+fn capitalize(text: &str) -> String {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Whether `text` ends with sentence-final punctuation (Latin or full-width).
+fn ends_sentence(text: &str) -> bool {
+    text.trim_end_matches(['"', '\'', ')', '」', '』', '）'])
+        .ends_with(['.', '!', '?', '…', '。', '！', '？'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_and_capitalizes_latin() {
+        let mut f = Formatter::new("en", false);
+        assert_eq!(f.format("hello there."), ("", "Hello there.".to_string()));
+        assert_eq!(f.format("how are you"), (" ", "How are you".to_string()));
+        assert_eq!(f.format("doing"), (" ", "doing".to_string()));
+    }
+
+    #[test]
+    fn formats_a_block_like_separate_results() {
+        let mut f = Formatter::new("en", true);
+        let block = ["so".to_string(), "this is it".to_string()];
+        assert_eq!(f.format_all(&block), ("", "So. This is it.".to_string()));
+        assert_eq!(
+            f.format_all(&["next".to_string()]),
+            (" ", "Next.".to_string())
+        );
+    }
+
+    #[test]
+    fn auto_judges_each_result_by_its_script() {
+        let mut f = Formatter::new("auto", true);
+        assert_eq!(f.format("hello"), ("", "Hello.".to_string()));
+        assert_eq!(f.format("こんにちは"), ("", "こんにちは。".to_string()));
+        assert_eq!(f.format("ok"), (" ", "Ok.".to_string()));
+    }
+
+    #[test]
+    fn punctuates_latin() {
+        let mut f = Formatter::new("fr", true);
+        assert_eq!(f.format("bonjour"), ("", "Bonjour.".to_string()));
+        assert_eq!(f.format("ça va ?"), (" ", "Ça va ?".to_string()));
+    }
+
+    #[test]
+    fn cjk_gets_no_spaces_or_latin_periods() {
+        for language in ["ja", "zh"] {
+            let mut f = Formatter::new(language, true);
+            let (sep, first) = f.format("こんにちは");
+            assert_eq!((sep, first.as_str()), ("", "こんにちは。"));
+            let (sep, second) = f.format("元気ですか？");
+            assert_eq!((sep, second.as_str()), ("", "元気ですか？"));
+            assert!(!first.contains(' ') && !first.contains('.'));
+        }
+    }
+
+    #[test]
+    fn cjk_leaves_embedded_latin_case_alone() {
+        let mut f = Formatter::new("zh", false);
+        assert_eq!(f.format("iphone很好"), ("", "iphone很好".to_string()));
+        assert_eq!(f.format("你好"), ("", "你好".to_string()));
+    }
+
+    #[test]
+    fn collapse_spaces_normalizes_runs() {
+        assert_eq!(collapse_spaces("  hello   world  "), "hello world");
+        assert_eq!(collapse_spaces("a\t b"), "a b");
+        assert_eq!(
+            collapse_spaces("line one  \n\n  line two"),
+            "line one\n\nline two"
+        );
+        assert_eq!(collapse_spaces("   "), "");
+        // Off, only the ends are trimmed (by sanitize)
+        assert_eq!(
+            crate::inject::sanitize("  hello   world  "),
+            "hello   world"
+        );
+    }
+
+    #[test]
+    fn closing_quote_still_ends_sentence() {
+        let mut f = Formatter::new("ja", true);
+        assert_eq!(f.format("「はい。」"), ("", "「はい。」".to_string()));
+    }
+}
@@ -1,12 +1,16 @@
-use anyhow::Result;
-use evdev::{Device, EventType, KeyCode};
+use anyhow::{Context, Result, bail};
+use crossbeam_channel::Sender;
+use evdev::{Device, EventType, InputEvent, KeyCode, LedCode};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-use space_tts_common::{debug, warn};
+use space_tts_common::{debug, info, warn};
 
 /// List all keyboard-like evdev devices (filtering out non-keyboards).
-fn find_keyboards() -> Vec<(std::path::PathBuf, String)> {
+pub fn find_keyboards() -> Vec<(std::path::PathBuf, String)> {
     evdev::enumerate()
         .filter(|(_, dev)| {
             if !dev.supported_events().contains(EventType::KEY) {
@@ -33,18 +37,199 @@ fn find_keyboards() -> Vec<(std::path::PathBuf, String)> {
         .collect()
 }
 
-/// Listen for the hotkey on ALL detected keyboards simultaneously.
-/// Spawns one thread per keyboard device. Any of them pressing the key triggers PTT.
-pub fn listen_all_keyboards(key: KeyCode, is_listening: Arc<AtomicBool>) -> Result<()> {
-    let keyboards = find_keyboards();
+/// USB vendor:product ID of a dedicated push-to-talk device (`--hotkey-device-id`),
+/// stable across reboots and renames unlike its name or event node.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DeviceId {
+    pub vendor: u16,
+    pub product: u16,
+}
+
+impl DeviceId {
+    /// Parse `vid:pid` in hex, as printed by `lsusb` (e.g. "05f3:00ff").
+    pub fn parse(s: &str) -> Result<Self> {
+        let hex = |part: &str| u16::from_str_radix(part.trim(), 16).ok();
+        match s.split_once(':').map(|(v, p)| (hex(v), hex(p))) {
+            Some((Some(vendor), Some(product))) => Ok(Self { vendor, product }),
+            _ => bail!("Invalid device ID '{s}' (expected vid:pid in hex, e.g. 05f3:00ff)"),
+        }
+    }
+}
+
+impl std::fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:04x}:{:04x}", self.vendor, self.product)
+    }
+}
+
+/// `--hotkey-device-id`: the device push-to-talk is read from. Many keyboards
+/// and receivers share their ID with other devices, so only the hotkeys count
+/// unless `any_key` (`--hotkey-any-key`, for pedals sending arbitrary keys).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HotkeyDevice {
+    pub id: DeviceId,
+    pub any_key: bool,
+}
+
+/// List the key-emitting evdev devices with this vendor:product ID, whatever
+/// keys they have (foot pedals rarely look like keyboards).
+fn find_devices_by_id(id: DeviceId) -> Vec<(std::path::PathBuf, String)> {
+    evdev::enumerate()
+        .filter(|(_, dev)| {
+            let input_id = dev.input_id();
+            input_id.vendor() == id.vendor
+                && input_id.product() == id.product
+                && dev.supported_events().contains(EventType::KEY)
+        })
+        .map(|(path, dev)| {
+            let name = dev.name().unwrap_or("Unknown").to_string();
+            (path, name)
+        })
+        .collect()
+}
+
+/// Attempts at opening a keyboard before giving up on it.
+const OPEN_ATTEMPTS: u32 = 4;
+
+/// Open an evdev device, retrying with a doubling delay (100ms, 200ms, 400ms):
+/// right after login, udev may not have settled permissions on the node yet.
+fn open_with_retry(path: &std::path::Path) -> std::io::Result<Device> {
+    let mut delay = Duration::from_millis(100);
+    let mut attempt = 1;
+    loop {
+        match Device::open(path) {
+            Err(e) if attempt < OPEN_ATTEMPTS && e.kind() != std::io::ErrorKind::NotFound => {
+                debug!(
+                    "Cannot open {} yet ({e}), retry {attempt}/{} in {delay:?}",
+                    path.display(),
+                    OPEN_ATTEMPTS - 1
+                );
+                std::thread::sleep(delay);
+                delay *= 2;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Listen for the hotkeys on ALL detected keyboards simultaneously.
+/// Spawns one thread per keyboard device. Any of them pressing one of `keys` triggers PTT,
+/// and the key is sent on `pressed` first (`--hotkey-languages` tells them apart).
+/// When none can be read, falls back to toggling on Enter in the terminal
+/// (`stdin_fallback`, off when stdin is already taken by `--stdin-control`).
+/// With a `device`, only that device is read.
+/// Returns the names of the devices listened to.
+pub fn listen_all_keyboards(
+    keys: &[KeyCode],
+    device: Option<HotkeyDevice>,
+    is_listening: Arc<AtomicBool>,
+    pressed: Sender<KeyCode>,
+    stdin_fallback: bool,
+) -> Result<Vec<String>> {
+    let listening = is_listening.clone();
+    let on_press = move |key| {
+        let _ = pressed.send(key);
+        toggle(&listening);
+    };
+    let opened = on_key_press(keys, device, on_press)?;
+    if opened.is_empty() && stdin_fallback {
+        warn!("  Falling back to the terminal: press Enter here to toggle listening.");
+        listen_stdin(is_listening)?;
+    }
+    Ok(opened)
+}
+
+/// The startup summary of what the hotkey listens to, e.g.
+/// "Listening for F9 on 2 keyboards: AT Translated Set 2 keyboard, USB Keyboard".
+pub fn report_listening(keys: &[KeyCode], device: Option<HotkeyDevice>, opened: &[String]) {
+    if opened.is_empty() {
+        return; // the failure was already explained
+    }
+    let plural = if opened.len() == 1 { "" } else { "s" };
+    let names: Vec<String> = keys
+        .iter()
+        .map(|key| format!("{key:?}").trim_start_matches("KEY_").to_string())
+        .collect();
+    let what = match device {
+        Some(HotkeyDevice { id, any_key: true }) => {
+            format!("any key on {} device{plural} {id}", opened.len())
+        }
+        Some(HotkeyDevice { id, any_key: false }) => {
+            format!(
+                "{} on {} device{plural} {id}",
+                names.join(", "),
+                opened.len()
+            )
+        }
+        None => format!("{} on {} keyboard{plural}", names.join(", "), opened.len()),
+    };
+    info!("Listening for {what}: {}", opened.join(", "));
+}
+
+fn toggle(is_listening: &AtomicBool) {
+    let prev = is_listening.load(Ordering::SeqCst);
+    is_listening.store(!prev, Ordering::SeqCst);
+}
+
+/// Toggle listening on every line read from stdin (i.e. each Enter press).
+fn listen_stdin(is_listening: Arc<AtomicBool>) -> Result<()> {
+    std::thread::Builder::new()
+        .name("stdin-toggle".into())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                if line.is_err() {
+                    break;
+                }
+                toggle(&is_listening);
+            }
+        })?;
+    Ok(())
+}
+
+/// Call `on_press` with the key whenever one of `keys` is pressed (not released, not
+/// repeated) on any keyboard, or on the devices matching `device` (any of
+/// their keys with `any_key`). Returns the names of the devices that could be opened.
+fn on_key_press(
+    keys: &[KeyCode],
+    device: Option<HotkeyDevice>,
+    on_press: impl Fn(KeyCode) + Send + Clone + 'static,
+) -> Result<Vec<String>> {
+    let any_key = device.is_some_and(|device| device.any_key);
+    let keyboards = match device {
+        Some(HotkeyDevice { id, .. }) => {
+            let devices = find_devices_by_id(id);
+            if devices.is_empty() {
+                warn!("No input device with ID {id} found for the hotkey (is it plugged in?).");
+                return Ok(Vec::new());
+            }
+            if devices.len() > 1 {
+                // Pedals often expose several nodes (or two identical units): read them all
+                let names: Vec<&str> = devices.iter().map(|(_, name)| name.as_str()).collect();
+                info!(
+                    "{} devices share ID {id}, listening on all: {}",
+                    devices.len(),
+                    names.join(", ")
+                );
+            }
+            devices
+        }
+        None => find_keyboards(),
+    };
 
     if keyboards.is_empty() {
         warn!("No keyboard devices found for hotkey. Is the user in the 'input' group?");
-        return Ok(());
+        warn!("  Without it, run with --sudo-helper to read keyboards through sudo.");
+        return Ok(Vec::new());
     }
 
+    // Each listener reports its device's name if it opened
+    let (opened_tx, opened_rx) = std::sync::mpsc::channel();
+    let count = keyboards.len();
     for (path, name) in keyboards {
-        let is_listening = is_listening.clone();
+        let on_press = on_press.clone();
+        let keys = keys.to_vec();
+        let opened_tx = opened_tx.clone();
         let path_display = path.display().to_string();
 
         std::thread::Builder::new()
@@ -53,27 +238,37 @@ pub fn listen_all_keyboards(key: KeyCode, is_listening: Arc<AtomicBool>) -> Resu
                 path.file_name().unwrap_or_default().to_string_lossy()
             ))
             .spawn(move || {
-                let mut device = match Device::open(&path) {
+                let mut device = match open_with_retry(&path) {
                     Ok(d) => d,
                     Err(e) => {
                         warn!("Cannot open {path_display} ({name}): {e}");
+                        if e.kind() == std::io::ErrorKind::PermissionDenied {
+                            warn!(
+                                "  Add yourself to the 'input' group, or run with --sudo-helper."
+                            );
+                        }
+                        let _ = opened_tx.send(None);
                         return;
                     }
                 };
+                let _ = opened_tx.send(Some(name.clone()));
+                // Reported: a listener that dies before then ends the wait below
+                drop(opened_tx);
 
                 debug!("Hotkey listener on: {name} ({path_display})");
 
                 loop {
+                    // The synced stream handles SYN_DROPPED itself: after an overrun it
+                    // re-reads the key states and emits the presses/releases we missed
                     match device.fetch_events() {
                         Ok(events) => {
                             for event in events {
+                                let key = KeyCode::new(event.code());
                                 if event.event_type() == EventType::KEY
-                                    && event.code() == key.code()
+                                    && (any_key || keys.contains(&key))
                                     && event.value() == 1
                                 {
-                                    // Toggle on key press (not release, not repeat)
-                                    let prev = is_listening.load(Ordering::SeqCst);
-                                    is_listening.store(!prev, Ordering::SeqCst);
+                                    on_press(key);
                                 }
                             }
                         }
@@ -85,6 +280,139 @@ pub fn listen_all_keyboards(key: KeyCode, is_listening: Arc<AtomicBool>) -> Resu
                 }
             })?;
     }
+    // Only the listeners hold senders now, so the wait can't outlive them
+    drop(opened_tx);
+
+    Ok(opened_rx.iter().take(count).flatten().collect())
+}
+
+/// `--led-indicator`: mirror the listening state on the keyboards' Scroll Lock LED.
+///
+/// Pressing Scroll Lock also makes the console or X server flip that LED. Both
+/// flips go the same way, and `set` runs after the press was handled, so the
+/// LED settles on our state instead of fighting over it.
+pub struct LedIndicator {
+    devices: Vec<Device>,
+}
+
+impl LedIndicator {
+    /// Only Scroll Lock has an LED among the offered hotkeys (Pause has none).
+    pub fn new(key: KeyCode) -> Option<Self> {
+        if key != KeyCode::KEY_SCROLLLOCK {
+            warn!("--led-indicator needs the Scroll Lock hotkey, {key:?} has no LED");
+            return None;
+        }
+        let devices: Vec<Device> = find_keyboards()
+            .into_iter()
+            .filter_map(|(path, _)| Device::open(path).ok())
+            .filter(|dev| {
+                dev.supported_leds()
+                    .is_some_and(|leds| leds.contains(LedCode::LED_SCROLLL))
+            })
+            .collect();
+        if devices.is_empty() {
+            warn!("--led-indicator: no keyboard with a Scroll Lock LED could be opened");
+            return None;
+        }
+        debug!("Scroll Lock LED indicator on {} keyboard(s)", devices.len());
+        let mut indicator = Self { devices };
+        indicator.set(false); // not listening yet
+        Some(indicator)
+    }
+
+    pub fn set(&mut self, on: bool) {
+        let event = InputEvent::new(EventType::LED.0, LedCode::LED_SCROLLL.0, i32::from(on));
+        for device in &mut self.devices {
+            if let Err(e) = device.send_events(&[event]) {
+                debug!("Could not set Scroll Lock LED: {e}");
+            }
+        }
+    }
+}
 
+/// Privileged side of `--sudo-helper`: watch the keyboards and print the key
+/// code of each hotkey press on stdout. Runs until the client closes the pipe.
+/// Reports the keyboards on stderr (the client's terminal) unless `no_summary`.
+pub fn run_helper(key_codes: &[u16], device: Option<HotkeyDevice>, no_summary: bool) -> Result<()> {
+    let keys: Vec<KeyCode> = key_codes.iter().map(|&code| KeyCode::new(code)).collect();
+    let opened = on_key_press(&keys, device, |key| {
+        let mut out = std::io::stdout().lock();
+        if writeln!(out, "{}", key.code())
+            .and_then(|_| out.flush())
+            .is_err()
+        {
+            std::process::exit(0); // client went away
+        }
+    })?;
+    if opened.is_empty() {
+        // Exiting lets the client fall back to the terminal
+        anyhow::bail!("No keyboard could be opened for the hotkey");
+    }
+    if !no_summary {
+        report_listening(&keys, device, &opened);
+    }
+    loop {
+        std::thread::park();
+    }
+}
+
+/// Unprivileged side of `--sudo-helper`: run this binary's hotkey helper under
+/// `sudo -n` (credentials cached beforehand with `sudo -v`) and toggle on its output.
+/// If the helper dies, falls back to the terminal like `listen_all_keyboards`.
+/// The helper reports the keyboards it reads itself, unless `no_summary`.
+/// This binary is user-writable: a `NOPASSWD` rule for it would hand out root.
+pub fn listen_via_sudo(
+    keys: &[KeyCode],
+    device: Option<HotkeyDevice>,
+    is_listening: Arc<AtomicBool>,
+    pressed: Sender<KeyCode>,
+    stdin_fallback: bool,
+    no_summary: bool,
+) -> Result<()> {
+    let exe = std::env::current_exe().context("Cannot locate the client executable")?;
+    let codes: Vec<String> = keys.iter().map(|key| key.code().to_string()).collect();
+    let mut child = Command::new("sudo")
+        .arg("-n")
+        .arg(exe)
+        .args(["--hotkey-helper", &codes.join(",")])
+        .args(
+            device
+                .iter()
+                .flat_map(|device| ["--hotkey-device-id".to_string(), device.id.to_string()]),
+        )
+        .args(
+            device
+                .is_some_and(|device| device.any_key)
+                .then_some("--hotkey-any-key"),
+        )
+        .args(no_summary.then_some("--no-keyboard-summary"))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn sudo hotkey helper")?;
+    let stdout = child
+        .stdout
+        .take()
+        .context("Failed to open hotkey helper stdout")?;
+
+    std::thread::Builder::new()
+        .name("hotkey-helper".into())
+        .spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else { break };
+                if let Ok(code) = line.trim().parse() {
+                    let _ = pressed.send(KeyCode::new(code));
+                }
+                toggle(&is_listening);
+            }
+            let _ = child.wait();
+            warn!("Hotkey helper exited, the hotkey no longer works.");
+            if stdin_fallback {
+                warn!("  Falling back to the terminal: press Enter here to toggle listening.");
+                if let Err(e) = listen_stdin(is_listening) {
+                    warn!("Cannot read the terminal: {e}");
+                }
+            }
+        })?;
     Ok(())
 }
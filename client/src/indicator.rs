@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use space_tts_common::warn;
+
+/// Default location of the listening-state file:
+/// `$XDG_STATE_HOME/space_tts/listening`, else `~/.local/state/space_tts/listening`.
+pub fn default_path() -> PathBuf {
+    let state_dir = std::env::var_os("XDG_STATE_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".local/state")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    state_dir.join("space_tts/listening")
+}
+
+/// Mirrors the listening state into a file (`1` = mic hot, `0` = muted)
+/// so status bars (waybar, polybar) can display a recording indicator.
+pub struct StateFile {
+    path: PathBuf,
+}
+
+impl StateFile {
+    pub fn new(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create state directory: {}", parent.display())
+            })?;
+        }
+        let state_file = Self {
+            path: path.to_path_buf(),
+        };
+        state_file.write(false)?;
+        Ok(state_file)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn set(&self, listening: bool) {
+        if let Err(e) = self.write(listening) {
+            warn!("Failed to update state file {}: {e}", self.path.display());
+        }
+    }
+
+    fn write(&self, listening: bool) -> Result<()> {
+        // Write to a sibling temp file and rename, so readers never see a partial write
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, if listening { "1\n" } else { "0\n" })?;
+        std::fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for StateFile {
+    fn drop(&mut self) {
+        // Leave the indicator in the "muted" state once the client exits
+        let _ = self.write(false);
+    }
+}
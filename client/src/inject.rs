@@ -1,76 +1,124 @@
 use anyhow::{Context, Result, bail};
 use std::io::Write;
+use std::os::fd::AsRawFd;
 use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 
 use space_tts_common::warn;
 
-pub trait TextInjector {
-    fn type_text(&mut self, text: &str) -> Result<()>;
+pub trait TextInjector: Send {
+    /// Type `text` (sanitized) preceded by `separator`, which is kept as is
+    /// (sanitizing would trim it).
+    fn type_after(&mut self, separator: &str, text: &str) -> Result<()>;
+
+    fn type_text(&mut self, text: &str) -> Result<()> {
+        self.type_after("", text)
+    }
+
+    /// When everything sent so far should be on screen, for injectors that
+    /// type in the background (dotool); `None` once `type_after` returns.
+    fn typed_by(&self) -> Option<Instant> {
+        None
+    }
 }
 
+/// Default `--max-type-chars`: long enough for a sentence, short enough for dotool's pipe.
+pub const DEFAULT_MAX_TYPE_CHARS: usize = 200;
+/// dotool's default delay between keys and time each key is held.
+const DOTOOL_TYPEDELAY_MS: u64 = 2;
+const DOTOOL_TYPEHOLD_MS: u64 = 8;
+/// How long a closed dotool gets past its estimated typing time before it is killed.
+const DOTOOL_EXIT_GRACE: Duration = Duration::from_millis(500);
+
 pub struct Injector {
     child: Child,
     xkb_layout: String,
+    /// Run dotool through `sudo -n` (for users outside the 'input' group).
+    sudo: bool,
+    /// Longer text is typed with several `type` commands, flushed one by one.
+    max_type_chars: usize,
+    /// `--human-typing`: a random typing speed for each word.
+    human: Option<HumanTyping>,
+    /// `--unicode-input`: type characters outside the layout with Ctrl+Shift+U.
+    unicode_input: bool,
+    /// When dotool should be done typing what it was sent.
+    typed_by: Instant,
 }
 
 impl Injector {
-    pub fn new(xkb_layout: &str) -> Result<Self> {
-        // Preflight: check /dev/uinput access
+    pub fn new(xkb_layout: &str, sudo: bool, max_type_chars: usize) -> Result<Self> {
+        // Preflight: check /dev/uinput access (sudo'd dotool opens it as root)
         let uinput = std::path::Path::new("/dev/uinput");
         if !uinput.exists() {
             bail!(
                 "Cannot access /dev/uinput. Ensure your user is in the 'input' group and log out/in."
             );
         }
-        match std::fs::OpenOptions::new()
-            .write(true)
-            .open(uinput)
-        {
-            Ok(_) => {}
-            Err(_) => {
-                bail!(
-                    "Cannot access /dev/uinput. Ensure your user is in the 'input' group and log out/in."
-                );
-            }
+        if !sudo && open_uinput().is_err() {
+            bail!(
+                "Cannot access /dev/uinput. Ensure your user is in the 'input' group and log out/in,\n\
+                 or run with --sudo-helper to inject through sudo."
+            );
         }
 
         // Preflight: check dotool in PATH
-        let status = Command::new("which")
-            .arg("dotool")
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .status();
-        match status {
-            Ok(s) if s.success() => {}
-            _ => {
-                bail!("dotool not found. Install it: https://git.sr.ht/~geb/dotool");
-            }
+        if !dotool_in_path() {
+            bail!("dotool not found. Install it: https://git.sr.ht/~geb/dotool");
         }
 
-        let child = spawn_dotool(xkb_layout)?;
+        let child = spawn_dotool(xkb_layout, sudo)?;
         Ok(Self {
             child,
             xkb_layout: xkb_layout.to_string(),
+            sudo,
+            max_type_chars,
+            human: None,
+            unicode_input: false,
+            typed_by: Instant::now(),
         })
     }
 
+    /// Type each word with its own delay between keys, drawn around `mean_ms`.
+    pub fn human_typing(mut self, mean_ms: u32) -> Self {
+        self.human = Some(HumanTyping::new(mean_ms));
+        self
+    }
+
+    /// Type characters outside the layout through GTK/IBus Unicode input
+    /// instead of skipping them. Elsewhere this types their hex code.
+    pub fn unicode_input(mut self) -> Self {
+        self.unicode_input = true;
+        self
+    }
+
     fn respawn(&mut self) -> Result<()> {
         let _ = self.child.kill();
         let _ = self.child.wait();
-        self.child = spawn_dotool(&self.xkb_layout)?;
+        self.child = spawn_dotool(&self.xkb_layout, self.sudo)?;
         Ok(())
     }
 }
 
-impl TextInjector for Injector {
-    fn type_text(&mut self, text: &str) -> Result<()> {
-        let sanitized = sanitize(text);
-        if sanitized.is_empty() {
-            return Ok(());
-        }
+/// Whether `/dev/uinput` can be opened for writing by this process.
+pub fn open_uinput() -> std::io::Result<()> {
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open("/dev/uinput")
+        .map(drop)
+}
 
-        let cmd = format!("type {sanitized}\n");
+pub fn dotool_in_path() -> bool {
+    Command::new("which")
+        .arg("dotool")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|s| s.success())
+}
 
+impl Injector {
+    fn send(&mut self, cmd: &str) -> Result<()> {
         let write_result = (|| -> Result<()> {
             let stdin = self
                 .child
@@ -98,39 +146,516 @@ impl TextInjector for Injector {
     }
 }
 
+impl TextInjector for Injector {
+    fn type_after(&mut self, separator: &str, text: &str) -> Result<()> {
+        let sanitized = sanitize(text);
+        if sanitized.is_empty() {
+            return Ok(());
+        }
+        let text = format!("{separator}{sanitized}");
+        let commands = dotool_commands(
+            &text,
+            &self.xkb_layout,
+            self.max_type_chars,
+            self.unicode_input,
+            self.human.as_mut(),
+        );
+        self.typed_by = self.typed_by.max(Instant::now()) + typing_time(&commands);
+        for command in commands {
+            self.send(&command)?;
+        }
+        Ok(())
+    }
+
+    fn typed_by(&self) -> Option<Instant> {
+        Some(self.typed_by)
+    }
+}
+
+/// Roughly how long dotool takes to run `commands` (`--human-typing` makes
+/// this tens of seconds for a long result).
+fn typing_time(commands: &[String]) -> Duration {
+    let mut delay = DOTOOL_TYPEDELAY_MS;
+    let mut keys = Duration::ZERO;
+    for line in commands.iter().flat_map(|command| command.lines()) {
+        if let Some(ms) = line.strip_prefix("typedelay ") {
+            delay = ms.parse().unwrap_or(DOTOOL_TYPEDELAY_MS);
+        } else if let Some(text) = line.strip_prefix("type ") {
+            keys +=
+                Duration::from_millis((delay + DOTOOL_TYPEHOLD_MS) * text.chars().count() as u64);
+        } else if line.starts_with("key ") {
+            keys += Duration::from_millis(DOTOOL_TYPEDELAY_MS + DOTOOL_TYPEHOLD_MS);
+        }
+    }
+    keys
+}
+
+/// `--human-typing` cadence: dotool's `typedelay` (between the keys of one
+/// `type` command) drawn for each word between half and one and a half times
+/// the mean. Xorshift is plenty for jitter.
+pub struct HumanTyping {
+    state: u64,
+    mean_ms: u32,
+}
+
+impl HumanTyping {
+    pub fn new(mean_ms: u32) -> Self {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::with_seed(mean_ms, nanos ^ u64::from(std::process::id()))
+    }
+
+    fn with_seed(mean_ms: u32, seed: u64) -> Self {
+        Self {
+            state: seed | 1, // xorshift never leaves 0
+            mean_ms,
+        }
+    }
+
+    fn next_delay(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.mean_ms / 2 + (self.state % (u64::from(self.mean_ms) + 1)) as u32
+    }
+}
+
+/// The dotool commands typing `text`: one `type` per run of at most
+/// `max_chars` characters (per word with `human`, each after its `typedelay`).
+/// Characters outside the layout get the Unicode input sequence with
+/// `unicode_input`, and are skipped with a warning otherwise.
+fn dotool_commands(
+    text: &str,
+    xkb_layout: &str,
+    max_chars: usize,
+    unicode_input: bool,
+    mut human: Option<&mut HumanTyping>,
+) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut skipped = String::new();
+    for run in split_typeable(text, xkb_layout) {
+        match run {
+            Run::Typeable(text) => match human.as_deref_mut() {
+                Some(human) => {
+                    for word in text.split_inclusive(char::is_whitespace) {
+                        for piece in split_long(word, max_chars) {
+                            let delay = human.next_delay();
+                            commands.push(format!("typedelay {delay}\ntype {piece}\n"));
+                        }
+                    }
+                }
+                None => commands.extend(
+                    split_long(&text, max_chars)
+                        .into_iter()
+                        .map(|piece| format!("type {piece}\n")),
+                ),
+            },
+            // GTK/IBus Unicode input: Ctrl+Shift+U, hex codepoint, Space to commit
+            Run::Unicode(c) if unicode_input => commands.push(format!(
+                "key ctrl+shift+u\ntype {:x}\nkey space\n",
+                c as u32
+            )),
+            Run::Unicode(c) => skipped.push(c),
+        }
+    }
+    if !skipped.is_empty() {
+        warn!(
+            "Skipped characters the {xkb_layout} layout can't type: {skipped} (see --unicode-input)"
+        );
+    }
+    commands
+}
+
+/// Cut `text` into pieces of at most `max_chars` characters, after whitespace
+/// when possible; a longer word is cut between characters.
+fn split_long(text: &str, max_chars: usize) -> Vec<String> {
+    let max_chars = max_chars.max(1);
+    let mut pieces = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+    for word in text.split_inclusive(char::is_whitespace) {
+        let word_len = word.chars().count();
+        if current_len + word_len > max_chars && !current.is_empty() {
+            pieces.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if word_len <= max_chars {
+            current.push_str(word);
+            current_len += word_len;
+            continue;
+        }
+        for c in word.chars() {
+            if current_len == max_chars {
+                pieces.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            current.push(c);
+            current_len += 1;
+        }
+    }
+    if !current.is_empty() {
+        pieces.push(current);
+    }
+    pieces
+}
+
+/// Non-ASCII characters each layout can type (base or AltGr level, or a dead
+/// key then a letter): sent to dotool's `type` with the rest of the text.
+const LAYOUT_CHARS: &[(&str, &str)] = &[
+    ("us", ""),
+    ("gb", "£€¬¦"),
+    ("fr", "éèàùç²°µ£¤§€«»œŒâêîôûÂÊÎÔÛäëïöüÿÄËÏÖÜ"),
+    ("be", "éèàùç²³°µ£§€âêîôûÂÊÎÔÛäëïöüÿÄËÏÖÜ"),
+    ("ch", "éèàäöüç§°£€âêîôûÂÊÎÔÛëïÿËÏáíóúÁÍÓÚìòÌÒñÑ"),
+    ("de", "äöüßÄÖÜ§°²³µ€âêîôûÂÊÎÔÛáéíóúÁÉÍÓÚàèìòùÀÈÌÒÙ"),
+    ("es", "ñÑçÇºª¡¿·¬€áéíóúÁÉÍÓÚàèìòùÀÈÌÒÙâêîôûÂÊÎÔÛäëïöüÄËÏÖÜ"),
+    ("it", "àèéìòùç°£§€"),
+    ("pt", "çÇºª«»£§€áéíóúÁÉÍÓÚàèìòùÀÈÌÒÙâêîôûÂÊÎÔÛãõÃÕ"),
+];
+
+/// Whether dotool cannot type `c` on `xkb_layout` (skipped, or typed through
+/// `--unicode-input`). Layouts (or variants) not listed above keep the old
+/// behaviour: everything goes through dotool's `type`.
+fn needs_unicode_input(c: char, xkb_layout: &str) -> bool {
+    if c.is_ascii() {
+        return false;
+    }
+    LAYOUT_CHARS
+        .iter()
+        .find(|(layout, _)| *layout == xkb_layout)
+        .is_some_and(|(_, chars)| !chars.contains(c))
+}
+
+#[derive(Debug, PartialEq)]
+enum Run {
+    /// Text dotool can type in one `type` command.
+    Typeable(String),
+    /// A character outside the layout.
+    Unicode(char),
+}
+
+/// Split `text` into runs dotool types directly and characters needing the fallback.
+fn split_typeable(text: &str, xkb_layout: &str) -> Vec<Run> {
+    let mut runs = Vec::new();
+    let mut current = String::new();
+    for c in text.chars() {
+        if needs_unicode_input(c, xkb_layout) {
+            if !current.is_empty() {
+                runs.push(Run::Typeable(std::mem::take(&mut current)));
+            }
+            runs.push(Run::Unicode(c));
+        } else {
+            current.push(c);
+        }
+    }
+    if !current.is_empty() {
+        runs.push(Run::Typeable(current));
+    }
+    runs
+}
+
+/// `--inject-backend tmux`: types into a tmux pane with `tmux send-keys`,
+/// bypassing uinput and the keyboard layout (e.g. a detached pane over SSH).
+pub struct TmuxInjector {
+    /// `-t` target pane (`session:window.pane`); tmux's current pane when unset.
+    target: Option<String>,
+}
+
+impl TmuxInjector {
+    pub fn new(target: Option<String>) -> Result<Self> {
+        // Check the pane exists now rather than failing on the first result
+        let status = Command::new("tmux")
+            .args(["display-message", "-p"])
+            .args(target.iter().flat_map(|t| ["-t", t.as_str()]))
+            .arg("")
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        match status {
+            Ok(s) if s.success() => Ok(Self { target }),
+            Ok(_) => bail!(
+                "tmux pane {} not found (is the tmux server running?)",
+                target.as_deref().unwrap_or("(current)")
+            ),
+            Err(e) => bail!("Cannot run tmux: {e}"),
+        }
+    }
+}
+
+impl TextInjector for TmuxInjector {
+    fn type_after(&mut self, separator: &str, text: &str) -> Result<()> {
+        let sanitized = sanitize(text);
+        if sanitized.is_empty() {
+            return Ok(());
+        }
+        let status = Command::new("tmux")
+            .args(tmux_args(
+                self.target.as_deref(),
+                &format!("{separator}{sanitized}"),
+            ))
+            .status()
+            .context("Failed to run tmux send-keys")?;
+        if !status.success() {
+            bail!("tmux send-keys failed ({status})");
+        }
+        Ok(())
+    }
+}
+
+/// `send-keys` arguments typing `text` literally (`-l`: no key-name lookup, so
+/// a dictated "Enter" stays a word).
+fn tmux_args(target: Option<&str>, text: &str) -> Vec<String> {
+    let mut args = vec!["send-keys".to_string()];
+    if let Some(target) = target {
+        args.extend(["-t".to_string(), target.to_string()]);
+    }
+    args.extend(["-l".to_string(), "--".to_string(), text.to_string()]);
+    args
+}
+
+/// `--inject-backend tty`: pushes the text into a Linux console's input queue
+/// with the `TIOCSTI` ioctl, as if typed there (no uinput, no keyboard layout).
+/// Needs root, and since Linux 6.2 `sysctl dev.tty.legacy_tiocsti=1`.
+pub struct TtyInjector {
+    tty: std::fs::File,
+    path: String,
+}
+
+impl TtyInjector {
+    /// `path` defaults to `/dev/tty0`, the console in the foreground.
+    pub fn new(path: Option<String>) -> Result<Self> {
+        let path = path.unwrap_or_else(|| "/dev/tty0".to_string());
+        let tty = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .with_context(|| format!("Cannot open {path} (the tty backend needs root)"))?;
+        Ok(Self { tty, path })
+    }
+}
+
+impl TextInjector for TtyInjector {
+    fn type_after(&mut self, separator: &str, text: &str) -> Result<()> {
+        let sanitized = sanitize(text);
+        if sanitized.is_empty() {
+            return Ok(());
+        }
+        for byte in format!("{separator}{sanitized}").bytes() {
+            // SAFETY: TIOCSTI reads a single byte through the pointer
+            let result =
+                unsafe { libc::ioctl(self.tty.as_raw_fd(), libc::TIOCSTI, &byte as *const u8) };
+            if result != 0 {
+                bail!(
+                    "TIOCSTI on {} failed: {} (needs root, and dev.tty.legacy_tiocsti=1 on Linux 6.2+)",
+                    self.path,
+                    std::io::Error::last_os_error()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether an X11 or Wayland session is around: on a bare console, dotool's
+/// XKB layout may not match the console keymap.
+pub fn graphical_session() -> bool {
+    ["WAYLAND_DISPLAY", "DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).is_some_and(|v| !v.is_empty()))
+}
+
+/// Results waiting for a stalled injector before new ones are dropped.
+const INJECT_QUEUE: usize = 8;
+/// How long dropping a `QueuedInjector` waits for queued results to be typed.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Runs another injector on its own thread behind a bounded queue, so a
+/// stalled dotool (e.g. hung compositor) can't block audio capture and
+/// transcription: when the queue is full, results are dropped with a warning.
+pub struct QueuedInjector {
+    tx: Option<crossbeam_channel::Sender<(String, String)>>,
+    done_rx: crossbeam_channel::Receiver<()>,
+    /// The inner injector's `typed_by` after its last result.
+    typed_by: Arc<Mutex<Option<Instant>>>,
+}
+
+impl QueuedInjector {
+    pub fn new(mut inner: Box<dyn TextInjector>) -> Result<Self> {
+        let (tx, rx) = crossbeam_channel::bounded::<(String, String)>(INJECT_QUEUE);
+        let (done_tx, done_rx) = crossbeam_channel::bounded(1);
+        let typed_by = Arc::new(Mutex::new(None));
+        let inner_typed_by = typed_by.clone();
+        std::thread::Builder::new()
+            .name("injector".into())
+            .spawn(move || {
+                for (separator, text) in rx {
+                    if let Err(e) = inner.type_after(&separator, &text) {
+                        warn!("Injection error: {e}");
+                    }
+                    *inner_typed_by
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner) = inner.typed_by();
+                }
+                drop(inner);
+                let _ = done_tx.send(());
+            })?;
+        Ok(Self {
+            tx: Some(tx),
+            done_rx,
+            typed_by,
+        })
+    }
+}
+
+impl TextInjector for QueuedInjector {
+    fn type_after(&mut self, separator: &str, text: &str) -> Result<()> {
+        let tx = self.tx.as_ref().context("injector stopped")?;
+        match tx.try_send((separator.to_string(), text.to_string())) {
+            Ok(()) => Ok(()),
+            Err(crossbeam_channel::TrySendError::Full(_)) => {
+                warn!("Injection is stalled ({INJECT_QUEUE} results waiting), result dropped.");
+                Ok(())
+            }
+            Err(crossbeam_channel::TrySendError::Disconnected(_)) => bail!("injector thread died"),
+        }
+    }
+}
+
+impl Drop for QueuedInjector {
+    fn drop(&mut self) {
+        // Let queued results be typed, but don't hang shutdown on a stalled
+        // injector: each result typed extends the wait by its typing time
+        drop(self.tx.take());
+        let stopped = Instant::now();
+        loop {
+            let typed_by = *self.typed_by.lock().unwrap_or_else(PoisonError::into_inner);
+            let deadline = typed_by.map_or(stopped, |t| t.max(stopped)) + DRAIN_TIMEOUT;
+            match self.done_rx.recv_deadline(deadline) {
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) if Instant::now() >= deadline => {
+                    warn!("Injector did not finish typing in time, exiting anyway.");
+                    return;
+                }
+                Err(crossbeam_channel::RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+        }
+    }
+}
+
+/// Suppresses an injection identical to the previous one within `window`
+/// (whisper sometimes returns the same text for two near-identical segments).
+/// A zero window disables it.
+pub struct Debouncer {
+    window: Duration,
+    last: Option<(String, Instant)>,
+}
+
+impl Debouncer {
+    pub fn new(window: Duration) -> Self {
+        Self { window, last: None }
+    }
+
+    /// Whether `text` should be injected at `now`; remembers it if so.
+    pub fn should_inject(&mut self, text: &str, now: Instant) -> bool {
+        let text = text.trim();
+        if let Some((last, at)) = &self.last
+            && last == text
+            && now.duration_since(*at) < self.window
+        {
+            return false;
+        }
+        self.last = Some((text.to_string(), now));
+        true
+    }
+}
+
 impl Drop for Injector {
     fn drop(&mut self) {
+        // At end of input dotool types what it was sent, then exits: give it
+        // the time that takes before killing it
+        drop(self.child.stdin.take());
+        let deadline = self.typed_by.max(Instant::now()) + DOTOOL_EXIT_GRACE;
+        while Instant::now() < deadline {
+            match self.child.try_wait() {
+                Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+                _ => return,
+            }
+        }
+        warn!("dotool still typing at exit, stopping it.");
         let _ = self.child.kill();
         let _ = self.child.wait();
     }
 }
 
-fn spawn_dotool(xkb_layout: &str) -> Result<Child> {
-    let mut cmd = Command::new("dotool");
-    cmd.stdin(Stdio::piped())
-        .stdout(Stdio::null())
-        .stderr(Stdio::null());
-
+fn spawn_dotool(xkb_layout: &str, sudo: bool) -> Result<Child> {
     // Split "us+altgr-intl" into DOTOOL_XKB_LAYOUT=us, DOTOOL_XKB_VARIANT=altgr-intl
+    let mut env = Vec::new();
     if let Some((layout, variant)) = xkb_layout.split_once('+') {
-        cmd.env("DOTOOL_XKB_LAYOUT", layout);
-        cmd.env("DOTOOL_XKB_VARIANT", variant);
+        env.push(("DOTOOL_XKB_LAYOUT", layout));
+        env.push(("DOTOOL_XKB_VARIANT", variant));
     } else {
-        cmd.env("DOTOOL_XKB_LAYOUT", xkb_layout);
+        env.push(("DOTOOL_XKB_LAYOUT", xkb_layout));
     }
 
+    let mut cmd = if sudo {
+        // sudo resets the environment: pass the layout through `env`. Never
+        // whitelist this with NOPASSWD: `env` runs whatever follows as root
+        let mut cmd = Command::new("sudo");
+        cmd.args(["-n", "env"]);
+        cmd.args(env.iter().map(|(k, v)| format!("{k}={v}")));
+        cmd.arg("dotool");
+        cmd
+    } else {
+        let mut cmd = Command::new("dotool");
+        cmd.envs(env);
+        cmd
+    };
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+
     cmd.spawn().context("Failed to spawn dotool")
 }
 
+/// The layout dotool types with: `--xkb-layout`, else the
+/// `SPACE_TTS_XKB_LAYOUT` environment variable, else the detected system layout.
+pub fn xkb_layout(flag: Option<&str>) -> String {
+    let env = std::env::var("SPACE_TTS_XKB_LAYOUT").ok();
+    resolve_xkb_layout(flag, env.as_deref(), detect_xkb_layout)
+}
+
+/// First non-empty override, falling back to `detect` (only run when needed).
+fn resolve_xkb_layout(
+    flag: Option<&str>,
+    env: Option<&str>,
+    detect: impl FnOnce() -> String,
+) -> String {
+    [flag, env]
+        .into_iter()
+        .flatten()
+        .map(str::trim)
+        .find(|layout| !layout.is_empty())
+        .map_or_else(detect, str::to_string)
+}
+
 /// Auto-detect the system XKB keyboard layout.
 /// Returns a string like "us", "us+altgr-intl", "fr", etc.
-pub fn detect_xkb_layout() -> String {
+fn detect_xkb_layout() -> String {
     if let Some(layout) = detect_from_gsettings() {
         return layout;
     }
     if let Some(layout) = detect_from_localectl() {
         return layout;
     }
+    if let Some(layout) = detect_from_kxkbrc() {
+        return layout;
+    }
+    if let Some(layout) = std::fs::read_to_string("/etc/default/keyboard")
+        .ok()
+        .and_then(|contents| parse_default_keyboard(&contents))
+    {
+        return layout;
+    }
     "us".to_string()
 }
 
@@ -202,6 +727,61 @@ fn parse_localectl_layout(output: &str) -> Option<String> {
     }
 }
 
+/// Join the first entries of comma-separated layout/variant lists as "layout+variant".
+fn first_layout(layouts: &str, variants: Option<&str>) -> Option<String> {
+    let layout = layouts.split(',').next()?.trim();
+    if layout.is_empty() {
+        return None;
+    }
+    match variants.and_then(|v| v.split(',').next()).map(str::trim) {
+        Some(variant) if !variant.is_empty() => Some(format!("{layout}+{variant}")),
+        _ => Some(layout.to_string()),
+    }
+}
+
+/// KDE Plasma keeps the user's layouts in ~/.config/kxkbrc.
+fn detect_from_kxkbrc() -> Option<String> {
+    let home = std::env::var("HOME").ok()?;
+    let contents = std::fs::read_to_string(format!("{home}/.config/kxkbrc")).ok()?;
+    parse_kxkbrc(&contents)
+}
+
+fn parse_kxkbrc(contents: &str) -> Option<String> {
+    // [Layout]
+    // LayoutList=fr,us
+    // VariantList=oss,
+    let mut layouts = None;
+    let mut variants = None;
+    for line in contents.lines() {
+        let trimmed = line.trim();
+        if let Some(rest) = trimmed.strip_prefix("LayoutList=") {
+            layouts = Some(rest);
+        }
+        if let Some(rest) = trimmed.strip_prefix("VariantList=") {
+            variants = Some(rest);
+        }
+    }
+    first_layout(layouts?, variants)
+}
+
+/// Debian-style /etc/default/keyboard (XKBLAYOUT="fr" / XKBVARIANT="oss").
+fn parse_default_keyboard(contents: &str) -> Option<String> {
+    let mut layouts = None;
+    let mut variants = None;
+    for line in contents.lines() {
+        let Some((key, value)) = line.trim().split_once('=') else {
+            continue;
+        };
+        let value = value.trim().trim_matches(['"', '\'']);
+        match key.trim() {
+            "XKBLAYOUT" => layouts = Some(value),
+            "XKBVARIANT" => variants = Some(value),
+            _ => {}
+        }
+    }
+    first_layout(layouts?, variants)
+}
+
 pub fn sanitize(text: &str) -> String {
     let s: String = text
         .chars()
@@ -220,6 +800,200 @@ pub fn sanitize(text: &str) -> String {
 mod tests {
     use super::*;
 
+    /// Takes `delay` per result, like dotool behind a hung compositor.
+    struct SlowInjector {
+        delay: Duration,
+        typed: std::sync::Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl TextInjector for SlowInjector {
+        fn type_after(&mut self, separator: &str, text: &str) -> Result<()> {
+            std::thread::sleep(self.delay);
+            self.typed
+                .lock()
+                .unwrap()
+                .push(format!("{separator}{text}"));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn queued_injector_never_blocks_the_caller() {
+        let typed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut injector = QueuedInjector::new(Box::new(SlowInjector {
+            delay: Duration::from_millis(100),
+            typed: typed.clone(),
+        }))
+        .unwrap();
+
+        // The main loop keeps going (draining audio) while results pile up
+        let started = Instant::now();
+        for i in 0..(INJECT_QUEUE + 5) {
+            injector.type_after(" ", &i.to_string()).unwrap();
+        }
+        assert!(started.elapsed() < Duration::from_millis(100));
+
+        drop(injector);
+        let typed = typed.lock().unwrap();
+        assert!(
+            typed.len() < INJECT_QUEUE + 5,
+            "Overflowing results are dropped"
+        );
+        assert_eq!(typed[0], " 0", "Results are typed in order");
+    }
+
+    #[test]
+    fn queued_injector_types_everything_when_keeping_up() {
+        let typed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut injector = QueuedInjector::new(Box::new(SlowInjector {
+            delay: Duration::ZERO,
+            typed: typed.clone(),
+        }))
+        .unwrap();
+        injector.type_text("hello").unwrap();
+        injector.type_after(" ", "world").unwrap();
+        drop(injector);
+        assert_eq!(*typed.lock().unwrap(), ["hello", " world"]);
+    }
+
+    #[test]
+    fn typing_time_follows_typedelay() {
+        let plain = [
+            "type hello\n".to_string(),
+            "key ctrl+shift+u\ntype e9\nkey space\n".to_string(),
+        ];
+        // 7 characters and 2 keys at dotool's default 2 + 8 ms
+        assert_eq!(typing_time(&plain), Duration::from_millis(90));
+        let human = ["typedelay 72\ntype Bonjour \n".to_string()];
+        assert_eq!(typing_time(&human), Duration::from_millis(8 * 80));
+    }
+
+    #[test]
+    fn layout_characters_stay_on_type_path() {
+        assert!(!needs_unicode_input('a', "us"));
+        assert!(!needs_unicode_input('é', "fr"));
+        assert!(!needs_unicode_input('ê', "fr"), "Dead key");
+        assert!(!needs_unicode_input('œ', "fr"));
+        assert!(!needs_unicode_input('ß', "de"));
+        // Unknown layouts and variants are left to dotool
+        assert!(!needs_unicode_input('€', "us+altgr-intl"));
+        assert!(!needs_unicode_input('ñ', "jp"));
+    }
+
+    #[test]
+    fn out_of_layout_characters_need_fallback() {
+        assert!(needs_unicode_input('€', "us"));
+        assert!(needs_unicode_input('ñ', "fr"));
+        assert!(needs_unicode_input('É', "fr"));
+        assert!(needs_unicode_input('→', "de"));
+    }
+
+    #[test]
+    fn split_typeable_isolates_fallback_characters() {
+        assert_eq!(
+            split_typeable(" 5€ ou 6 ñ", "fr"),
+            [Run::Typeable(" 5€ ou 6 ".to_string()), Run::Unicode('ñ'),]
+        );
+        assert_eq!(
+            split_typeable("Élan", "fr"),
+            [Run::Unicode('É'), Run::Typeable("lan".to_string())]
+        );
+        assert_eq!(split_typeable("", "us"), []);
+    }
+
+    #[test]
+    fn long_text_is_typed_in_several_commands() {
+        let sentence = "Il marche très bien. ".repeat(20); // 420 characters
+        let commands = dotool_commands(&sentence, "fr", 100, false, None);
+        assert_eq!(commands.len(), 5);
+        let pieces: Vec<&str> = commands
+            .iter()
+            .map(|c| c.strip_prefix("type ").unwrap().strip_suffix('\n').unwrap())
+            .collect();
+        for piece in &pieces {
+            assert!(piece.chars().count() <= 100);
+            assert!(piece.ends_with(' '), "Cut after whitespace: {piece:?}");
+        }
+        assert_eq!(pieces.concat(), sentence);
+
+        assert_eq!(
+            dotool_commands("Short one", "us", 100, false, None),
+            ["type Short one\n"]
+        );
+    }
+
+    #[test]
+    fn unicode_input_is_opt_in() {
+        assert_eq!(
+            dotool_commands("5 ñ", "fr", 100, true, None),
+            ["type 5 \n", "key ctrl+shift+u\ntype f1\nkey space\n"]
+        );
+        assert_eq!(
+            dotool_commands("5 ñ", "fr", 100, false, None),
+            ["type 5 \n"],
+            "Skipped"
+        );
+    }
+
+    #[test]
+    fn human_typing_draws_a_delay_per_word() {
+        let mut human = HumanTyping::with_seed(80, 42);
+        let commands = dotool_commands("Bonjour à tous", "fr", 100, false, Some(&mut human));
+        assert_eq!(commands.len(), 3);
+        let words: Vec<&str> = commands
+            .iter()
+            .map(|command| {
+                let (delay, typed) = command.split_once('\n').unwrap();
+                let delay: u32 = delay.strip_prefix("typedelay ").unwrap().parse().unwrap();
+                assert!((40..=120).contains(&delay), "{delay}ms");
+                typed
+                    .strip_prefix("type ")
+                    .unwrap()
+                    .strip_suffix('\n')
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(words, ["Bonjour ", "à ", "tous"]);
+
+        let delays: Vec<u32> = (0..20).map(|_| human.next_delay()).collect();
+        assert!(delays.iter().any(|&d| d != delays[0]), "Delays vary");
+    }
+
+    #[test]
+    fn split_long_cuts_overlong_words_between_characters() {
+        assert_eq!(split_long("ééééé ab", 2), ["éé", "éé", "é ", "ab"]);
+        assert_eq!(split_long("", 10), Vec::<String>::new());
+    }
+
+    #[test]
+    fn tmux_args_type_literally() {
+        assert_eq!(
+            tmux_args(Some("work:1.0"), "-n Enter"),
+            ["send-keys", "-t", "work:1.0", "-l", "--", "-n Enter"]
+        );
+        assert_eq!(tmux_args(None, "hi"), ["send-keys", "-l", "--", "hi"]);
+    }
+
+    #[test]
+    fn debouncer_suppresses_repeats_within_window() {
+        let mut debouncer = Debouncer::new(Duration::from_secs(2));
+        let t0 = Instant::now();
+        assert!(debouncer.should_inject("Bonjour.", t0));
+        assert!(!debouncer.should_inject("Bonjour. ", t0 + Duration::from_millis(500)));
+        assert!(debouncer.should_inject("Autre chose.", t0 + Duration::from_secs(1)));
+        assert!(debouncer.should_inject("Bonjour.", t0 + Duration::from_millis(1500)));
+        // Same text again, but after the window
+        assert!(debouncer.should_inject("Bonjour.", t0 + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn debouncer_zero_window_is_off() {
+        let mut debouncer = Debouncer::new(Duration::ZERO);
+        let t0 = Instant::now();
+        assert!(debouncer.should_inject("oui", t0));
+        assert!(debouncer.should_inject("oui", t0));
+    }
+
     #[test]
     fn sanitize_newlines_to_spaces() {
         assert_eq!(sanitize("line1\nline2"), "line1 line2");
@@ -293,4 +1067,61 @@ mod tests {
             "   System Locale: LANG=en_US.UTF-8\n       VC Keymap: us\n      X11 Layout: us\n";
         assert_eq!(parse_localectl_layout(output), Some("us".to_string()));
     }
+
+    #[test]
+    fn parse_default_keyboard_with_variant() {
+        let contents = "# KEYBOARD CONFIGURATION FILE\n\nXKBMODEL=\"pc105\"\nXKBLAYOUT=\"us\"\nXKBVARIANT=\"altgr-intl\"\nXKBOPTIONS=\"\"\n\nBACKSPACE=\"guess\"\n";
+        assert_eq!(
+            parse_default_keyboard(contents),
+            Some("us+altgr-intl".to_string())
+        );
+    }
+
+    #[test]
+    fn parse_default_keyboard_without_variant() {
+        let contents = "XKBMODEL=\"pc105\"\nXKBLAYOUT=\"fr\"\nXKBVARIANT=\"\"\nXKBOPTIONS=\"\"\n";
+        assert_eq!(parse_default_keyboard(contents), Some("fr".to_string()));
+    }
+
+    #[test]
+    fn parse_default_keyboard_multiple_layouts() {
+        // First layout wins, with its own (possibly empty) variant
+        let contents = "XKBLAYOUT=\"de,us\"\nXKBVARIANT=\"nodeadkeys,\"\n";
+        assert_eq!(
+            parse_default_keyboard(contents),
+            Some("de+nodeadkeys".to_string())
+        );
+        let contents = "XKBLAYOUT=fr,us\nXKBVARIANT=,intl\n";
+        assert_eq!(parse_default_keyboard(contents), Some("fr".to_string()));
+    }
+
+    #[test]
+    fn parse_default_keyboard_missing_layout() {
+        assert_eq!(parse_default_keyboard("XKBMODEL=\"pc105\"\n"), None);
+        assert_eq!(parse_default_keyboard("XKBLAYOUT=\"\"\n"), None);
+    }
+
+    #[test]
+    fn xkb_layout_overrides_take_precedence() {
+        let detect = || "fr".to_string();
+        assert_eq!(resolve_xkb_layout(None, None, detect), "fr");
+        assert_eq!(resolve_xkb_layout(None, Some("de"), detect), "de");
+        assert_eq!(
+            resolve_xkb_layout(Some("us+altgr-intl"), Some("de"), detect),
+            "us+altgr-intl"
+        );
+        // Empty values (e.g. `SPACE_TTS_XKB_LAYOUT=`) don't count
+        assert_eq!(resolve_xkb_layout(Some(""), Some(" "), detect), "fr");
+        assert_eq!(
+            resolve_xkb_layout(None, Some("de"), || unreachable!()),
+            "de"
+        );
+    }
+
+    #[test]
+    fn parse_kxkbrc_layouts() {
+        let contents = "[Layout]\nDisplayNames=,\nLayoutList=fr,us\nUse=true\nVariantList=oss,\n";
+        assert_eq!(parse_kxkbrc(contents), Some("fr+oss".to_string()));
+        assert_eq!(parse_kxkbrc("[Layout]\nUse=false\n"), None);
+    }
 }
@@ -0,0 +1,111 @@
+use anyhow::{Context, Result, bail};
+use evdev::KeyCode;
+
+use space_tts_common::languages;
+
+/// What a `--hotkey-languages` key starts a listening session with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub language: String,
+    /// Model to switch to as well, when given.
+    pub model: Option<String>,
+}
+
+/// `--hotkey-languages`: extra hotkeys that each start listening in their own
+/// language (and optionally with their own model).
+#[derive(Clone)]
+pub struct KeyLanguages {
+    keys: Vec<(KeyCode, Session)>,
+}
+
+impl KeyLanguages {
+    /// Parse `F9=en,F10=fr:medium`: comma-separated `key=language[:model]`
+    /// entries, keys named like evdev's (`F9`, `KEY_F9`, `scrolllock`).
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut keys: Vec<(KeyCode, Session)> = Vec::new();
+        for entry in crate::cli::split_list(spec) {
+            let Some((key, session)) = entry.split_once('=') else {
+                bail!("Invalid --hotkey-languages entry '{entry}' (expected key=language)");
+            };
+            let key = parse_key(key)?;
+            if keys.iter().any(|(k, _)| *k == key) {
+                bail!("{key:?} is bound twice in --hotkey-languages");
+            }
+            let (language, model) = match session.split_once(':') {
+                Some((language, model)) => (language, Some(model.trim().to_string())),
+                None => (session, None),
+            };
+            let session = Session {
+                language: languages::validate(language)?,
+                model: model.filter(|m| !m.is_empty()),
+            };
+            keys.push((key, session));
+        }
+        if keys.is_empty() {
+            bail!("--hotkey-languages needs at least one key");
+        }
+        Ok(Self { keys })
+    }
+
+    /// The bound keys and their sessions, in the order given.
+    pub fn iter(&self) -> impl Iterator<Item = (KeyCode, &Session)> {
+        self.keys.iter().map(|(key, session)| (*key, session))
+    }
+
+    /// The session `key` starts, if it is bound.
+    pub fn session_for(&self, key: KeyCode) -> Option<&Session> {
+        self.keys
+            .iter()
+            .find(|(k, _)| *k == key)
+            .map(|(_, session)| session)
+    }
+}
+
+/// `F9`, `f9` or `KEY_F9`.
+fn parse_key(name: &str) -> Result<KeyCode> {
+    let name = name.trim().to_uppercase();
+    let name = if name.starts_with("KEY_") {
+        name
+    } else {
+        format!("KEY_{name}")
+    };
+    name.parse()
+        .ok()
+        .with_context(|| format!("Unknown key '{name}' in --hotkey-languages"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_keys_to_sessions() {
+        let keys = KeyLanguages::parse("F9=en, f10=fr:medium, KEY_PAUSE=de").unwrap();
+        assert_eq!(
+            keys.iter().map(|(key, _)| key).collect::<Vec<_>>(),
+            [KeyCode::KEY_F9, KeyCode::KEY_F10, KeyCode::KEY_PAUSE]
+        );
+        assert_eq!(
+            keys.session_for(KeyCode::KEY_F10),
+            Some(&Session {
+                language: "fr".to_string(),
+                model: Some("medium".to_string()),
+            })
+        );
+        assert_eq!(
+            keys.session_for(KeyCode::KEY_F9).unwrap().model,
+            None,
+            "Keeps the current model"
+        );
+        assert_eq!(keys.session_for(KeyCode::KEY_F2), None);
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert!(KeyLanguages::parse("F9").is_err());
+        assert!(KeyLanguages::parse("F99=en").is_err());
+        assert!(KeyLanguages::parse("F9=xx").is_err());
+        assert!(KeyLanguages::parse("F9=en,F9=fr").is_err());
+        assert!(KeyLanguages::parse("").is_err());
+    }
+}
@@ -1,175 +1,954 @@
 mod audio;
+mod chunk;
+mod cli;
+mod commit;
+mod control;
+mod doctor;
+mod focus;
+mod format;
 mod hotkey;
+mod indicator;
 mod inject;
+mod keymap;
+mod memory;
+mod profile;
+mod queue;
 mod remote;
+mod triggers;
 mod tui;
 mod vad;
 
 use anyhow::Result;
+use cpal::traits::StreamTrait;
 use inject::TextInjector;
-use remote::Transcriber;
+use space_tts_common::languages;
+use space_tts_common::transcriber::Transcriber;
 use space_tts_common::{debug, info, warn};
-use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// With --confirm-quit, how long a first Ctrl+C waits for the second.
+const QUIT_CONFIRM_WINDOW: Duration = Duration::from_secs(3);
+/// On shutdown mid-utterance, how long to wait for the last segment's text.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(3);
+/// `--auto-restart` waits this long after a crash, doubling up to the max.
+const RESTART_BACKOFF_MIN: Duration = Duration::from_secs(1);
+const RESTART_BACKOFF_MAX: Duration = Duration::from_secs(60);
+/// A running capture stream delivers a chunk every few ms: this long without
+/// one means it stalled (device suspended, PipeWire reconfigured).
+const CAPTURE_STALL: Duration = Duration::from_secs(5);
 
 fn check_input_group() {
-    // Check if current user is in the 'input' group
-    let output = std::process::Command::new("id").arg("-Gn").output();
-    match output {
-        Ok(o) => {
-            let groups = String::from_utf8_lossy(&o.stdout);
-            if !groups.split_whitespace().any(|g| g == "input") {
-                warn!("User is NOT in the 'input' group.");
-                warn!("  This will block evdev hotkey and dotool uinput access.");
-                warn!("  Fix: sudo usermod -aG input $USER && log out/in");
-            }
-        }
-        Err(_) => {
+    match doctor::in_input_group() {
+        Some(true) => {}
+        Some(false) => {
+            warn!("User is NOT in the 'input' group.");
+            warn!("  This will block evdev hotkey and dotool uinput access.");
+            warn!("  Fix: sudo usermod -aG input $USER && log out/in");
+            warn!("  Or run with --sudo-helper to do both through sudo.");
+        }
+        None => {
             warn!("Could not check group membership (id command failed).");
         }
     }
 
     // Check /dev/uinput access
-    match std::fs::OpenOptions::new()
-        .write(true)
-        .open("/dev/uinput")
-    {
-        Ok(_) => {}
-        Err(e) => {
-            warn!("Cannot open /dev/uinput: {e}");
-            warn!("  dotool text injection will fail.");
-            warn!("  Fix: sudo usermod -aG input $USER && log out/in");
-        }
+    if let Err(e) = inject::open_uinput() {
+        warn!("Cannot open /dev/uinput: {e}");
+        warn!("  dotool text injection will fail.");
+        warn!("  Fix: sudo usermod -aG input $USER && log out/in");
     }
 }
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
+    let options = cli::Options::parse(&args)?;
 
     // Parse --debug flag
-    if args.iter().any(|a| a == "--debug") {
+    if options.debug {
         space_tts_common::log::set_debug(true);
     }
 
-    run_client()
+    // --hotkey-helper: privileged keyboard reader spawned by --sudo-helper
+    if let Some(key_codes) = &options.hotkey_helper {
+        return hotkey::run_helper(
+            key_codes,
+            options.hotkey_device,
+            options.no_keyboard_summary,
+        );
+    }
+
+    // --test-inject: type a fixed string to check dotool/XKB/uinput, then exit
+    if let Some(text) = &options.test_inject {
+        return test_inject(text, &options);
+    }
+
+    // --doctor: check the whole setup, print a report and exit
+    if options.doctor {
+        return doctor::run(
+            options.audio_host.as_deref(),
+            options.sudo_helper,
+            options.xkb_layout.as_deref(),
+            options.models_dir.as_deref(),
+        );
+    }
+
+    // --list-languages: print the language codes --language accepts and exit
+    if options.list_languages {
+        space_tts_common::languages::print();
+        return Ok(());
+    }
+
+    // --list-audio-configs: print device capabilities and exit
+    if options.list_audio_configs {
+        return audio::print_input_configs(options.audio_host.as_deref());
+    }
+
+    run_client(&options)
+}
+
+/// The injector selected by `--inject-backend` (dotool by default), typing on
+/// its own thread so a stall can't block the main loop.
+fn create_injector(options: &cli::Options, xkb_layout: &str) -> Result<Box<dyn TextInjector>> {
+    let backend: Box<dyn TextInjector> = match options.inject_backend.as_deref() {
+        None | Some("dotool") => {
+            if !inject::graphical_session() {
+                warn!(
+                    "No graphical session (DISPLAY and WAYLAND_DISPLAY unset): dotool types with"
+                );
+                warn!(
+                    "  the XKB layout, which may not match the console keymap. On a bare console,"
+                );
+                warn!("  try --inject-backend tty.");
+            }
+            let mut injector =
+                inject::Injector::new(xkb_layout, options.sudo_helper, options.max_type_chars)?;
+            if options.unicode_input {
+                injector = injector.unicode_input();
+            }
+            match options.human_typing_ms {
+                Some(ms) => Box::new(injector.human_typing(ms)),
+                None => Box::new(injector),
+            }
+        }
+        Some("tmux") => Box::new(inject::TmuxInjector::new(options.tmux_target.clone())?),
+        Some("tty") => Box::new(inject::TtyInjector::new(options.tty.clone())?),
+        Some(other) => {
+            anyhow::bail!("Unknown inject backend '{other}' (expected dotool, tmux or tty)")
+        }
+    };
+    Ok(Box::new(inject::QueuedInjector::new(backend)?))
 }
 
-fn run_client() -> Result<()> {
+fn test_inject(text: &str, options: &cli::Options) -> Result<()> {
+    let xkb_layout = inject::xkb_layout(options.xkb_layout.as_deref());
+    info!("XKB layout: {xkb_layout}");
+    let mut injector = create_injector(options, &xkb_layout)?;
+
+    // Leave time to focus the target window
+    for remaining in (1..=3).rev() {
+        info!("Typing in {remaining}...");
+        std::thread::sleep(Duration::from_secs(1));
+    }
+    injector.type_text(text)?;
+    // Waits for dotool, which types in the background, to finish
+    drop(injector);
+    info!("Done.");
+    Ok(())
+}
+
+fn run_client(options: &cli::Options) -> Result<()> {
     info!("Space STT — Remote Speech-to-Text Terminal Injector");
-    check_input_group();
+    if options.sudo_helper {
+        // Cache sudo credentials now, while the terminal is free for a password prompt
+        let status = std::process::Command::new("sudo").arg("-v").status()?;
+        if !status.success() {
+            anyhow::bail!("sudo authentication failed (needed by --sudo-helper)");
+        }
+    } else {
+        check_input_group();
+    }
 
     // 1. Run TUI setup
-    let config = tui::run_setup()?;
+    let config = tui::run_setup(
+        options.audio_host.as_deref(),
+        &options.ssh_targets,
+        options.model.as_deref(),
+        options.language.as_deref(),
+        options.xkb_layout.as_deref(),
+        &options.models_dir_args(),
+    )?;
 
-    info!("  Backend:  Remote ({0})", config.ssh_target);
+    info!("  Backend:  Remote ({0})", config.ssh_targets.join(", "));
     info!("  Model:    {0}", config.remote_model_path);
     info!("  Device:   {}", config.device_name);
-    info!("  Hotkey:   {:?}", config.hotkey);
+    match options.hotkey_device {
+        Some(hotkey::HotkeyDevice { id, any_key: true }) => {
+            info!("  Hotkey:   any key on device {id}")
+        }
+        Some(hotkey::HotkeyDevice { id, any_key: false }) => {
+            info!("  Hotkey:   {:?} on device {id}", config.hotkey)
+        }
+        None => info!("  Hotkey:   {:?}", config.hotkey),
+    }
+    if let Some(keys) = &options.hotkey_languages {
+        for (key, session) in keys.iter() {
+            match &session.model {
+                Some(model) => info!("  Hotkey:   {key:?} → {} ({model})", session.language),
+                None => info!("  Hotkey:   {key:?} → {}", session.language),
+            }
+        }
+    }
     info!("  Language: {}", config.language);
     debug!("  XKB:      {}", config.xkb_layout);
 
-    // 2. Set up transcription thread
+    // 2. Set up hotkey on all keyboards (--once listens from the start, no toggling)
+    let is_listening = Arc::new(AtomicBool::new(options.once));
+    // Which key each press came from: --hotkey-languages keys pick the session's language
+    let (pressed_tx, pressed_rx) = crossbeam_channel::unbounded::<evdev::KeyCode>();
+    let mut keys = vec![config.hotkey];
+    if let Some(bound) = &options.hotkey_languages {
+        keys.extend(
+            bound
+                .iter()
+                .map(|(key, _)| key)
+                .filter(|&key| key != config.hotkey),
+        );
+    }
+    if !options.once {
+        if options.sudo_helper {
+            hotkey::listen_via_sudo(
+                &keys,
+                options.hotkey_device,
+                is_listening.clone(),
+                pressed_tx,
+                !options.stdin_control,
+                options.no_keyboard_summary,
+            )?;
+        } else {
+            let opened = hotkey::listen_all_keyboards(
+                &keys,
+                options.hotkey_device,
+                is_listening.clone(),
+                pressed_tx,
+                !options.stdin_control,
+            )?;
+            if !options.no_keyboard_summary {
+                hotkey::report_listening(&keys, options.hotkey_device, &opened);
+            }
+        }
+    }
+
+    // 3. Set up Ctrl+C handler
+    let quit_presses = Arc::new(AtomicU32::new(0));
+    let quit_presses_clone = quit_presses.clone();
+    ctrlc::set_handler(move || {
+        quit_presses_clone.fetch_add(1, Ordering::SeqCst);
+    })?;
+    // SIGUSR1 (`pkill -USR1 space_tts_client`) flips debug logging while running
+    // SAFETY: the handler only does an atomic operation, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGUSR1,
+            on_sigusr1 as extern "C" fn(libc::c_int) as libc::sighandler_t,
+        );
+    }
+    // --stdin-control's `m <model>`: reconnect with another model
+    let (model_tx, model_rx) = crossbeam_channel::unbounded::<String>();
+    if options.stdin_control {
+        control::spawn(is_listening.clone(), quit_presses.clone(), model_tx)?;
+    }
+
+    let shared = Shared {
+        is_listening,
+        quit_presses,
+        model_rx,
+        pressed_rx,
+        selection: Arc::new(Mutex::new(Selection {
+            model: config.remote_model_path.clone(),
+            language: config.language.clone(),
+        })),
+    };
+    if options.auto_restart {
+        return run_supervised(options, &config, &shared);
+    }
+    match run_pipeline(options, &config, &shared)? {
+        RunEnd::Quit => Ok(()),
+        RunEnd::Crashed(reason) => anyhow::bail!("{reason} (--auto-restart would restart)"),
+    }
+}
+
+/// Switch the transcription to `code`, keeping `language` and the formatter
+/// (through `language_tx`) in step. A failure keeps the current language.
+fn switch_language(
+    transcriber: &mut dyn Transcriber,
+    code: &str,
+    language: &mut String,
+    language_tx: &crossbeam_channel::Sender<String>,
+) {
+    match transcriber.set_language(code) {
+        Ok(()) => {
+            info!("[LANGUAGE] {code}");
+            *language = code.to_string();
+            let _ = language_tx.send(language.clone());
+        }
+        Err(e) => warn!("Could not switch to {code}: {e}"),
+    }
+}
+
+extern "C" fn on_sigusr1(_: libc::c_int) {
+    space_tts_common::log::toggle_debug();
+}
+
+/// Set up once in `run_client` and kept across pipeline restarts.
+struct Shared {
+    is_listening: Arc<AtomicBool>,
+    quit_presses: Arc<AtomicU32>,
+    model_rx: crossbeam_channel::Receiver<String>,
+    pressed_rx: crossbeam_channel::Receiver<evdev::KeyCode>,
+    /// Updated by the transcription thread, so a restart resumes with what was switched to.
+    selection: Arc<Mutex<Selection>>,
+}
+
+/// The model and language in use, which can change at runtime (`m <model>`,
+/// --hotkey-languages, --language-triggers).
+#[derive(Clone)]
+struct Selection {
+    model: String,
+    language: String,
+}
+
+/// How a pipeline run ended.
+enum RunEnd {
+    /// Ctrl+C, or `--once` is done: the user is finished.
+    Quit,
+    /// Capture or transcription died under the pipeline.
+    Crashed(String),
+}
+
+/// `--auto-restart`: rebuild the pipeline (capture, VAD, transcription) after a
+/// crash, or after a failed rebuild, waiting twice as long after each failure
+/// in a row. A failure to start the very first run is still fatal: that's a
+/// setup problem, not a crash.
+fn run_supervised(
+    options: &cli::Options,
+    config: &tui::SetupConfig,
+    shared: &Shared,
+) -> Result<()> {
+    let mut backoff = RESTART_BACKOFF_MIN;
+    let mut first_run = true;
+    loop {
+        let started = Instant::now();
+        let reason = match run_pipeline(options, config, shared) {
+            Ok(RunEnd::Quit) => return Ok(()),
+            Ok(RunEnd::Crashed(reason)) => reason,
+            Err(e) if first_run => return Err(e),
+            Err(e) => format!("{e:#}"),
+        };
+        first_run = false;
+        // A run that lasted was a fresh failure, not one in a row
+        if started.elapsed() >= RESTART_BACKOFF_MAX {
+            backoff = RESTART_BACKOFF_MIN;
+        }
+        warn!(
+            "Pipeline stopped: {reason}. Restarting in {}s...",
+            backoff.as_secs()
+        );
+
+        // Ctrl+C while waiting quits
+        let presses = shared.quit_presses.load(Ordering::SeqCst);
+        let restart_at = Instant::now() + backoff;
+        while Instant::now() < restart_at {
+            if shared.quit_presses.load(Ordering::SeqCst) > presses {
+                return Ok(());
+            }
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        backoff = (backoff * 2).min(RESTART_BACKOFF_MAX);
+        info!("Restarting the pipeline...");
+    }
+}
+
+/// Capture, VAD, transcription and injection until the user quits or
+/// something under them dies.
+fn run_pipeline(
+    options: &cli::Options,
+    config: &tui::SetupConfig,
+    shared: &Shared,
+) -> Result<RunEnd> {
+    let is_listening = &shared.is_listening;
+    let quit_presses = &shared.quit_presses;
+    let model_rx = shared.model_rx.clone();
+
+    // 4. Set up transcription thread
     info!("Connecting to remote server...");
 
-    let (seg_tx, seg_rx) = crossbeam_channel::bounded::<Vec<i16>>(4);
+    let (mut seg_queue, seg_rx) = queue::SegmentQueue::new(4, options.queue_policy);
+    let in_flight = seg_queue.in_flight();
     let (text_tx, text_rx) = crossbeam_channel::bounded::<String>(4);
 
-    let ssh_target = config.ssh_target.clone();
-    let remote_model_path = config.remote_model_path.clone();
-    let language = config.language.clone();
+    let ssh_targets = config.ssh_targets.clone();
+    let Selection {
+        model: remote_model_path,
+        mut language,
+    } = shared
+        .selection
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    let thread_selection = shared.selection.clone();
+    let formatter_language = language.clone();
+    let startup_model = remote_model_path.clone();
+    let language_triggers = options.language_triggers.clone();
+    // --language-triggers, --hotkey-languages: the language switched to, for the formatter
+    let (language_tx, language_rx) = crossbeam_channel::unbounded::<String>();
+    // --hotkey-languages: the session the key that started listening asks for
+    let (session_tx, session_rx) = crossbeam_channel::unbounded::<keymap::Session>();
+    let server_args = options.server_args();
+    // --once needs to hear about empty transcriptions too, to stop waiting
+    let forward_empty = options.once;
+    let chunk_mode = options.chunk_secs.is_some();
+    let normalize_spaces = options.normalize_spaces;
+    let segment_stats = options.segment_stats;
+    let server_resample = options.server_resample;
+    let reconnect_buffer = options.reconnect_buffer;
+    // --reconnect manual: set by hotkey presses, consumed by the transcriber
+    let reconnect_request =
+        (options.reconnect == remote::Reconnect::Manual).then(|| Arc::new(AtomicBool::new(false)));
+    let thread_reconnect_request = reconnect_request.clone();
+    // --context-carryover: each listening session's prompt starts afresh
+    let context_carryover = options.context_carryover;
+    // --chunk-mode: set when listening starts, so no overlap is stripped across sessions
+    let chunk_reset = chunk_mode.then(|| Arc::new(AtomicBool::new(false)));
+    let thread_chunk_reset = chunk_reset.clone();
+    // --server-resample: the capture rate, known once the stream is open
+    let (rate_tx, rate_rx) = crossbeam_channel::bounded::<u32>(1);
+    // Set on quit: the transcriber stops after its current segment, leaving queued ones
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let thread_shutdown = shutdown.clone();
+    // Why the first connection failed: without a transcriber there's nothing to run
+    let (startup_error_tx, startup_error_rx) = crossbeam_channel::bounded::<String>(1);
+
+    let mut profiler = options.profile.then(profile::Profiler::default);
+    let transcribe_stats = profiler.as_ref().map(|p| p.transcribe.clone());
 
     let transcribe_handle = std::thread::Builder::new()
         .name("transcriber".into())
         .spawn(move || {
-            let mut transcriber: Box<dyn Transcriber> =
-                match remote::RemoteTranscriber::new(&ssh_target, &remote_model_path, &language) {
-                    Ok(t) => Box::new(t),
-                    Err(e) => {
-                        info!("Failed to connect to remote: {e}");
-                        return;
-                    }
+            let rate = if server_resample {
+                match rate_rx.recv() {
+                    Ok(rate) => Some(rate),
+                    Err(_) => return, // capture failed to start
+                }
+            } else {
+                None
+            };
+            let connect = |model: &str, language: &str| {
+                let transcriber = remote::RemoteTranscriber::new(
+                    &ssh_targets,
+                    model,
+                    language,
+                    &server_args,
+                    reconnect_buffer,
+                )?;
+                let transcriber = match &thread_reconnect_request {
+                    Some(request) => transcriber.reconnect_on_request(request.clone()),
+                    None => transcriber,
                 };
+                Ok::<_, anyhow::Error>(match rate {
+                    Some(rate) => transcriber.send_rate(rate),
+                    None => transcriber,
+                })
+            };
+            let mut current_model = remote_model_path;
+            let mut transcriber: Box<dyn Transcriber> = match connect(&current_model, &language) {
+                Ok(t) => Box::new(t),
+                Err(e) => {
+                    let _ = startup_error_tx.send(format!("{e:#}"));
+                    return;
+                }
+            };
+
+            let mut previous_chunk = String::new();
+            // The listening session of the last segment taken
+            let mut current_session = 0;
+
+            // Process segments from channel; when idle, retry buffered ones
+            loop {
+                if let Ok(mut selection) = thread_selection.lock() {
+                    selection.model.clone_from(&current_model);
+                    selection.language.clone_from(&language);
+                }
+                if thread_shutdown.load(Ordering::SeqCst) {
+                    break;
+                }
+                if let Some(reset) = &thread_chunk_reset
+                    && reset.swap(false, Ordering::SeqCst)
+                {
+                    previous_chunk.clear();
+                }
+                if let Ok(model) = model_rx.try_recv() {
+                    info!("Switching to model {model}...");
+                    match connect(&model, &language) {
+                        Ok(mut t) => {
+                            // Segments buffered while the old link was down
+                            t.adopt(transcriber.take_pending());
+                            transcriber = Box::new(t);
+                            current_model = model;
+                        }
+                        Err(e) => warn!("Could not switch to model {model}: {e}"),
+                    }
+                }
+                if let Ok(session) = session_rx.try_recv() {
+                    match session.model.filter(|model| *model != current_model) {
+                        Some(model) => {
+                            info!("Switching to model {model} ({})...", session.language);
+                            match connect(&model, &session.language) {
+                                Ok(mut t) => {
+                                    t.adopt(transcriber.take_pending());
+                                    transcriber = Box::new(t);
+                                    current_model = model;
+                                    language = session.language;
+                                    let _ = language_tx.send(language.clone());
+                                }
+                                Err(e) => warn!("Could not switch to model {model}: {e}"),
+                            }
+                        }
+                        None if session.language != language => switch_language(
+                            transcriber.as_mut(),
+                            &session.language,
+                            &mut language,
+                            &language_tx,
+                        ),
+                        None => {}
+                    }
+                }
 
-            // Process segments from channel
-            for segment in seg_rx {
-                match transcriber.transcribe(&segment) {
-                    Ok(text) if !text.is_empty() => {
-                        if text_tx.send(text).is_err() {
-                            break; // main thread dropped receiver
+                let mut segment_taken = false;
+                let result = match seg_rx.recv_timeout(Duration::from_secs(1)) {
+                    Ok(queue::Queued {
+                        session, segment, ..
+                    }) => {
+                        segment_taken = true;
+                        if context_carryover && session != current_session {
+                            transcriber.reset_context();
+                        }
+                        current_session = session;
+                        if segment_stats {
+                            let stats = audio::SegmentStats::new(&segment, rate.unwrap_or(16000));
+                            debug!("Segment: {stats}");
+                        }
+                        let started = Instant::now();
+                        // A panic fails this segment, not the transcription thread
+                        let result =
+                            space_tts_common::panic::catch(|| transcriber.transcribe(&segment));
+                        if let Some(stats) = &transcribe_stats
+                            && let Ok(mut stats) = stats.lock()
+                        {
+                            stats.record(started.elapsed());
+                        }
+                        result
+                    }
+                    Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                        space_tts_common::panic::catch(|| transcriber.flush_pending())
+                    }
+                    Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+                };
+                match result {
+                    Ok(texts) => {
+                        for mut text in texts {
+                            if chunk_mode {
+                                // Windows overlap: drop the words already sent
+                                let fresh = chunk::strip_overlap(&previous_chunk, &text);
+                                previous_chunk = std::mem::replace(&mut text, fresh);
+                            }
+                            if normalize_spaces {
+                                text = format::collapse_spaces(&text);
+                            }
+                            if let Some(triggers) = &language_triggers
+                                && let Some(code) = triggers.language_for(&text)
+                            {
+                                switch_language(
+                                    transcriber.as_mut(),
+                                    code,
+                                    &mut language,
+                                    &language_tx,
+                                );
+                                continue; // the command itself isn't typed
+                            }
+                            if text.is_empty() && !forward_empty {
+                                continue; // empty transcription, skip
+                            }
+                            if text_tx.send(text).is_err() {
+                                return; // main thread dropped receiver
+                            }
                         }
                     }
-                    Ok(_) => {} // empty transcription, skip
                     Err(e) => debug!("Transcription error: {e}"),
                 }
+                if segment_taken {
+                    in_flight.done();
+                }
             }
         })?;
 
-    // 3. Start audio capture
+    // 5. Start audio capture
     let device_name = &config.device_name;
     debug!("Starting audio capture on {device_name}...");
 
     let (audio_tx, audio_rx) = crossbeam_channel::bounded::<Vec<i16>>(64);
-    let (_stream, capture_config) = audio::start_capture(&config.device, audio_tx)?;
+    let (stream, capture_config) =
+        audio::start_capture(&config.device, audio_tx, options.capture_rate)?;
 
-    // 4. Create resampler
+    // 6. Create resampler (the VAD always works at 16kHz)
     let mut resample =
         audio::create_resampler(capture_config.sample_rate, 16000, capture_config.channels)?;
+    let mut native = options.server_resample.then(|| {
+        let max_segment = options
+            .chunk_secs
+            .map_or(options.vad_config().max_segment_samples, |secs| {
+                (secs * 16000.0) as usize
+            });
+        // Room for the VAD's pre-roll and trailing silence on top of the longest segment
+        audio::NativeHistory::new(
+            capture_config.sample_rate,
+            capture_config.channels,
+            max_segment + 2 * 16000,
+            resample.delay(),
+        )
+    });
+    if let Some(native) = &native {
+        info!(
+            "  Sending {} Hz audio, resampled by the server",
+            native.rate()
+        );
+        let _ = rate_tx.send(native.rate());
+    }
+    drop(rate_tx);
 
-    // 5. Set up hotkey on all keyboards
-    let is_listening = Arc::new(AtomicBool::new(false));
-    hotkey::listen_all_keyboards(config.hotkey, is_listening.clone())?;
+    // 7. Create injector
+    let mut injector = create_injector(options, &config.xkb_layout)?;
+    let window_filter = focus::WindowFilter::new(
+        &options.inject_allow,
+        &options.inject_block,
+        !options.no_default_block,
+    );
+    let mut debouncer = inject::Debouncer::new(Duration::from_millis(options.dedup_window_ms));
+    let mut formatter = (options.auto_format || options.auto_punctuate)
+        .then(|| format::Formatter::new(&formatter_language, options.auto_punctuate));
+    // Joins held results without a formatter (--commit-on-pause)
+    let mut output_language = formatter_language;
+    if !options.inject_allow.is_empty() {
+        info!("  Inject only into: {}", options.inject_allow.join(", "));
+    }
 
-    // 6. Create injector
-    let mut injector = inject::Injector::new(&config.xkb_layout)?;
+    // Optional listening indicators: state file for status bars, Scroll Lock LED
+    let state_file = match &options.state_file {
+        Some(path) => {
+            let state_file = indicator::StateFile::new(path)?;
+            info!("  State file: {}", state_file.path().display());
+            Some(state_file)
+        }
+        None => None,
+    };
+    let mut led = if options.led_indicator {
+        hotkey::LedIndicator::new(config.hotkey)
+    } else {
+        None
+    };
 
-    // 7. Set up Ctrl+C handler
-    let shutdown = Arc::new(AtomicBool::new(false));
-    let shutdown_clone = shutdown.clone();
-    ctrlc::set_handler(move || {
-        shutdown_clone.store(true, Ordering::SeqCst);
-    })?;
+    // Presses from an earlier run (--auto-restart) are already handled
+    let mut seen_quit_presses = quit_presses.load(Ordering::SeqCst);
+    let mut quit_prompted_at: Option<Instant> = None;
 
     // 8. Main processing loop
-    info!("Ready! Press {:?} to toggle listening.", config.hotkey);
+    let once_deadline = options
+        .once
+        .then(|| Instant::now() + Duration::from_secs_f64(options.once_timeout_secs));
+    let mut once_sent = false;
+    if options.once {
+        info!("Ready! Listening for one utterance...");
+    } else {
+        info!("Ready! Press {:?} to toggle listening.", config.hotkey);
+    }
 
-    let mut voice_detector = vad::VoiceDetector::new()?;
+    // --lookback-ms: nothing to look back on while an idle-paused stream is stopped
+    let mut lookback = (options.lookback_ms > 0 && !options.idle_pause).then(|| {
+        audio::Lookback::new(
+            capture_config.sample_rate,
+            capture_config.channels,
+            options.lookback_ms,
+        )
+    });
+    let mut voice_detector = vad::VoiceDetector::new(options.vad_config())?;
+    let mut chunker = options
+        .chunk_secs
+        .map(|secs| chunk::Chunker::new((secs * 16000.0) as usize, options.vad_config().min_rms));
     let mut was_listening = false;
+    // --inject-delay-ms: results wait in text_rx until then
+    let mut inject_after = Instant::now();
+    // --commit-on-pause: results wait here; due once listening stops
+    let mut commit_buffer = options
+        .commit_on_pause
+        .map(|secs| commit::CommitBuffer::new(Duration::from_secs_f64(secs)));
+    let mut commit_due = false;
     let mut chunk_count: u64 = 0;
     let mut listening_chunks: u64 = 0;
+    let mut stream_paused = false;
+    // Capture watchdog: when the last chunk came, and whether the stall was reported
+    let mut last_chunk = Instant::now();
+    let mut stall_reported = false;
+    let mut startup_error = None;
+    let mut end = RunEnd::Quit;
+    let mut debug_logging = space_tts_common::log::is_debug();
+    // --queue-policy block: while a push waits for room, keep taking texts (the
+    // transcriber may itself be waiting for us to) and stop waiting on a quit
+    let keep_waiting = |inject_after: Instant,
+                        seen_quit_presses: u32,
+                        commit_buffer: Option<&mut commit::CommitBuffer>,
+                        debouncer: &mut inject::Debouncer,
+                        formatter: &mut Option<format::Formatter>,
+                        injector: &mut dyn TextInjector| {
+        if Instant::now() >= inject_after {
+            deliver_results(
+                &text_rx,
+                commit_buffer,
+                &window_filter,
+                debouncer,
+                formatter,
+                injector,
+            );
+        }
+        quit_presses.load(Ordering::SeqCst) == seen_quit_presses && !transcribe_handle.is_finished()
+    };
 
     loop {
-        // Check shutdown
-        if shutdown.load(Ordering::SeqCst) {
+        if let Ok(e) = startup_error_rx.try_recv() {
+            startup_error = Some(e);
             break;
         }
+        // Something under the pipeline died (--auto-restart rebuilds it)
+        if capture_config.failed.load(Ordering::SeqCst) {
+            end = RunEnd::Crashed("audio device lost".to_string());
+            break;
+        }
+        if transcribe_handle.is_finished() {
+            end = RunEnd::Crashed("transcription thread died".to_string());
+            break;
+        }
+        // SIGUSR1 flipped debug logging
+        let debug = space_tts_common::log::is_debug();
+        if debug != debug_logging {
+            debug_logging = debug;
+            info!("Debug logging {}", if debug { "on" } else { "off" });
+        }
+        if let Ok(code) = language_rx.try_recv() {
+            if formatter.is_some() {
+                formatter = Some(format::Formatter::new(&code, options.auto_punctuate));
+            }
+            output_language = code;
+        }
 
-        // Receive audio chunk (with timeout to stay responsive)
-        let chunk = match audio_rx.recv_timeout(Duration::from_millis(100)) {
-            Ok(c) => c,
-            Err(crossbeam_channel::RecvTimeoutError::Timeout) => continue,
-            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
-        };
+        // Check shutdown (with --confirm-quit, a second Ctrl+C must follow shortly)
+        let presses = quit_presses.load(Ordering::SeqCst);
+        if presses > seen_quit_presses {
+            let confirmed = !options.confirm_quit
+                || presses - seen_quit_presses >= 2
+                || quit_prompted_at.is_some_and(|at| at.elapsed() < QUIT_CONFIRM_WINDOW);
+            if confirmed {
+                break;
+            }
+            seen_quit_presses = presses;
+            quit_prompted_at = Some(Instant::now());
+            info!(
+                "Press Ctrl+C again within {}s to quit.",
+                QUIT_CONFIRM_WINDOW.as_secs()
+            );
+        }
 
-        chunk_count += 1;
+        if once_sent {
+            break; // --once: segment captured, wait for its text below
+        }
+        if let Some(deadline) = once_deadline
+            && Instant::now() >= deadline
+        {
+            warn!(
+                "No speech within {}s, giving up.",
+                options.once_timeout_secs
+            );
+            break;
+        }
 
         let listening = is_listening.load(Ordering::SeqCst);
+        // Keys are sent before they toggle, so this press is already here
+        for key in shared.pressed_rx.try_iter() {
+            if listening
+                && let Some(session) = options
+                    .hotkey_languages
+                    .as_ref()
+                    .and_then(|keys| keys.session_for(key))
+            {
+                let _ = session_tx.send(session.clone());
+            }
+        }
 
         // PTT release detection: discard incomplete segment
         if was_listening && !listening {
+            // The resampler's filter still holds the last moments of speech
+            let tail = resample.flush();
             voice_detector.reset();
+            // --chunk-mode: the last partial window still holds speech
+            if let Some(mut window) = chunker.as_mut().and_then(chunk::Chunker::finish) {
+                window.audio.extend_from_slice(&tail);
+                window.end += tail.len() as u64;
+                let waiting = || {
+                    keep_waiting(
+                        inject_after,
+                        seen_quit_presses,
+                        commit_buffer.as_mut(),
+                        &mut debouncer,
+                        &mut formatter,
+                        injector.as_mut(),
+                    )
+                };
+                seg_queue.push(outgoing(window, native.as_ref()), waiting);
+            }
+            if let Some(native) = &mut native {
+                native.clear();
+            }
+            seg_queue.new_session();
+            commit_due = commit_buffer.is_some();
             info!("[PAUSED]");
+            if let Some(state_file) = &state_file {
+                state_file.set(false);
+            }
+            if let Some(led) = &mut led {
+                led.set(false);
+            }
             debug!("  (processed {listening_chunks} audio chunks while listening)");
             listening_chunks = 0;
         }
 
         if !was_listening && listening {
             info!("[LISTENING]");
+            if let Some(reset) = &chunk_reset {
+                reset.store(true, Ordering::SeqCst);
+            }
+            inject_after = Instant::now() + Duration::from_millis(options.inject_delay_ms);
+            if let Some(state_file) = &state_file {
+                state_file.set(true);
+            }
+            if let Some(led) = &mut led {
+                led.set(true);
+            }
             listening_chunks = 0;
         }
 
+        // --reconnect manual: any hotkey press is the go-ahead to reconnect
+        if listening != was_listening
+            && let Some(request) = &reconnect_request
+        {
+            request.store(true, Ordering::SeqCst);
+        }
         was_listening = listening;
 
+        // Idle mode: stop the capture stream entirely while not listening
+        if options.idle_pause && listening == stream_paused {
+            let result = if listening {
+                stream.play().map_err(anyhow::Error::from)
+            } else {
+                stream.pause().map_err(anyhow::Error::from)
+            };
+            match result {
+                Ok(()) => {
+                    stream_paused = !listening;
+                    last_chunk = Instant::now();
+                    debug!(
+                        "  (capture stream {})",
+                        if listening { "resumed" } else { "paused" }
+                    );
+                }
+                Err(e) => warn!("Could not pause/resume capture stream: {e}"),
+            }
+        }
+
+        // --commit-on-pause: type the held results once every segment is
+        // transcribed after listening stopped, or after a long pause
+        if let Some(buffer) = &mut commit_buffer {
+            // Checked before draining: idle means the last texts are already sent
+            let released = commit_due && seg_queue.idle();
+            deliver_results(
+                &text_rx,
+                Some(&mut *buffer),
+                &window_filter,
+                &mut debouncer,
+                &mut formatter,
+                injector.as_mut(),
+            );
+            let now = Instant::now();
+            if now >= inject_after && (released || buffer.idle(now)) {
+                commit_due &= !released;
+                let texts = buffer.take();
+                if !texts.is_empty() {
+                    deliver_block(
+                        texts,
+                        block_separator(&output_language),
+                        &window_filter,
+                        &mut debouncer,
+                        &mut formatter,
+                        injector.as_mut(),
+                    );
+                }
+            }
+        }
+
+        // Receive audio chunk (with timeout to stay responsive)
+        let chunk = match audio_rx.recv_timeout(Duration::from_millis(100)) {
+            Ok(c) => c,
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                // Nothing captured (e.g. stream paused): still deliver finished transcriptions
+                if Instant::now() >= inject_after {
+                    deliver_results(
+                        &text_rx,
+                        commit_buffer.as_mut(),
+                        &window_filter,
+                        &mut debouncer,
+                        &mut formatter,
+                        injector.as_mut(),
+                    );
+                }
+                if !stream_paused && !stall_reported && last_chunk.elapsed() >= CAPTURE_STALL {
+                    stall_reported = true;
+                    warn!(
+                        "No audio from {device_name} for {}s: the device may be suspended or reconfigured.",
+                        CAPTURE_STALL.as_secs()
+                    );
+                    if options.auto_restart {
+                        end = RunEnd::Crashed("audio capture stalled".to_string());
+                        break;
+                    }
+                    warn!("  Check the device, or run with --auto-restart to rebuild the stream.");
+                }
+                continue;
+            }
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => {
+                end = RunEnd::Crashed("audio capture stopped".to_string());
+                break;
+            }
+        };
+
+        chunk_count += 1;
+        last_chunk = Instant::now();
+        if stall_reported {
+            stall_reported = false;
+            info!("Audio capture resumed.");
+        }
+
+        if let Some(profiler) = &mut profiler {
+            profiler.maybe_report();
+        }
+
         if !listening {
             // Log audio flow periodically to confirm capture works
             if chunk_count.is_multiple_of(500) {
@@ -178,13 +957,36 @@ fn run_client() -> Result<()> {
                     chunk.len()
                 );
             }
+            if let Some(lookback) = &mut lookback {
+                lookback.push(&chunk);
+            }
+            capture_config.buffer_pool.recycle(chunk);
             continue; // discard samples when not listening
         }
 
         listening_chunks += 1;
 
+        // Speech begun just before the hotkey goes in ahead of the first chunk
+        let chunk = match &mut lookback {
+            Some(lookback) if listening_chunks == 1 => {
+                let seeded = lookback.seed(&chunk);
+                capture_config.buffer_pool.recycle(chunk);
+                seeded
+            }
+            _ => chunk,
+        };
+
         // Resample to 16kHz mono
-        let resampled = resample(&chunk);
+        let started = Instant::now();
+        let resampled = resample.process(&chunk);
+        if let Some(profiler) = &mut profiler {
+            profiler.resample.record(started.elapsed());
+        }
+        if let Some(native) = &mut native {
+            native.push(&chunk);
+        }
+        let chunk_len = chunk.len();
+        capture_config.buffer_pool.recycle(chunk);
         if resampled.is_empty() {
             if listening_chunks.is_multiple_of(100) {
                 debug!("  WARNING: resampler producing empty output");
@@ -196,46 +998,172 @@ fn run_client() -> Result<()> {
         if listening_chunks == 1 {
             debug!(
                 "  Audio chunk: {} samples -> resampled to {} samples",
-                chunk.len(),
+                chunk_len,
                 resampled.len()
             );
         }
 
         // Feed to VAD
-        let segments = voice_detector.process_samples(&resampled);
+        let started = Instant::now();
+        let segments = match &mut chunker {
+            Some(chunker) => chunker.process_samples(&resampled),
+            None => voice_detector.process_samples(&resampled),
+        };
+        if let Some(profiler) = &mut profiler {
+            profiler.vad.record(started.elapsed());
+            profiler.vad_buffer_peak = profiler
+                .vad_buffer_peak
+                .max(voice_detector.buffered_samples());
+        }
 
         // Send completed segments for transcription
         for segment in segments {
-            let duration_ms = segment.len() as f64 / 16.0; // 16 samples per ms at 16kHz
+            if once_sent {
+                break; // --once: only the first segment
+            }
+            if !voice_detector.worth_transcribing(&segment.audio) {
+                continue;
+            }
+            let duration_ms = segment.audio.len() as f64 / 16.0; // 16 samples per ms at 16kHz
             debug!(
                 "[TRANSCRIBING...] segment: {} samples ({:.0}ms)",
-                segment.len(),
+                segment.audio.len(),
                 duration_ms
             );
-            if seg_tx.try_send(segment).is_err() {
-                debug!("Transcription busy, segment dropped.");
+            let waiting = || {
+                keep_waiting(
+                    inject_after,
+                    seen_quit_presses,
+                    commit_buffer.as_mut(),
+                    &mut debouncer,
+                    &mut formatter,
+                    injector.as_mut(),
+                )
+            };
+            if seg_queue.push(outgoing(segment, native.as_ref()), waiting) && options.once {
+                once_sent = true;
             }
         }
 
         // Check for transcription results (non-blocking)
-        while let Ok(text) = text_rx.try_recv() {
-            info!("[RESULT] \"{}\"", text);
-            if let Err(e) = injector.type_text(&text) {
-                warn!("Injection error: {e}");
+        if Instant::now() >= inject_after {
+            deliver_results(
+                &text_rx,
+                commit_buffer.as_mut(),
+                &window_filter,
+                &mut debouncer,
+                &mut formatter,
+                injector.as_mut(),
+            );
+        }
+    }
+
+    // --once: wait for the single transcription, inject it, and quit
+    if once_sent && let Some(deadline) = once_deadline {
+        match text_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(text) if text.is_empty() => info!("[EMPTY] nothing recognized"),
+            Ok(text) => deliver_result(
+                text,
+                &window_filter,
+                &mut debouncer,
+                &mut formatter,
+                injector.as_mut(),
+            ),
+            Err(_) => warn!(
+                "No transcription within {}s, giving up.",
+                options.once_timeout_secs
+            ),
+        }
+    }
+
+    // Quitting mid-utterance: transcribe and inject what was said so far
+    if !once_sent && is_listening.load(Ordering::SeqCst) {
+        let tail = resample.flush();
+        let last = match &mut chunker {
+            Some(chunker) => chunker.finish(),
+            None => voice_detector.flush(),
+        }
+        .map(|mut segment| {
+            segment.audio.extend_from_slice(&tail);
+            segment.end += tail.len() as u64;
+            segment
+        });
+        if let Some(segment) = last.filter(|s| voice_detector.worth_transcribing(&s.audio)) {
+            let deadline = Instant::now() + FLUSH_TIMEOUT;
+            if seg_queue.push_until(outgoing(segment, native.as_ref()), deadline) {
+                // Once the transcriber has taken it, earlier texts are all in text_rx
+                while !seg_queue.is_empty() && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(20));
+                }
+                deliver_results(
+                    &text_rx,
+                    commit_buffer.as_mut(),
+                    &window_filter,
+                    &mut debouncer,
+                    &mut formatter,
+                    injector.as_mut(),
+                );
+                match text_rx.recv_deadline(deadline) {
+                    Ok(text) => match &mut commit_buffer {
+                        // --commit-on-pause: typed with the rest of the held results below
+                        Some(buffer) => buffer.push(text, Instant::now()),
+                        None => {
+                            deliver_result(
+                                text,
+                                &window_filter,
+                                &mut debouncer,
+                                &mut formatter,
+                                injector.as_mut(),
+                            );
+                        }
+                    },
+                    Err(_) => debug!("Last segment not transcribed in time, dropped."),
+                }
             }
         }
     }
 
+    // --commit-on-pause: what is still held is typed before quitting
+    if let Some(buffer) = &mut commit_buffer {
+        deliver_results(
+            &text_rx,
+            Some(&mut *buffer),
+            &window_filter,
+            &mut debouncer,
+            &mut formatter,
+            injector.as_mut(),
+        );
+        let texts = buffer.take();
+        if !texts.is_empty() {
+            deliver_block(
+                texts,
+                block_separator(&output_language),
+                &window_filter,
+                &mut debouncer,
+                &mut formatter,
+                injector.as_mut(),
+            );
+        }
+    }
+
     // 9. Graceful shutdown
     info!("Shutting down...");
 
-    // Drop stream (stops capture) and senders (signal threads to exit)
-    drop(_stream);
-    drop(seg_tx);
+    if let Some(profiler) = &mut profiler {
+        profiler.report();
+    }
+    seg_queue.report();
+
+    // Drop stream (stops capture) and channels (signal threads to exit)
+    shutdown.store(true, Ordering::SeqCst);
+    drop(stream);
+    drop(seg_queue);
+    // Nobody reads results anymore: a pending send must fail, not block
+    drop(text_rx);
 
-    // Wait for transcription thread to finish (segments channel is closed)
-    // The thread will exit once seg_rx is drained/disconnected.
-    // Use a 10-second timeout via a helper thread.
+    // Wait for transcription thread to finish: it stops after the segment in
+    // flight, if any. The 10-second timeout (via a helper thread) is only a
+    // safety net against a remote that never answers.
     let (done_tx, done_rx) = crossbeam_channel::bounded::<()>(1);
     std::thread::spawn(move || {
         let _ = transcribe_handle.join();
@@ -245,9 +1173,131 @@ fn run_client() -> Result<()> {
         warn!("Transcription thread did not stop within 10s, exiting anyway.");
     }
 
-    // Drop injector (kills dotool)
+    // Drop injector (waits for dotool to finish typing, then stops it)
     drop(injector);
 
     info!("Shutdown complete.");
-    Ok(())
+    if let Some(e) = startup_error {
+        anyhow::bail!(
+            "Could not start transcription with model {} on {}: {e}\n\
+             The server may not support this model: run again and pick another model or target.",
+            startup_model,
+            config.ssh_targets.join(", ")
+        );
+    }
+    Ok(end)
+}
+
+/// The audio to send for a 16 kHz segment: itself, or with --server-resample
+/// the native-rate audio it was cut from.
+fn outgoing(segment: vad::Segment, native: Option<&audio::NativeHistory>) -> Vec<i16> {
+    match native {
+        Some(history) => history.matching(segment.start(), segment.end),
+        None => segment.audio,
+    }
+}
+
+/// Inject every finished transcription, or hold it in `commit` (`--commit-on-pause`).
+fn deliver_results(
+    text_rx: &crossbeam_channel::Receiver<String>,
+    mut commit: Option<&mut commit::CommitBuffer>,
+    window_filter: &focus::WindowFilter,
+    debouncer: &mut inject::Debouncer,
+    formatter: &mut Option<format::Formatter>,
+    injector: &mut dyn TextInjector,
+) {
+    while let Ok(text) = text_rx.try_recv() {
+        match &mut commit {
+            Some(buffer) => {
+                info!("[HELD] \"{text}\"");
+                buffer.push(text, Instant::now());
+            }
+            None => deliver_result(text, window_filter, debouncer, formatter, injector),
+        }
+    }
+}
+
+/// Inject one transcription, honouring the focused-window filter and debounce.
+fn deliver_result(
+    text: String,
+    window_filter: &focus::WindowFilter,
+    debouncer: &mut inject::Debouncer,
+    formatter: &mut Option<format::Formatter>,
+    injector: &mut dyn TextInjector,
+) {
+    deliver_block(
+        vec![text],
+        " ",
+        window_filter,
+        debouncer,
+        formatter,
+        injector,
+    );
+}
+
+/// What joins held results without `--auto-format`: nothing in languages
+/// written without spaces.
+fn block_separator(language: &str) -> &'static str {
+    if languages::is_unspaced(language) {
+        ""
+    } else {
+        " "
+    }
+}
+
+/// Inject transcriptions as one block: joined by `separator`, or by the formatter.
+fn deliver_block(
+    texts: Vec<String>,
+    separator: &str,
+    window_filter: &focus::WindowFilter,
+    debouncer: &mut inject::Debouncer,
+    formatter: &mut Option<format::Formatter>,
+    injector: &mut dyn TextInjector,
+) {
+    let text = texts.join(separator);
+    info!("[RESULT] \"{}\"", text);
+    if window_filter.is_active() {
+        let focused = focus::focused_window();
+        if window_filter.blocks(focused.as_ref()) {
+            if let Some(w) = &focused {
+                warn!(
+                    "Not injecting into a blocked window: {} — {}",
+                    w.class, w.title
+                );
+            }
+            return;
+        }
+        if !window_filter.permits(focused.as_ref()) {
+            match focused {
+                Some(w) => info!(
+                    "[SKIPPED] focused window not allowed: {} — {}",
+                    w.class, w.title
+                ),
+                None => info!("[SKIPPED] focused window unknown, not injecting"),
+            }
+            return;
+        }
+    }
+    if !debouncer.should_inject(&text, Instant::now()) {
+        info!("[SKIPPED] duplicate of the previous result");
+        return;
+    }
+    let result = match formatter {
+        Some(formatter) => {
+            let texts: Vec<String> = texts
+                .iter()
+                .map(|text| inject::sanitize(text))
+                .filter(|text| !text.is_empty())
+                .collect();
+            if texts.is_empty() {
+                return;
+            }
+            let (separator, text) = formatter.format_all(&texts);
+            injector.type_after(separator, &text)
+        }
+        None => injector.type_text(&text),
+    };
+    if let Err(e) = result {
+        warn!("Injection error: {e}");
+    }
 }
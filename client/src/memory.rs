@@ -0,0 +1,100 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// Default location of the model → language memory:
+/// `$XDG_CONFIG_HOME/space_tts/languages`, else `~/.config/space_tts/languages`.
+pub fn default_path() -> PathBuf {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|d| !d.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".config")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    config_dir.join("space_tts/languages")
+}
+
+/// The language last used with each model, so the TUI can pre-select it.
+/// Stored as `model<TAB>language` lines, like `--list-models` output.
+pub struct LanguageMemory {
+    path: PathBuf,
+    languages: BTreeMap<String, String>,
+}
+
+impl LanguageMemory {
+    /// Load the memory (empty if the file doesn't exist or can't be read).
+    pub fn load(path: PathBuf) -> Self {
+        let languages = std::fs::read_to_string(&path)
+            .map(|contents| parse(&contents))
+            .unwrap_or_default();
+        Self { path, languages }
+    }
+
+    pub fn get(&self, model: &str) -> Option<&str> {
+        self.languages.get(model).map(String::as_str)
+    }
+
+    /// Remember `language` for `model` and save the file.
+    pub fn set(&mut self, model: &str, language: &str) -> Result<()> {
+        if self.get(model) == Some(language) {
+            return Ok(());
+        }
+        self.languages
+            .insert(model.to_string(), language.to_string());
+        if let Some(parent) = self.path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent).with_context(|| {
+                format!("Failed to create config directory: {}", parent.display())
+            })?;
+        }
+        let contents: String = self
+            .languages
+            .iter()
+            .map(|(model, language)| format!("{model}\t{language}\n"))
+            .collect();
+        std::fs::write(&self.path, contents)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+fn parse(contents: &str) -> BTreeMap<String, String> {
+    contents
+        .lines()
+        .filter_map(|line| {
+            let (model, language) = line.split_once('\t')?;
+            let (model, language) = (model.trim(), language.trim());
+            (!model.is_empty() && !language.is_empty())
+                .then(|| (model.to_string(), language.to_string()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_skips_malformed_lines() {
+        let languages = parse("ggml-small.bin\tfr\nbroken line\n\tde\nggml-base.en.bin\ten\n");
+        assert_eq!(languages.len(), 2);
+        assert_eq!(languages["ggml-small.bin"], "fr");
+        assert_eq!(languages["ggml-base.en.bin"], "en");
+    }
+
+    #[test]
+    fn remembers_across_loads() {
+        let dir = std::env::temp_dir().join("space-stt-test-languages");
+        let _ = std::fs::remove_dir_all(&dir);
+        let path = dir.join("languages");
+
+        let mut memory = LanguageMemory::load(path.clone());
+        assert_eq!(memory.get("ggml-small.bin"), None);
+        memory.set("ggml-small.bin", "fr").unwrap();
+        memory.set("ggml-medium.bin", "de").unwrap();
+
+        let memory = LanguageMemory::load(path);
+        assert_eq!(memory.get("ggml-small.bin"), Some("fr"));
+        assert_eq!(memory.get("ggml-medium.bin"), Some("de"));
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use space_tts_common::info;
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Accumulated timings for one pipeline stage.
+#[derive(Default)]
+pub struct StageStats {
+    count: u64,
+    total: Duration,
+    min: Duration,
+    max: Duration,
+}
+
+impl StageStats {
+    pub fn record(&mut self, elapsed: Duration) {
+        if self.count == 0 || elapsed < self.min {
+            self.min = elapsed;
+        }
+        if elapsed > self.max {
+            self.max = elapsed;
+        }
+        self.total += elapsed;
+        self.count += 1;
+    }
+
+    pub fn avg(&self) -> Duration {
+        if self.count == 0 {
+            Duration::ZERO
+        } else {
+            // In nanoseconds: the count doesn't fit Duration's u32 divisor on long runs
+            Duration::from_nanos((self.total.as_nanos() / u128::from(self.count)) as u64)
+        }
+    }
+}
+
+impl fmt::Display for StageStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.count == 0 {
+            return write!(f, "no samples");
+        }
+        write!(
+            f,
+            "n={} min={:.2}ms avg={:.2}ms max={:.2}ms",
+            self.count,
+            self.min.as_secs_f64() * 1000.0,
+            self.avg().as_secs_f64() * 1000.0,
+            self.max.as_secs_f64() * 1000.0
+        )
+    }
+}
+
+/// Per-stage timings for `--profile`, printed periodically from the main loop.
+/// Transcription runs on its own thread, so its stats are shared behind a mutex.
+pub struct Profiler {
+    pub resample: StageStats,
+    pub vad: StageStats,
+    pub transcribe: std::sync::Arc<std::sync::Mutex<StageStats>>,
+    /// Longest VAD buffer (16 kHz samples) since the last report.
+    pub vad_buffer_peak: usize,
+    last_report: Instant,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self {
+            resample: StageStats::default(),
+            vad: StageStats::default(),
+            transcribe: Default::default(),
+            vad_buffer_peak: 0,
+            last_report: Instant::now(),
+        }
+    }
+}
+
+impl Profiler {
+    /// Print a summary if the report interval has elapsed.
+    pub fn maybe_report(&mut self) {
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+    }
+
+    pub fn report(&mut self) {
+        self.last_report = Instant::now();
+        info!("[PROFILE] resample:   {}", self.resample);
+        info!("[PROFILE] vad:        {}", self.vad);
+        info!(
+            "[PROFILE] vad buffer: peak {:.1}s",
+            self.vad_buffer_peak as f64 / 16000.0
+        );
+        self.vad_buffer_peak = 0;
+        if let Ok(transcribe) = self.transcribe.lock() {
+            info!("[PROFILE] transcribe: {}", *transcribe);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stage_stats_min_avg_max() {
+        let mut stats = StageStats::default();
+        stats.record(Duration::from_millis(4));
+        stats.record(Duration::from_millis(2));
+        stats.record(Duration::from_millis(6));
+        assert_eq!(stats.min, Duration::from_millis(2));
+        assert_eq!(stats.max, Duration::from_millis(6));
+        assert_eq!(stats.avg(), Duration::from_millis(4));
+    }
+
+    #[test]
+    fn stage_stats_avg_over_a_long_run() {
+        let stats = StageStats {
+            count: 1 << 32,
+            total: Duration::from_secs(1 << 33),
+            ..StageStats::default()
+        };
+        assert_eq!(stats.avg(), Duration::from_secs(2));
+    }
+
+    #[test]
+    fn stage_stats_empty() {
+        let stats = StageStats::default();
+        assert_eq!(stats.avg(), Duration::ZERO);
+        assert_eq!(stats.to_string(), "no samples");
+    }
+}
@@ -0,0 +1,298 @@
+use anyhow::{Result, bail};
+use crossbeam_channel::{Receiver, SendTimeoutError, Sender, TrySendError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use space_tts_common::{debug, info};
+
+/// How often a `Block` push stops waiting for room to let the caller act.
+const BLOCK_POLL: Duration = Duration::from_millis(50);
+
+/// `--queue-policy`: what happens to a finished segment when the transcription
+/// queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QueuePolicy {
+    /// Drop the new segment (default).
+    #[default]
+    DropNewest,
+    /// Drop the oldest queued segment to make room: keeps the latest speech.
+    DropOldest,
+    /// Wait for room; capture backs up meanwhile.
+    Block,
+}
+
+impl QueuePolicy {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "drop-newest" => Ok(Self::DropNewest),
+            "drop-oldest" => Ok(Self::DropOldest),
+            "block" => Ok(Self::Block),
+            other => {
+                bail!("Unknown queue policy '{other}' (expected drop-newest, drop-oldest or block)")
+            }
+        }
+    }
+}
+
+/// Segments queued and not yet fully transcribed, shared with the
+/// transcription thread, which calls `done` once a segment's texts are sent.
+#[derive(Clone, Default)]
+pub struct InFlight(Arc<AtomicUsize>);
+
+impl InFlight {
+    pub fn done(&self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    fn add(&self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// A segment for the transcription thread.
+pub struct Queued {
+    pub number: u64,
+    /// The listening session it was cut in: a new one starts a fresh context,
+    /// in order with the segments whatever was dropped in between.
+    pub session: u64,
+    pub segment: Vec<i16>,
+}
+
+/// The bounded queue of segments waiting for the transcription thread. Segments
+/// are numbered in the order they are pushed, so drops can be reported.
+pub struct SegmentQueue {
+    tx: Sender<Queued>,
+    /// Our own end, to take the oldest segment out (`DropOldest` only: it keeps
+    /// the channel connected after the transcription thread is gone).
+    oldest_rx: Option<Receiver<Queued>>,
+    policy: QueuePolicy,
+    next: u64,
+    session: u64,
+    dropped: Vec<u64>,
+    in_flight: InFlight,
+}
+
+impl SegmentQueue {
+    /// A queue of `capacity` segments and the receiver for the transcription thread.
+    pub fn new(capacity: usize, policy: QueuePolicy) -> (Self, Receiver<Queued>) {
+        let (tx, rx) = crossbeam_channel::bounded(capacity);
+        let queue = Self {
+            tx,
+            oldest_rx: (policy == QueuePolicy::DropOldest).then(|| rx.clone()),
+            policy,
+            next: 1,
+            session: 0,
+            dropped: Vec::new(),
+            in_flight: InFlight::default(),
+        };
+        (queue, rx)
+    }
+
+    /// Segments pushed from now on belong to a new listening session.
+    pub fn new_session(&mut self) {
+        self.session += 1;
+    }
+
+    /// Queue `segment` according to the policy. False if it was dropped.
+    /// While `Block` waits for room, `waiting` is called every `BLOCK_POLL`:
+    /// returning false gives up on the segment.
+    pub fn push(&mut self, segment: Vec<i16>, mut waiting: impl FnMut() -> bool) -> bool {
+        let mut item = self.item(segment);
+        let number = item.number;
+        // Counted before it can reach the transcriber, which may be done with it at once
+        self.in_flight.add();
+        loop {
+            let full = match self.policy {
+                QueuePolicy::Block => match self.tx.send_timeout(item, BLOCK_POLL) {
+                    Ok(()) => return true,
+                    Err(SendTimeoutError::Disconnected(_)) => return self.sent(false),
+                    Err(SendTimeoutError::Timeout(back)) if waiting() => {
+                        item = back;
+                        continue;
+                    }
+                    Err(SendTimeoutError::Timeout(_)) => {
+                        debug!("Stopped waiting for the transcription, segment #{number} dropped.");
+                        self.dropped.push(number);
+                        return self.sent(false);
+                    }
+                },
+                _ => match self.tx.try_send(item) {
+                    Ok(()) => return true,
+                    Err(TrySendError::Disconnected(_)) => return self.sent(false),
+                    Err(TrySendError::Full(full)) => full,
+                },
+            };
+            if self.policy == QueuePolicy::DropNewest {
+                debug!("Transcription busy, segment #{number} dropped.");
+                self.dropped.push(number);
+                return self.sent(false);
+            }
+            // DropOldest (the transcriber may have emptied a slot meanwhile: just retry)
+            if let Some(rx) = &self.oldest_rx
+                && let Ok(Queued { number: oldest, .. }) = rx.try_recv()
+            {
+                debug!("Transcription busy, oldest segment #{oldest} dropped.");
+                self.dropped.push(oldest);
+                self.in_flight.done();
+            }
+            item = full;
+        }
+    }
+
+    /// Queue `segment` whatever the policy, waiting for room until `deadline`.
+    pub fn push_until(&mut self, segment: Vec<i16>, deadline: Instant) -> bool {
+        let item = self.item(segment);
+        let number = item.number;
+        self.in_flight.add();
+        match self.tx.send_deadline(item, deadline) {
+            Ok(()) => true,
+            Err(SendTimeoutError::Timeout(_)) => {
+                self.dropped.push(number);
+                self.sent(false)
+            }
+            Err(SendTimeoutError::Disconnected(_)) => self.sent(false),
+        }
+    }
+
+    /// Number the next segment.
+    fn item(&mut self, segment: Vec<i16>) -> Queued {
+        let number = self.next;
+        self.next += 1;
+        Queued {
+            number,
+            session: self.session,
+            segment,
+        }
+    }
+
+    /// Uncount a segment that never made it into the queue.
+    fn sent(&self, queued: bool) -> bool {
+        if !queued {
+            self.in_flight.done();
+        }
+        queued
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tx.is_empty()
+    }
+
+    /// The count of segments still to transcribe, for the transcription thread.
+    pub fn in_flight(&self) -> InFlight {
+        self.in_flight.clone()
+    }
+
+    /// Whether every queued segment is transcribed and its texts sent.
+    pub fn idle(&self) -> bool {
+        self.in_flight.count() == 0
+    }
+
+    /// Numbers of the segments dropped so far, in order.
+    pub fn dropped(&self) -> &[u64] {
+        &self.dropped
+    }
+
+    /// Log the dropped segments, if any (at shutdown).
+    pub fn report(&self) {
+        let dropped = self.dropped();
+        if dropped.is_empty() {
+            return;
+        }
+        let numbers: Vec<String> = dropped.iter().map(|n| format!("#{n}")).collect();
+        info!(
+            "{} of {} segments dropped, queue full: {}",
+            dropped.len(),
+            self.next - 1,
+            numbers.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn queued(rx: &Receiver<Queued>) -> Vec<u64> {
+        rx.try_iter().map(|q| q.number).collect()
+    }
+
+    #[test]
+    fn drop_newest_keeps_the_queue() {
+        let (mut queue, rx) = SegmentQueue::new(2, QueuePolicy::DropNewest);
+        assert!(queue.push(vec![1], || true));
+        assert!(queue.push(vec![2], || true));
+        assert!(!queue.push(vec![3], || true));
+        assert_eq!(queue.dropped(), [3]);
+        assert_eq!(queued(&rx), [1, 2]);
+    }
+
+    #[test]
+    fn drop_oldest_keeps_the_latest_speech() {
+        let (mut queue, rx) = SegmentQueue::new(2, QueuePolicy::DropOldest);
+        for i in 1..=4 {
+            assert!(queue.push(vec![i], || true));
+        }
+        assert_eq!(queue.dropped(), [1, 2]);
+        assert_eq!(queued(&rx), [3, 4]);
+    }
+
+    #[test]
+    fn in_flight_counts_segments_until_done() {
+        let (mut queue, rx) = SegmentQueue::new(1, QueuePolicy::DropOldest);
+        let in_flight = queue.in_flight();
+        assert!(queue.push(vec![1], || true));
+        assert!(queue.push(vec![2], || true));
+        assert_eq!(in_flight.count(), 1, "The dropped segment isn't counted");
+
+        let (mut full, _rx) = SegmentQueue::new(1, QueuePolicy::DropNewest);
+        assert!(full.push(vec![1], || true));
+        assert!(!full.push(vec![2], || true));
+        assert_eq!(full.in_flight().count(), 1);
+
+        assert_eq!(queued(&rx), [2]);
+        assert!(!queue.idle(), "Taken but not transcribed yet");
+        in_flight.done();
+        assert!(queue.idle());
+    }
+
+    #[test]
+    fn block_keeps_the_caller_going_until_it_gives_up() {
+        let (mut queue, rx) = SegmentQueue::new(1, QueuePolicy::Block);
+        assert!(queue.push(vec![1], || true));
+        let mut polls = 0;
+        assert!(!queue.push(vec![2], || {
+            polls += 1;
+            polls < 3
+        }));
+        assert_eq!(polls, 3);
+        assert_eq!(queue.dropped(), [2]);
+        assert_eq!(queue.in_flight().count(), 1);
+        assert_eq!(queued(&rx), [1]);
+    }
+
+    #[test]
+    fn segments_carry_their_session() {
+        let (mut queue, rx) = SegmentQueue::new(4, QueuePolicy::Block);
+        assert!(queue.push(vec![1], || true));
+        queue.new_session();
+        assert!(queue.push(vec![2], || true));
+        assert!(queue.push_until(vec![3], Instant::now()));
+        let sessions: Vec<(u64, u64)> = rx.try_iter().map(|q| (q.number, q.session)).collect();
+        assert_eq!(sessions, [(1, 0), (2, 1), (3, 1)]);
+    }
+
+    #[test]
+    fn parses_policies() {
+        assert_eq!(
+            QueuePolicy::parse("drop-oldest").unwrap(),
+            QueuePolicy::DropOldest
+        );
+        assert_eq!(QueuePolicy::parse("block").unwrap(), QueuePolicy::Block);
+        assert!(QueuePolicy::parse("drop").is_err());
+    }
+}
@@ -1,25 +1,334 @@
 use anyhow::{Result, bail};
-use std::io::{BufReader, BufWriter};
+use std::collections::VecDeque;
+use std::io::{BufReader, BufWriter, Read};
 use std::process::{Child, Command, Stdio};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
-use space_tts_common::info;
-use space_tts_common::protocol::{ClientMsg, ServerMsg, read_server_msg, write_client_msg};
+use space_tts_common::log::Level;
+use space_tts_common::models;
+use space_tts_common::protocol::{
+    ClientMsg, PROTOCOL_VERSION, ServerMsg, WHISPER_RATE, read_server_msg, write_client_msg,
+};
+use space_tts_common::transcriber::Transcriber;
+use space_tts_common::{debug, info, warn};
 
-pub trait Transcriber: Send {
-    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<String>;
+/// How long to wait between reconnection attempts.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+/// SSH keepalives: a link that stops answering (suspended laptop, dropped
+/// Wi-Fi) ends the session after 15s instead of leaving a read hanging forever.
+/// A long transcription doesn't trip it: sshd answers them on its own.
+const SSH_KEEPALIVE: [&str; 4] = ["-o", "ServerAliveInterval=5", "-o", "ServerAliveCountMax=3"];
+
+/// `--reconnect`: what to do when the remote link drops.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reconnect {
+    /// Retry every few seconds (default).
+    Auto,
+    /// Wait for the user to press the push-to-talk key (e.g. to avoid
+    /// re-triggering an expensive GPU spin-up behind their back).
+    Manual,
 }
 
+impl Reconnect {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "manual" => Ok(Self::Manual),
+            _ => bail!("Invalid value for --reconnect: '{s}' (expected auto or manual)"),
+        }
+    }
+}
+
+/// Transcribes over SSH, reconnecting when the link drops. Segments produced
+/// while disconnected are buffered (up to `max_pending`, oldest dropped first)
+/// and sent in order once the server is back.
+///
+/// With several SSH targets, the first one that answers Ready is used, and a
+/// dropped link fails over to the next one (wrapping around).
 pub struct RemoteTranscriber {
+    ssh_targets: Vec<String>,
+    /// Index in `ssh_targets` of the current (or last) connection.
+    active: usize,
+    remote_model_path: String,
+    language: String,
+    server_args: Vec<String>,
+    conn: Option<Connection>,
+    /// Segments not yet transcribed, in order.
+    pending: VecDeque<Pending>,
+    max_pending: usize,
+    /// Set by `reset_context`: the next segment starts a fresh context.
+    reset_next: bool,
+    next_seq: u32,
+    retry_at: Instant,
+    /// With `Reconnect::Manual`: set when the user asks for a reconnection.
+    reconnect_request: Option<Arc<AtomicBool>>,
+    /// Sample rate of the segments (other than 16 kHz with `--server-resample`).
+    rate: u32,
+}
+
+impl RemoteTranscriber {
+    pub fn new(
+        ssh_targets: &[String],
+        remote_model_path: &str,
+        language: &str,
+        server_args: &[String],
+        max_pending: usize,
+    ) -> Result<Self> {
+        let (active, conn) =
+            Connection::open_first(ssh_targets, 0, remote_model_path, language, server_args)?;
+        Ok(Self {
+            ssh_targets: ssh_targets.to_vec(),
+            active,
+            remote_model_path: remote_model_path.to_string(),
+            language: language.to_string(),
+            server_args: server_args.to_vec(),
+            conn: Some(conn),
+            pending: VecDeque::new(),
+            max_pending: max_pending.max(1),
+            reset_next: false,
+            next_seq: 1,
+            retry_at: Instant::now(),
+            reconnect_request: None,
+            rate: WHISPER_RATE,
+        })
+    }
+
+    /// `--server-resample`: segments are mono audio at `rate`, resampled by the server.
+    pub fn send_rate(mut self, rate: u32) -> Self {
+        self.rate = rate;
+        self
+    }
+
+    /// `--reconnect manual`: after a drop, only reconnect once `request` is set
+    /// (by the push-to-talk key), instead of retrying on a timer.
+    pub fn reconnect_on_request(mut self, request: Arc<AtomicBool>) -> Self {
+        self.reconnect_request = Some(request);
+        self
+    }
+
+    /// Whether a reconnection may be attempted now.
+    fn may_reconnect(&self) -> bool {
+        match &self.reconnect_request {
+            Some(request) => request.swap(false, Ordering::SeqCst),
+            None => Instant::now() >= self.retry_at,
+        }
+    }
+
+    /// The live connection, reconnecting if it dropped and the retry delay has passed.
+    fn connection(&mut self) -> Option<&mut Connection> {
+        if self.conn.is_none() && self.may_reconnect() {
+            // Fail over: the target that just dropped is tried last
+            match Connection::open_first(
+                &self.ssh_targets,
+                self.active + 1,
+                &self.remote_model_path,
+                &self.language,
+                &self.server_args,
+            ) {
+                Ok((active, conn)) => {
+                    info!(
+                        "Reconnected to {} ({} buffered segments).",
+                        self.ssh_targets[active],
+                        self.pending.len()
+                    );
+                    self.active = active;
+                    self.conn = Some(conn);
+                }
+                Err(e) => {
+                    if self.reconnect_request.is_some() {
+                        warn!("Reconnect failed: {e}");
+                        warn!("  Press the push-to-talk key again to retry.");
+                    } else {
+                        debug!("Reconnect failed: {e}");
+                    }
+                    self.retry_at = Instant::now() + RECONNECT_INTERVAL;
+                }
+            }
+        }
+        self.conn.as_mut()
+    }
+
+    /// Drop the broken link; `connection()` brings it back later.
+    fn lost_connection(&mut self, e: &anyhow::Error) {
+        self.conn = None;
+        self.retry_at = Instant::now() + RECONNECT_INTERVAL;
+        match &self.reconnect_request {
+            Some(request) => {
+                // A press from before the drop must not count
+                request.store(false, Ordering::SeqCst);
+                warn!("Lost connection to remote ({e}), remote is DOWN.");
+                warn!("  Segments are buffered. Press the push-to-talk key to reconnect.");
+            }
+            None => warn!("Lost connection to remote ({e}), buffering segments until it is back."),
+        }
+    }
+
+    /// Take over the segments a replaced transcriber had not transcribed,
+    /// to be sent before any new one.
+    pub fn adopt(&mut self, segments: Vec<Vec<i16>>) {
+        if !segments.is_empty() {
+            info!(
+                "{} buffered segments carried over to the new connection.",
+                segments.len()
+            );
+        }
+        for segment in segments {
+            self.enqueue(segment);
+        }
+    }
+
+    fn enqueue(&mut self, segment: Vec<i16>) {
+        let mut reset_context = std::mem::take(&mut self.reset_next);
+        if self.pending.len() >= self.max_pending
+            && let Some(dropped) = self.pending.pop_front()
+        {
+            warn!(
+                "Remote unreachable and segment buffer full, dropped segment #{}.",
+                dropped.seq
+            );
+            // The fresh start it carried now comes before the next oldest
+            match self.pending.front_mut() {
+                Some(next) => next.reset_context |= dropped.reset_context,
+                None => reset_context |= dropped.reset_context,
+            }
+        }
+        // 0 is reserved for replies not tied to a segment
+        let seq = self.next_seq;
+        self.next_seq = self.next_seq.checked_add(1).unwrap_or(1);
+        self.pending.push_back(Pending {
+            seq,
+            segment,
+            reset_context,
+        });
+    }
+}
+
+/// A segment waiting for its transcription.
+struct Pending {
+    seq: u32,
+    segment: Vec<i16>,
+    /// Forget the earlier text before transcribing it (a new listening session).
+    reset_context: bool,
+}
+
+impl Transcriber for RemoteTranscriber {
+    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>> {
+        self.enqueue(audio_i16.to_vec());
+        self.flush_pending()
+    }
+
+    fn flush_pending(&mut self) -> Result<Vec<String>> {
+        let mut texts = Vec::new();
+        while !self.pending.is_empty() {
+            if self.connection().is_none() {
+                break;
+            }
+            let (Some(conn), Some(pending)) = (self.conn.as_mut(), self.pending.front_mut()) else {
+                break;
+            };
+            // Sent right before it: the previous session's buffered segments keep their context
+            if std::mem::take(&mut pending.reset_context)
+                && let Err(e) = write_client_msg(&mut conn.writer, &ClientMsg::ResetContext)
+            {
+                debug!("Could not reset the remote context: {e}");
+            }
+            match conn.transcribe(pending.seq, self.rate, &pending.segment) {
+                Ok(reply) => {
+                    self.pending.pop_front();
+                    match reply {
+                        Ok(text) => texts.push(text),
+                        Err(e) => warn!("Remote transcription error: {e}"),
+                    }
+                }
+                Err(e) => {
+                    self.lost_connection(&e);
+                    break;
+                }
+            }
+        }
+        Ok(texts)
+    }
+
+    fn reset_context(&mut self) {
+        // Buffered segments go first: the reset is sent along with the next one
+        self.reset_next = true;
+    }
+
+    fn set_language(&mut self, language: &str) -> Result<()> {
+        // Also checked here: a reconnection would start a server that refuses it
+        if models::is_english_only(&self.remote_model_path) && language != "en" {
+            bail!("English-only model, can't switch to '{language}'");
+        }
+        if let Some(conn) = &mut self.conn {
+            match conn.set_language(language) {
+                Ok(reply) => reply?, // refused: the server keeps its language, so do we
+                Err(e) => self.lost_connection(&e),
+            }
+        }
+        // Kept for reconnections, which start a server with `--language`
+        self.language = language.to_string();
+        Ok(())
+    }
+
+    fn take_pending(&mut self) -> Vec<Vec<i16>> {
+        self.pending
+            .drain(..)
+            .map(|pending| pending.segment)
+            .collect()
+    }
+}
+
+/// One SSH session running `space_tts_server`.
+struct Connection {
     child: Child,
     writer: BufWriter<std::process::ChildStdin>,
     reader: BufReader<std::process::ChildStdout>,
 }
 
-impl RemoteTranscriber {
-    pub fn new(ssh_target: &str, remote_model_path: &str, language: &str) -> Result<Self> {
+impl Connection {
+    /// Open the first of `ssh_targets` that answers, trying them in order from
+    /// `first` and wrapping around. Returns the index of the target used.
+    fn open_first(
+        ssh_targets: &[String],
+        first: usize,
+        remote_model_path: &str,
+        language: &str,
+        server_args: &[String],
+    ) -> Result<(usize, Self)> {
+        let mut errors = Vec::new();
+        for i in (0..ssh_targets.len()).map(|k| (first + k) % ssh_targets.len()) {
+            match Self::open(&ssh_targets[i], remote_model_path, language, server_args) {
+                Ok(conn) => {
+                    if ssh_targets.len() > 1 {
+                        info!("Using remote {}.", ssh_targets[i]);
+                    }
+                    return Ok((i, conn));
+                }
+                Err(e) if ssh_targets.len() == 1 => return Err(e),
+                Err(e) => {
+                    debug!("{} unavailable: {e}", ssh_targets[i]);
+                    errors.push(format!("{}: {e}", ssh_targets[i]));
+                }
+            }
+        }
+        if errors.is_empty() {
+            bail!("No SSH target given");
+        }
+        bail!("No SSH target reachable ({})", errors.join("; "))
+    }
+
+    fn open(
+        ssh_target: &str,
+        remote_model_path: &str,
+        language: &str,
+        server_args: &[String],
+    ) -> Result<Self> {
         info!("Connecting to {ssh_target}...");
 
         let mut child = Command::new("ssh")
+            .args(SSH_KEEPALIVE)
             .args([
                 "-o",
                 "BatchMode=yes",
@@ -30,6 +339,7 @@ impl RemoteTranscriber {
                 "--language",
                 language,
             ])
+            .args(server_args)
             .stdin(Stdio::piped())
             .stdout(Stdio::piped())
             .stderr(Stdio::inherit()) // remote logs visible locally
@@ -45,16 +355,28 @@ impl RemoteTranscriber {
             .take()
             .ok_or_else(|| anyhow::anyhow!("Failed to open SSH stdout"))?;
 
-        let writer = BufWriter::new(stdin);
+        let mut writer = BufWriter::new(stdin);
         let mut reader = BufReader::new(stdout);
 
         // Wait for Ready message from server
-        let msg = read_server_msg(&mut reader)
+        let msg = read_reply(&mut reader)
             .map_err(|e| anyhow::anyhow!("Server did not send Ready: {e}"))?;
 
         match msg {
-            ServerMsg::Ready => info!("Remote server ready."),
-            ServerMsg::Error(e) => bail!("Remote server error during startup: {e}"),
+            ServerMsg::Ready { version } if version == PROTOCOL_VERSION => {
+                let hello = ClientMsg::Hello {
+                    version: PROTOCOL_VERSION,
+                };
+                write_client_msg(&mut writer, &hello)?;
+                info!("Remote server ready.")
+            }
+            ServerMsg::Ready { version } => bail!(
+                "Remote server speaks protocol v{version}, this client v{PROTOCOL_VERSION}: \
+                 install the same space_tts version on both sides"
+            ),
+            ServerMsg::Error { message, .. } => {
+                bail!("Remote server error during startup: {message}")
+            }
             other => bail!("Unexpected message from server: {other:?}"),
         }
 
@@ -64,21 +386,57 @@ impl RemoteTranscriber {
             reader,
         })
     }
-}
 
-impl Transcriber for RemoteTranscriber {
-    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<String> {
-        write_client_msg(&mut self.writer, &ClientMsg::AudioSegment(audio_i16.to_vec()))?;
+    /// Outer error: the link is broken (or out of sync). Inner error: the server
+    /// failed this segment.
+    fn transcribe(&mut self, seq: u32, rate: u32, audio_i16: &[i16]) -> Result<Result<String>> {
+        let msg = ClientMsg::AudioSegment {
+            seq,
+            rate,
+            samples: audio_i16.to_vec(),
+        };
+        write_client_msg(&mut self.writer, &msg)?;
+
+        let (reply_seq, reply) = match read_reply(&mut self.reader)? {
+            ServerMsg::Text { seq, text } => (seq, Ok(text)),
+            ServerMsg::Error { seq, message } => (seq, Err(anyhow::anyhow!("{message}"))),
+            other => bail!("Unexpected message during transcription: {other:?}"),
+        };
+        if reply_seq != seq {
+            bail!("Reply for segment #{reply_seq} while waiting for #{seq}");
+        }
+        Ok(reply)
+    }
+
+    /// Ask the server to switch language. Err for a broken link, Ok(Err) if refused.
+    fn set_language(&mut self, language: &str) -> Result<Result<()>> {
+        let msg = ClientMsg::SetLanguage {
+            language: language.to_string(),
+        };
+        write_client_msg(&mut self.writer, &msg)?;
+        match read_reply(&mut self.reader)? {
+            ServerMsg::Text { seq: 0, .. } => Ok(Ok(())),
+            ServerMsg::Error { seq: 0, message } => Ok(Err(anyhow::anyhow!("{message}"))),
+            other => bail!("Unexpected reply to a language switch: {other:?}"),
+        }
+    }
+}
 
-        match read_server_msg(&mut self.reader)? {
-            ServerMsg::Text(text) => Ok(text),
-            ServerMsg::Error(e) => bail!("Remote transcription error: {e}"),
-            ServerMsg::Ready => bail!("Unexpected Ready message during transcription"),
+/// Read the next non-log message, printing forwarded server logs on the way.
+fn read_reply(reader: &mut impl Read) -> Result<ServerMsg> {
+    loop {
+        match read_server_msg(reader)? {
+            ServerMsg::Log { level, text } => match level {
+                Level::Debug => debug!("[remote] {text}"),
+                Level::Info => info!("[remote] {text}"),
+                Level::Warn => warn!("[remote] {text}"),
+            },
+            msg => return Ok(msg),
         }
     }
 }
 
-impl Drop for RemoteTranscriber {
+impl Drop for Connection {
     fn drop(&mut self) {
         // Close stdin to signal EOF to the server
         drop(self.child.stdin.take());
@@ -93,11 +451,34 @@ impl Drop for RemoteTranscriber {
     }
 }
 
+/// `list_remote_models` on the first of `ssh_targets` that answers, with that target.
+pub fn list_first_remote_models<'a>(
+    ssh_targets: &'a [String],
+    extra_args: &[String],
+) -> Result<(&'a str, Vec<(String, String)>)> {
+    let mut last_error = None;
+    for target in ssh_targets {
+        match list_remote_models(target, extra_args) {
+            Ok(models) => return Ok((target, models)),
+            Err(e) => {
+                warn!("{target}: {e}");
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No SSH target given")))
+}
+
 /// Discover models available on a remote machine.
-/// Executes `ssh <target> space_tts_server --list-models` and parses `name\tpath` lines.
-pub fn list_remote_models(ssh_target: &str) -> Result<Vec<(String, String)>> {
+/// Executes `ssh <target> space_tts_server --list-models` (plus `extra_args`,
+/// e.g. `--models-dir`) and parses `name\tpath` lines.
+pub fn list_remote_models(
+    ssh_target: &str,
+    extra_args: &[String],
+) -> Result<Vec<(String, String)>> {
     let output = Command::new("ssh")
         .args(["-o", "BatchMode=yes", ssh_target, "space_tts_server", "--list-models"])
+        .args(extra_args)
         .output()
         .map_err(|e| anyhow::anyhow!("Failed to run SSH: {e}"))?;
 
@@ -123,3 +504,93 @@ pub fn list_remote_models(ssh_target: &str) -> Result<Vec<(String, String)>> {
 
     Ok(models)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use space_tts_common::protocol::write_server_msg;
+
+    /// Disconnected, with `--reconnect manual` so it never tries SSH by itself.
+    fn offline(max_pending: usize) -> RemoteTranscriber {
+        RemoteTranscriber {
+            ssh_targets: Vec::new(),
+            active: 0,
+            remote_model_path: "ggml-small.bin".to_string(),
+            language: "fr".to_string(),
+            server_args: Vec::new(),
+            conn: None,
+            pending: VecDeque::new(),
+            max_pending,
+            reset_next: false,
+            next_seq: 1,
+            retry_at: Instant::now(),
+            reconnect_request: Some(Arc::new(AtomicBool::new(false))),
+            rate: WHISPER_RATE,
+        }
+    }
+
+    /// A connection to a stand-in server that answers with `replies`.
+    fn replaying(replies: &[ServerMsg]) -> Connection {
+        let path =
+            std::env::temp_dir().join(format!("space-stt-test-replies-{}", std::process::id()));
+        let mut encoded = Vec::new();
+        for reply in replies {
+            write_server_msg(&mut encoded, reply).unwrap();
+        }
+        std::fs::write(&path, encoded).unwrap();
+        let mut child = Command::new("sh")
+            .args(["-c", "cat \"$0\"; cat >/dev/null"])
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let writer = BufWriter::new(child.stdin.take().unwrap());
+        let reader = BufReader::new(child.stdout.take().unwrap());
+        Connection {
+            child,
+            writer,
+            reader,
+        }
+    }
+
+    #[test]
+    fn full_buffer_drops_the_oldest_and_replays_the_rest_in_order() {
+        let mut remote = offline(3);
+        for i in 1..=5 {
+            assert!(remote.transcribe(&[i; 160]).unwrap().is_empty());
+        }
+        let buffered: Vec<u32> = remote.pending.iter().map(|p| p.seq).collect();
+        assert_eq!(buffered, [3, 4, 5], "The two oldest segments are dropped");
+
+        remote.conn = Some(replaying(&[
+            ServerMsg::Text {
+                seq: 3,
+                text: "three".to_string(),
+            },
+            ServerMsg::Text {
+                seq: 4,
+                text: "four".to_string(),
+            },
+            ServerMsg::Text {
+                seq: 5,
+                text: "five".to_string(),
+            },
+        ]));
+        assert_eq!(remote.flush_pending().unwrap(), ["three", "four", "five"]);
+        assert!(remote.pending.is_empty());
+    }
+
+    #[test]
+    fn a_new_connection_takes_over_the_buffered_segments() {
+        let mut old = offline(3);
+        old.transcribe(&[1; 160]).unwrap();
+        old.transcribe(&[2; 160]).unwrap();
+        let mut new = offline(3);
+        new.adopt(old.take_pending());
+        new.transcribe(&[3; 160]).unwrap();
+        let segments: Vec<i16> = new.pending.iter().map(|p| p.segment[0]).collect();
+        assert_eq!(segments, [1, 2, 3]);
+        assert!(old.pending.is_empty());
+    }
+}
@@ -0,0 +1,80 @@
+use anyhow::{Result, bail};
+
+use space_tts_common::languages;
+
+/// `--language-triggers`: phrases that, said on their own, switch the
+/// transcription language instead of being typed.
+#[derive(Clone)]
+pub struct LanguageTriggers {
+    /// (normalized phrase, language code)
+    phrases: Vec<(String, String)>,
+}
+
+impl LanguageTriggers {
+    /// Parse `fr=switch to French|passe en français,en=switch to English`:
+    /// comma-separated languages, each with `|`-separated phrases.
+    pub fn parse(spec: &str) -> Result<Self> {
+        let mut phrases = Vec::new();
+        for entry in crate::cli::split_list(spec) {
+            let Some((code, alternatives)) = entry.split_once('=') else {
+                bail!("Invalid --language-triggers entry '{entry}' (expected code=phrase)");
+            };
+            let code = languages::validate(code)?;
+            for phrase in alternatives.split('|').map(normalize) {
+                if !phrase.is_empty() {
+                    phrases.push((phrase, code.clone()));
+                }
+            }
+        }
+        if phrases.is_empty() {
+            bail!("--language-triggers needs at least one phrase");
+        }
+        Ok(Self { phrases })
+    }
+
+    /// The language `text` asks for, if it is one of the phrases (ignoring
+    /// case and punctuation). A phrase inside a longer sentence doesn't count.
+    pub fn language_for(&self, text: &str) -> Option<&str> {
+        let text = normalize(text);
+        self.phrases
+            .iter()
+            .find(|(phrase, _)| *phrase == text)
+            .map(|(_, code)| code.as_str())
+    }
+}
+
+/// Lowercase words only: whisper adds capitals and punctuation at will.
+fn normalize(text: &str) -> String {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_whole_phrases_only() {
+        let triggers =
+            LanguageTriggers::parse("fr=switch to French|passe en français, en=switch to English")
+                .unwrap();
+        assert_eq!(triggers.language_for(" Switch to French."), Some("fr"));
+        assert_eq!(triggers.language_for("Passe en Français !"), Some("fr"));
+        assert_eq!(triggers.language_for("switch to english"), Some("en"));
+        assert_eq!(
+            triggers.language_for("I could switch to French later."),
+            None
+        );
+        assert_eq!(triggers.language_for(""), None);
+    }
+
+    #[test]
+    fn rejects_bad_specs() {
+        assert!(LanguageTriggers::parse("xx=switch to nothing").is_err());
+        assert!(LanguageTriggers::parse("switch to French").is_err());
+        assert!(LanguageTriggers::parse("fr=").is_err());
+    }
+}
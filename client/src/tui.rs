@@ -5,13 +5,30 @@ use evdev::KeyCode as EvdevKeyCode;
 use ratatui::Frame;
 use ratatui::style::{Modifier, Style};
 use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph};
+use space_tts_common::{debug, warn};
 use std::time::Duration;
 
+use crate::audio;
+use crate::cli;
 use crate::inject;
+use crate::memory;
 use crate::remote;
 
+/// Languages offered in the TUI: (label, whisper code).
+const LANGUAGES: &[(&str, &str)] = &[
+    ("English", "en"),
+    ("Français", "fr"),
+    ("Deutsch", "de"),
+    ("Español", "es"),
+    ("Italiano", "it"),
+    ("Português", "pt"),
+    ("日本語", "ja"),
+    ("中文", "zh"),
+];
+
 pub struct SetupConfig {
-    pub ssh_target: String,
+    /// In order of preference; the first reachable one is used.
+    pub ssh_targets: Vec<String>,
     pub remote_model_path: String,
     pub device: cpal::Device,
     pub device_name: String,
@@ -20,77 +37,194 @@ pub struct SetupConfig {
     pub xkb_layout: String,
 }
 
-pub fn run_setup() -> Result<SetupConfig> {
-    // Auto-detect default audio input device (routes through PipeWire on modern Linux)
-    let host = cpal::default_host();
-    let device = host
-        .default_input_device()
-        .ok_or_else(|| anyhow::anyhow!("No default audio input device found."))?;
-    let device_name = device
-        .description()
-        .map(|d: cpal::DeviceDescription| d.name().to_string())
-        .unwrap_or_else(|_| "Default".into());
+/// `audio_host` forces a cpal host by name (e.g. "jack"); otherwise the user
+/// picks one when several are available. Non-empty `ssh_targets` skip the SSH
+/// screen (which also takes a comma-separated list). `model` pre-selects the
+/// remote model (falling back to the picker if the server doesn't have it).
+/// `language` (an already validated code, possibly one the menu doesn't offer)
+/// skips the language screen. `xkb_layout` overrides the detected keyboard
+/// layout (see `inject::xkb_layout`). `list_args` are added to the remote
+/// `--list-models` (e.g. `--models-dir`). A summary of the choices ends the wizard; going
+/// back from it starts over from the first screen.
+pub fn run_setup(
+    audio_host: Option<&str>,
+    ssh_targets: &[String],
+    model: Option<&str>,
+    language: Option<&str>,
+    xkb_layout: Option<&str>,
+    list_args: &[String],
+) -> Result<SetupConfig> {
+    loop {
+        let config = setup_screens(
+            audio_host,
+            ssh_targets,
+            model,
+            language,
+            xkb_layout,
+            list_args,
+        )?;
+        let mut terminal = ratatui::init();
+        let confirmed = summary_screen(&mut terminal, &config);
+        ratatui::restore();
+        if confirmed? {
+            return Ok(config);
+        }
+    }
+}
+
+/// One pass through the selection screens (see `run_setup`).
+fn setup_screens(
+    audio_host: Option<&str>,
+    ssh_targets: &[String],
+    model: Option<&str>,
+    language: Option<&str>,
+    xkb_layout: Option<&str>,
+    list_args: &[String],
+) -> Result<SetupConfig> {
+    // Resolve a forced host before the TUI takes the terminal, so fallback warnings show
+    let forced_host = audio_host.map(|name| audio::host(Some(name)));
 
     let mut terminal = ratatui::init();
 
-    // Screen 1: SSH target input
-    let ssh_target = match text_input_screen(&mut terminal, "SSH Target", "user@host") {
-        Ok(t) => t,
-        Err(e) => {
-            ratatui::restore();
-            return Err(e);
+    // Screen 0: Audio host (only when there is a choice, e.g. ALSA vs JACK)
+    let hosts = cpal::available_hosts();
+    let host = match forced_host {
+        Some(host) => host,
+        None if hosts.len() > 1 => {
+            let labels: Vec<String> = hosts.iter().map(|id| id.name().to_string()).collect();
+            match select_screen(&mut terminal, "Select Audio Host", &labels, 0) {
+                Ok(idx) => audio::host(Some(hosts[idx].name())),
+                Err(e) => {
+                    ratatui::restore();
+                    return Err(e);
+                }
+            }
         }
+        None => cpal::default_host(),
     };
 
-    // Screen 2: Discover remote models (temporarily restore terminal for SSH output)
+    // Auto-detect default audio input device (routes through PipeWire on modern Linux)
+    let Some(device) = host.default_input_device() else {
+        ratatui::restore();
+        bail!("No default audio input device found.");
+    };
+    let default_id = device.id().ok();
+    let mut devices = list_input_devices(&host, default_id.as_ref());
+
+    // Screen 1: SSH target input (several, comma-separated, for failover)
+    let ssh_targets = if ssh_targets.is_empty() {
+        match text_input_screen(&mut terminal, "SSH Target", "user@host[,user@backup]") {
+            Ok(t) => cli::split_list(&t),
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
+        }
+    } else {
+        ssh_targets.to_vec()
+    };
+    if ssh_targets.is_empty() {
+        ratatui::restore();
+        bail!("No SSH target given.");
+    }
+
+    // Screen 2: Discover remote models on the first reachable target
+    // (temporarily restore terminal for SSH output)
     ratatui::restore();
-    let models = remote::list_remote_models(&ssh_target)?;
+    let (ssh_target, models) = remote::list_first_remote_models(&ssh_targets, list_args)?;
     if models.is_empty() {
         bail!("No Whisper models found on remote machine {ssh_target}.");
     }
+    let preselected = model.and_then(|m| {
+        let idx = space_tts_common::models::find_model(&models, m);
+        if idx.is_none() {
+            warn!("Model '{m}' not found on {ssh_target}, pick one instead.");
+        }
+        idx
+    });
     terminal = ratatui::init();
 
     let model_labels: Vec<String> = models.iter().map(|(name, _)| name.clone()).collect();
-    let model_idx = match select_screen(&mut terminal, "Select Remote Model", &model_labels) {
-        Ok(idx) => idx,
-        Err(e) => {
-            ratatui::restore();
-            return Err(e);
-        }
+    let model_idx = match preselected {
+        Some(idx) => idx,
+        None => match select_screen(&mut terminal, "Select Remote Model", &model_labels, 0) {
+            Ok(idx) => idx,
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
+        },
     };
-    let remote_model_path = models[model_idx].1.clone();
-
-    // Screen 3: Language selection
-    let language_choices = vec![
-        "English".to_string(),
-        "Français".to_string(),
-        "Deutsch".to_string(),
-        "Español".to_string(),
-        "Italiano".to_string(),
-        "Português".to_string(),
-        "日本語".to_string(),
-        "中文".to_string(),
-    ];
-    let language_idx = match select_screen(&mut terminal, "Select Language", &language_choices) {
-        Ok(idx) => idx,
-        Err(e) => {
-            ratatui::restore();
-            return Err(e);
+    // Other targets have their own models directory: name the file, not our path
+    let remote_model_path = if ssh_targets.len() > 1 {
+        std::path::Path::new(&models[model_idx].1)
+            .file_name()
+            .map_or_else(
+                || models[model_idx].1.clone(),
+                |f| f.to_string_lossy().into_owned(),
+            )
+    } else {
+        models[model_idx].1.clone()
+    };
+
+    // Screen 3: Language selection, starting on the language last used with this
+    // model, else the one its name says it was fine-tuned for
+    let model_name = &models[model_idx].0;
+    let mut language_memory = memory::LanguageMemory::load(memory::default_path());
+    let remembered = language_memory
+        .get(model_name)
+        .or_else(|| space_tts_common::models::model_language(&remote_model_path))
+        .and_then(|code| LANGUAGES.iter().position(|&(_, c)| c == code))
+        .unwrap_or(0);
+    let language_choices: Vec<String> = LANGUAGES
+        .iter()
+        .map(|(label, _)| label.to_string())
+        .collect();
+    // English-only models (".en") ignore the language: skip the screen
+    let language = if space_tts_common::models::is_english_only(&remote_model_path) {
+        "en"
+    } else if let Some(language) = language {
+        language
+    } else {
+        match select_screen(
+            &mut terminal,
+            "Select Language",
+            &language_choices,
+            remembered,
+        ) {
+            Ok(idx) => LANGUAGES[idx].1,
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
         }
     };
-    let language = match language_idx {
-        0 => "en",
-        1 => "fr",
-        2 => "de",
-        3 => "es",
-        4 => "it",
-        5 => "pt",
-        6 => "ja",
-        7 => "zh",
-        _ => "en",
+    if let Err(e) = language_memory.set(model_name, language) {
+        debug!("Could not remember the language: {e}");
+    }
+
+    // Screen 4: Audio input device (only when there is a choice, e.g. monitor sources)
+    let (device, device_name) = if devices.len() > 1 {
+        let labels: Vec<String> = devices.iter().map(|(label, _)| label.clone()).collect();
+        match select_screen(&mut terminal, "Select Audio Input", &labels, 0) {
+            Ok(idx) => {
+                let (label, device) = devices.swap_remove(idx);
+                (device, label)
+            }
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
+        }
+    } else {
+        let device_name = device
+            .description()
+            .map(|d: cpal::DeviceDescription| d.name().to_string())
+            .unwrap_or_else(|_| "Default".into());
+        (device, device_name)
     };
 
-    // Screen 4: Push-to-Talk Key selection
+    // Screen 5: Push-to-Talk Key selection
     let hotkey_choices = vec![
         "F2".to_string(),
         "F3".to_string(),
@@ -102,14 +236,14 @@ pub fn run_setup() -> Result<SetupConfig> {
         "ScrollLock".to_string(),
         "Pause".to_string(),
     ];
-    let hotkey_idx = match select_screen(&mut terminal, "Select Push-to-Talk Key", &hotkey_choices)
-    {
-        Ok(idx) => idx,
-        Err(e) => {
-            ratatui::restore();
-            return Err(e);
-        }
-    };
+    let hotkey_idx =
+        match select_screen(&mut terminal, "Select Push-to-Talk Key", &hotkey_choices, 0) {
+            Ok(idx) => idx,
+            Err(e) => {
+                ratatui::restore();
+                return Err(e);
+            }
+        };
 
     ratatui::restore();
 
@@ -127,16 +261,64 @@ pub fn run_setup() -> Result<SetupConfig> {
     };
 
     Ok(SetupConfig {
-        ssh_target,
+        ssh_targets,
         remote_model_path,
         device,
         device_name,
         hotkey,
         language: language.to_string(),
-        xkb_layout: inject::detect_xkb_layout(),
+        xkb_layout: inject::xkb_layout(xkb_layout),
     })
 }
 
+/// Enumerate input devices, default first. Monitor/loopback sources (system audio
+/// on PipeWire/PulseAudio) are labelled "(monitor)" to tell them apart from mics.
+fn list_input_devices(
+    host: &cpal::Host,
+    default_id: Option<&cpal::DeviceId>,
+) -> Vec<(String, cpal::Device)> {
+    let Ok(devices) = host.input_devices() else {
+        return Vec::new();
+    };
+
+    let mut labelled: Vec<(bool, String, cpal::Device)> = devices
+        .map(|device| {
+            let is_default = default_id.is_some() && device.id().ok().as_ref() == default_id;
+            let mut label = match device.description() {
+                Ok(desc) => {
+                    let mut label = desc.name().to_string();
+                    if is_monitor(&desc) {
+                        label.push_str(" (monitor)");
+                    }
+                    label
+                }
+                Err(_) => "Unknown".to_string(),
+            };
+            if is_default {
+                label.push_str(" (default)");
+            }
+            (is_default, label, device)
+        })
+        .collect();
+
+    // Stable sort keeps host order otherwise
+    labelled.sort_by_key(|(is_default, _, _)| !is_default);
+    labelled
+        .into_iter()
+        .map(|(_, label, device)| (label, device))
+        .collect()
+}
+
+fn is_monitor(desc: &cpal::DeviceDescription) -> bool {
+    use cpal::{DeviceType, InterfaceType};
+
+    let name = desc.name().to_lowercase();
+    desc.device_type() == DeviceType::Virtual
+        || desc.interface_type() == InterfaceType::Virtual
+        || name.contains("monitor")
+        || name.contains("loopback")
+}
+
 fn text_input_screen(
     terminal: &mut ratatui::DefaultTerminal,
     title: &str,
@@ -191,26 +373,72 @@ fn text_input_screen(
     }
 }
 
+/// Show the choices made. True to start (Enter), false to go back (Esc).
+fn summary_screen(terminal: &mut ratatui::DefaultTerminal, config: &SetupConfig) -> Result<bool> {
+    let summary = [
+        format!("Backend:  Remote ({})", config.ssh_targets.join(", ")),
+        format!("Model:    {}", config.remote_model_path),
+        format!("Device:   {}", config.device_name),
+        format!("Language: {}", config.language),
+        format!("Hotkey:   {:?}", config.hotkey),
+        format!("XKB:      {}", config.xkb_layout),
+    ]
+    .join("\n");
+    let title = " Summary (Enter=start, Esc=back, q=quit) ";
+
+    loop {
+        terminal.draw(|frame: &mut Frame| {
+            let paragraph = Paragraph::new(summary.as_str())
+                .block(Block::default().borders(Borders::ALL).title(title));
+            frame.render_widget(paragraph, frame.area());
+        })?;
+
+        if event::poll(Duration::from_millis(100))?
+            && let Event::Key(key) = event::read()?
+            && key.kind == KeyEventKind::Press
+        {
+            match key.code {
+                KeyCode::Enter => return Ok(true),
+                KeyCode::Esc => return Ok(false),
+                KeyCode::Char('q') => bail!("Setup cancelled by user."),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Let the user pick one of `items`, starting on `initial`.
 fn select_screen(
     terminal: &mut ratatui::DefaultTerminal,
     title: &str,
     items: &[String],
+    initial: usize,
 ) -> Result<usize> {
     let mut state = ListState::default();
-    state.select(Some(0));
+    state.select(Some(initial.min(items.len().saturating_sub(1))));
+    // "/" starts type-to-filter (vim-style), so j/k/g/G stay free for navigation
+    let mut filter = String::new();
+    let mut filtering = false;
+    let mut page: u16 = 10;
 
     loop {
-        let title = title.to_string();
-        let list_items: Vec<ListItem> = items.iter().map(|s| ListItem::new(s.as_str())).collect();
+        let visible = filter_items(items, &filter);
+        let hint = if filtering {
+            format!("/{filter}_ Enter, Esc=clear")
+        } else {
+            "↑↓ jk PgUp/PgDn g/G, /=filter, Enter, q=quit".to_string()
+        };
+        let title = format!(" {title} ({hint}) ");
+        let list_items: Vec<ListItem> = visible
+            .iter()
+            .map(|&i| ListItem::new(items[i].as_str()))
+            .collect();
 
         terminal.draw(|frame: &mut Frame| {
             let area = frame.area();
+            page = area.height.saturating_sub(2).max(1); // minus borders
             let list = List::new(list_items)
-                .block(
-                    Block::default()
-                        .borders(Borders::ALL)
-                        .title(format!(" {title} (↑↓ Enter, q=quit) ")),
-                )
+                .block(Block::default().borders(Borders::ALL).title(title))
                 .highlight_style(Style::default().add_modifier(Modifier::REVERSED))
                 .highlight_symbol("▸ ");
             frame.render_stateful_widget(list, area, &mut state);
@@ -223,11 +451,33 @@ fn select_screen(
             match key.code {
                 KeyCode::Up => state.select_previous(),
                 KeyCode::Down => state.select_next(),
+                KeyCode::PageUp => state.scroll_up_by(page),
+                KeyCode::PageDown => state.scroll_down_by(page),
+                KeyCode::Home => state.select_first(),
+                KeyCode::End => state.select_last(),
                 KeyCode::Enter => {
-                    if let Some(idx) = state.selected() {
+                    if let Some(&idx) = state.selected().and_then(|i| visible.get(i)) {
                         return Ok(idx);
                     }
                 }
+                KeyCode::Backspace if filtering => {
+                    filter.pop();
+                    state.select(Some(0));
+                }
+                KeyCode::Esc if filtering => {
+                    filter.clear();
+                    filtering = false;
+                    state.select(Some(0));
+                }
+                KeyCode::Char(c) if filtering => {
+                    filter.push(c);
+                    state.select(Some(0));
+                }
+                KeyCode::Char('/') => filtering = true,
+                KeyCode::Char('j') => state.select_next(),
+                KeyCode::Char('k') => state.select_previous(),
+                KeyCode::Char('g') => state.select_first(),
+                KeyCode::Char('G') => state.select_last(),
                 KeyCode::Char('q') | KeyCode::Esc => {
                     bail!("Setup cancelled by user.");
                 }
@@ -236,3 +486,37 @@ fn select_screen(
         }
     }
 }
+
+/// Indices of the items containing `filter` (case-insensitive), all of them when empty.
+fn filter_items(items: &[String], filter: &str) -> Vec<usize> {
+    let filter = filter.to_lowercase();
+    items
+        .iter()
+        .enumerate()
+        .filter(|(_, item)| item.to_lowercase().contains(&filter))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_matches_substrings_ignoring_case() {
+        let items = [
+            "Large-v3".to_string(),
+            "small".to_string(),
+            "medium.en".to_string(),
+        ];
+        assert_eq!(filter_items(&items, "LARGE"), [0]);
+        assert_eq!(filter_items(&items, "m"), [1, 2]);
+        assert!(filter_items(&items, "tiny").is_empty());
+    }
+
+    #[test]
+    fn cleared_filter_shows_everything() {
+        let items = ["Large-v3".to_string(), "small".to_string()];
+        assert_eq!(filter_items(&items, ""), [0, 1]);
+    }
+}
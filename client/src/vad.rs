@@ -2,36 +2,171 @@ use anyhow::Result;
 use std::collections::VecDeque;
 use webrtc_vad::{SampleRate, Vad, VadMode};
 
+use space_tts_common::{debug, warn};
+
 const FRAME_SIZE: usize = 160; // 10ms at 16kHz
 const SILENCE_THRESHOLD: u32 = 50; // 500ms of silence = end of speech
 const PRE_ROLL_FRAMES: usize = 5; // 50ms pre-roll buffer
+const CALIBRATION_FRAMES: usize = 100; // 1s for the first noise-floor estimate
+const RECALIBRATION_FRAMES: usize = 1000; // then re-estimated every 10s
+const NOISE_PERCENTILE: usize = 10; // the quietest frames of a window are the room, not the speaker
+
+/// webrtc-vad aggressiveness, from most to least willing to call a frame voice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggressiveness {
+    Quality,
+    LowBitrate,
+    Aggressive,
+    VeryAggressive,
+}
+
+impl Aggressiveness {
+    /// Parse `--vad-mode`; "auto" yields `None` (picked from the noise floor).
+    pub fn parse(s: &str) -> Result<Option<Self>> {
+        match s {
+            "auto" => Ok(None),
+            "quality" => Ok(Some(Self::Quality)),
+            "low-bitrate" => Ok(Some(Self::LowBitrate)),
+            "aggressive" => Ok(Some(Self::Aggressive)),
+            "very-aggressive" => Ok(Some(Self::VeryAggressive)),
+            other => anyhow::bail!(
+                "Unknown VAD mode '{other}' (expected auto, quality, low-bitrate, aggressive or very-aggressive)"
+            ),
+        }
+    }
+
+    /// Quiet rooms can afford a permissive VAD (fewer clipped words); noisy
+    /// ones need a strict one, or the noise keeps segments open.
+    fn for_noise_floor(rms: f64) -> Self {
+        if rms < 100.0 {
+            Self::LowBitrate
+        } else if rms < 1000.0 {
+            Self::Aggressive
+        } else {
+            Self::VeryAggressive
+        }
+    }
+
+    fn vad(self) -> Vad {
+        let mode = match self {
+            Self::Quality => VadMode::Quality,
+            Self::LowBitrate => VadMode::LowBitrate,
+            Self::Aggressive => VadMode::Aggressive,
+            Self::VeryAggressive => VadMode::VeryAggressive,
+        };
+        Vad::new_with_rate_and_mode(SampleRate::Rate16kHz, mode)
+    }
+}
+
+pub struct VadConfig {
+    /// Force-split a segment once it reaches this many samples (Whisper's window is 30s).
+    pub max_segment_samples: usize,
+    /// Safety net above `max_segment_samples`: a buffer this long however it got
+    /// there means the VAD is stuck, and it is flushed as a segment.
+    pub max_buffer_samples: usize,
+    /// Samples from the end of a force-split segment repeated at the start of the next,
+    /// so a word cut at the boundary keeps its context.
+    pub overlap_samples: usize,
+    /// Frames whose RMS (in i16 units) is below this are treated as silence without
+    /// asking the VAD, which otherwise flags low-level background noise as voice.
+    pub min_rms: f64,
+    /// Segments shorter than this are not worth a transcription round-trip.
+    pub min_segment_samples: usize,
+    /// Segments whose spectral flatness is above this (0 = pure tone, 1 = impulse)
+    /// are keyboard or mouse clicks rather than speech and are dropped; `None` keeps them.
+    pub max_flatness: Option<f64>,
+    /// Fixed aggressiveness, or `None` to pick it from the noise floor (the
+    /// first second, then every 10s).
+    pub mode: Option<Aggressiveness>,
+}
+
+impl Default for VadConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_samples: 30 * 16000, // 30s
+            max_buffer_samples: 120 * 16000, // 2min
+            overlap_samples: 200 * 16,       // 200ms
+            min_rms: 100.0,                  // ~-50 dBFS, well below quiet speech
+            min_segment_samples: 100 * 16,   // 100ms
+            max_flatness: None,
+            mode: None,
+        }
+    }
+}
+
+/// A cut segment and where it ends in the 16 kHz stream fed since the last
+/// reset. Segments are contiguous audio, so it starts `audio.len()` earlier.
+#[derive(Debug, PartialEq)]
+pub struct Segment {
+    pub audio: Vec<i16>,
+    pub end: u64,
+}
+
+impl Segment {
+    pub fn start(&self) -> u64 {
+        self.end - self.audio.len() as u64
+    }
+}
 
 pub struct VoiceDetector {
     vad: Vad,
+    config: VadConfig,
+    /// Aggressiveness `vad` currently runs with.
+    mode: Aggressiveness,
+    /// Frame RMS over the current noise-floor window (auto mode).
+    noise_window: Vec<f64>,
+    /// Aggressiveness picked from the last window, waiting for a segment boundary.
+    pending_mode: Option<Aggressiveness>,
+    calibrated: bool,
     is_speaking: bool,
     silence_frames: u32,
     audio_buffer: Vec<i16>,
     pre_roll_buffer: VecDeque<[i16; FRAME_SIZE]>,
+    /// The start of a frame split across calls, completed by the next one.
+    partial: Vec<i16>,
+    /// Samples framed since the last reset.
+    position: u64,
 }
 
 impl VoiceDetector {
-    pub fn new() -> Result<Self> {
-        let vad = Vad::new_with_rate_and_mode(SampleRate::Rate16kHz, VadMode::Aggressive);
+    pub fn new(config: VadConfig) -> Result<Self> {
+        let mode = config.mode.unwrap_or(Aggressiveness::Aggressive);
         Ok(Self {
-            vad,
+            vad: mode.vad(),
+            config,
+            mode,
+            noise_window: Vec::with_capacity(CALIBRATION_FRAMES),
+            pending_mode: None,
+            calibrated: false,
             is_speaking: false,
             silence_frames: 0,
             audio_buffer: Vec::new(),
             pre_roll_buffer: VecDeque::with_capacity(PRE_ROLL_FRAMES),
+            partial: Vec::with_capacity(FRAME_SIZE),
+            position: 0,
         })
     }
 
-    pub fn process_samples(&mut self, samples: &[i16]) -> Vec<Vec<i16>> {
+    pub fn process_samples(&mut self, samples: &[i16]) -> Vec<Segment> {
         let mut segments = Vec::new();
 
-        for chunk in samples.chunks_exact(FRAME_SIZE) {
+        let mut input = std::mem::take(&mut self.partial);
+        input.extend_from_slice(samples);
+        let frames = input.chunks_exact(FRAME_SIZE);
+        self.partial.extend_from_slice(frames.remainder());
+
+        for chunk in frames {
+            self.position += FRAME_SIZE as u64;
             let frame: [i16; FRAME_SIZE] = chunk.try_into().unwrap();
-            let is_voice = self.vad.is_voice_segment(&frame).unwrap_or(false);
+            let rms = frame_rms(&frame);
+            if self.config.mode.is_none() {
+                self.measure_noise(rms);
+            }
+            if !self.is_speaking {
+                self.apply_pending_mode();
+            }
+            let is_voice =
+                rms >= self.config.min_rms && self.vad.is_voice_segment(&frame).unwrap_or(false);
 
             match (self.is_speaking, is_voice) {
                 // Silence → Silence
@@ -55,34 +190,218 @@ impl VoiceDetector {
                 (true, true) => {
                     self.silence_frames = 0;
                     self.audio_buffer.extend_from_slice(&frame);
+                    if self.audio_buffer.len() >= self.config.max_segment_samples {
+                        segments.push(Segment {
+                            audio: self.force_split(),
+                            end: self.position,
+                        });
+                        // Noise loud enough to hold the VAD open never gives a pause to switch in
+                        self.apply_pending_mode();
+                    }
                 }
                 // Voice → Silence
                 (true, false) => {
                     self.audio_buffer.extend_from_slice(&frame);
                     self.silence_frames += 1;
                     if self.silence_frames >= SILENCE_THRESHOLD {
-                        segments.push(std::mem::take(&mut self.audio_buffer));
+                        segments.push(Segment {
+                            audio: std::mem::take(&mut self.audio_buffer),
+                            end: self.position,
+                        });
                         self.is_speaking = false;
                         self.silence_frames = 0;
                         self.pre_roll_buffer.clear();
                     }
                 }
             }
+
+            if self.audio_buffer.len() >= self.config.max_buffer_samples {
+                warn!(
+                    "VAD buffer reached {}s without ending a segment, flushing it (the VAD looks stuck)",
+                    self.audio_buffer.len() / 16000
+                );
+                segments.push(Segment {
+                    audio: std::mem::take(&mut self.audio_buffer),
+                    end: self.position,
+                });
+                self.is_speaking = false;
+                self.silence_frames = 0;
+                self.pre_roll_buffer.clear();
+            }
         }
 
         segments
     }
 
+    /// Samples of the segment in progress (16 kHz), for `--profile`.
+    pub fn buffered_samples(&self) -> usize {
+        self.audio_buffer.len()
+    }
+
+    /// Accumulate a frame into the noise floor and, once the window is full,
+    /// pick the aggressiveness that suits it. Every frame counts, speech
+    /// included: a low percentile of the window is the room's own level, and
+    /// noise that the VAD takes for voice still raises it.
+    fn measure_noise(&mut self, rms: f64) {
+        self.noise_window.push(rms);
+        let window = if self.calibrated {
+            RECALIBRATION_FRAMES
+        } else {
+            CALIBRATION_FRAMES
+        };
+        if self.noise_window.len() < window {
+            return;
+        }
+
+        let index = self.noise_window.len() * NOISE_PERCENTILE / 100;
+        let (_, &mut floor, _) = self
+            .noise_window
+            .select_nth_unstable_by(index, f64::total_cmp);
+        let mode = Aggressiveness::for_noise_floor(floor);
+        debug!("VAD noise floor {floor:.0} RMS: mode {mode:?}");
+        self.pending_mode = Some(mode);
+        self.calibrated = true;
+        self.noise_window.clear();
+    }
+
+    /// Switch to the aggressiveness picked from the noise floor, at a segment
+    /// boundary so an utterance isn't judged half by one mode and half by another.
+    fn apply_pending_mode(&mut self) {
+        if let Some(mode) = self.pending_mode.take()
+            && mode != self.mode
+        {
+            self.mode = mode;
+            self.vad = mode.vad();
+        }
+    }
+
+    /// Cut an over-long segment while speech continues, carrying the last
+    /// `overlap_samples` over as the start of the next segment.
+    fn force_split(&mut self) -> Vec<i16> {
+        // Cap the overlap so the next segment can't start out already over the limit
+        let overlap = self
+            .config
+            .overlap_samples
+            .min(self.config.max_segment_samples / 2)
+            .min(self.audio_buffer.len());
+        let tail = self.audio_buffer[self.audio_buffer.len() - overlap..].to_vec();
+        std::mem::replace(&mut self.audio_buffer, tail)
+    }
+
+    /// Whether `segment` is long enough to send for transcription and, with
+    /// `max_flatness`, voiced enough not to be clicks.
+    pub fn worth_transcribing(&self, segment: &[i16]) -> bool {
+        if segment.len() < self.config.min_segment_samples {
+            debug!("Segment too short ({} samples), skipped.", segment.len());
+            return false;
+        }
+        if let Some(max) = self.config.max_flatness {
+            let flatness = spectral_flatness(segment, self.config.min_rms);
+            if flatness > max {
+                debug!("Segment sounds like clicks (spectral flatness {flatness:.2}), skipped.");
+                return false;
+            }
+        }
+        true
+    }
+
+    /// The speech accumulated so far, as a final segment (`None` outside speech),
+    /// for shutdown mid-utterance. Leaves the detector ready for new speech.
+    pub fn flush(&mut self) -> Option<Segment> {
+        self.position += self.partial.len() as u64;
+        let partial = std::mem::take(&mut self.partial);
+        let segment = (self.is_speaking && !self.audio_buffer.is_empty()).then(|| {
+            let mut audio = std::mem::take(&mut self.audio_buffer);
+            audio.extend_from_slice(&partial);
+            Segment {
+                audio,
+                end: self.position,
+            }
+        });
+        self.audio_buffer.clear();
+        self.pre_roll_buffer.clear();
+        self.is_speaking = false;
+        self.silence_frames = 0;
+        segment
+    }
+
     pub fn reset(&mut self) {
         // Recreate Vad to clear its internal state (no reset API available)
-        self.vad = Vad::new_with_rate_and_mode(SampleRate::Rate16kHz, VadMode::Aggressive);
+        self.vad = self.mode.vad();
+        // Listening (re)starts: measure the room again
+        self.calibrated = false;
+        self.noise_window.clear();
+        self.pending_mode = None;
         self.audio_buffer.clear();
         self.pre_roll_buffer.clear();
+        self.partial.clear();
+        self.position = 0;
         self.is_speaking = false;
         self.silence_frames = 0;
     }
 }
 
+/// Whether every 10ms frame of `audio` is below `min_rms`, the VAD's
+/// threshold for silence.
+pub fn is_silent(audio: &[i16], min_rms: f64) -> bool {
+    audio
+        .chunks(FRAME_SIZE)
+        .all(|frame| frame_rms(frame) < min_rms)
+}
+
+fn frame_rms(frame: &[i16]) -> f64 {
+    let sum_sq: f64 = frame.iter().map(|&s| (s as f64) * (s as f64)).sum();
+    (sum_sq / frame.len() as f64).sqrt()
+}
+
+/// Mean spectral flatness (geometric over arithmetic mean of the power
+/// spectrum) of the frames at or above `min_rms`: near 0 for voiced speech,
+/// whose energy sits in a few harmonics, near 0.5 for noise bursts and 1 for
+/// an impulse. 0 when no frame is loud enough to judge.
+fn spectral_flatness(segment: &[i16], min_rms: f64) -> f64 {
+    // A plain DFT over 10ms frames: 80 bins is cheap enough without an FFT
+    let twiddles: Vec<(f64, f64)> = (0..FRAME_SIZE)
+        .map(|n| {
+            let angle = 2.0 * std::f64::consts::PI * n as f64 / FRAME_SIZE as f64;
+            (angle.cos(), angle.sin())
+        })
+        .collect();
+    let mut sum = 0.0;
+    let mut frames = 0;
+    for frame in segment.chunks_exact(FRAME_SIZE) {
+        if frame_rms(frame) < min_rms {
+            continue;
+        }
+        // Hann window, so a tone doesn't leak into every bin
+        let windowed: Vec<f64> = frame
+            .iter()
+            .zip(&twiddles)
+            .map(|(&s, (cos, _))| s as f64 * (0.5 - 0.5 * cos))
+            .collect();
+        let power: Vec<f64> = (1..FRAME_SIZE / 2)
+            .map(|bin| {
+                let (re, im) = windowed
+                    .iter()
+                    .enumerate()
+                    .fold((0.0, 0.0), |(re, im), (n, &s)| {
+                        let (cos, sin) = twiddles[bin * n % FRAME_SIZE];
+                        (re + s * cos, im - s * sin)
+                    });
+                re * re + im * im + 1.0 // keeps ln() finite on empty bins
+            })
+            .collect();
+        let log_mean = power.iter().map(|p| p.ln()).sum::<f64>() / power.len() as f64;
+        let mean = power.iter().sum::<f64>() / power.len() as f64;
+        sum += log_mean.exp() / mean;
+        frames += 1;
+    }
+    if frames == 0 {
+        0.0
+    } else {
+        sum / frames as f64
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -99,20 +418,171 @@ mod tests {
         samples
     }
 
+    /// Like `make_voice`, but with a slowly decaying amplitude so every frame is distinct.
+    fn make_distinct_voice(num_frames: usize) -> Vec<i16> {
+        (0..(FRAME_SIZE * num_frames))
+            .map(|i| {
+                let amplitude = 30000 - (i / FRAME_SIZE) as i16 * 10;
+                if (i / 16) % 2 == 0 {
+                    amplitude
+                } else {
+                    -amplitude
+                }
+            })
+            .collect()
+    }
+
+    /// Low-level background noise: a faint square wave plus pseudo-random jitter.
+    fn make_noise(num_frames: usize) -> Vec<i16> {
+        let mut seed: u32 = 12345;
+        (0..(FRAME_SIZE * num_frames))
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let jitter = (seed >> 16) as i16 % 30;
+                let tone = if (i / 16) % 2 == 0 { 40 } else { -40 };
+                tone + jitter
+            })
+            .collect()
+    }
+
     fn make_silence(num_frames: usize) -> Vec<i16> {
         vec![0i16; FRAME_SIZE * num_frames]
     }
 
+    /// A voiced sound: a 150Hz fundamental with decaying harmonics.
+    fn make_voiced_tone(num_frames: usize) -> Vec<i16> {
+        (0..(FRAME_SIZE * num_frames))
+            .map(|i| {
+                let t = i as f64 / 16000.0;
+                (1..=10)
+                    .map(|h| {
+                        8000.0 / h as f64
+                            * (2.0 * std::f64::consts::PI * 150.0 * h as f64 * t).sin()
+                    })
+                    .sum::<f64>() as i16
+            })
+            .collect()
+    }
+
+    /// Keyboard-like clicks: a 3ms decaying noise burst every 50ms.
+    fn make_clicks(num_frames: usize) -> Vec<i16> {
+        let mut seed: u32 = 12345;
+        (0..(FRAME_SIZE * num_frames))
+            .map(|i| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let noise = ((seed >> 16) & 0x7fff) as f64 / 16384.0 - 1.0;
+                let k = i % 800;
+                if k < 48 {
+                    (20000.0 * (-(k as f64) / 8.0).exp() * noise) as i16
+                } else {
+                    0
+                }
+            })
+            .collect()
+    }
+
     #[test]
     fn silence_produces_no_segments() {
-        let mut vd = VoiceDetector::new().unwrap();
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
         let segments = vd.process_samples(&make_silence(100));
         assert!(segments.is_empty());
     }
 
+    #[test]
+    fn low_level_noise_produces_no_segments() {
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
+        let mut segments = vd.process_samples(&make_noise(300));
+        segments.extend(vd.process_samples(&make_silence(SILENCE_THRESHOLD as usize + 20)));
+        assert!(
+            segments.is_empty(),
+            "Noise below the RMS gate must not emit segments"
+        );
+        assert!(!vd.is_speaking);
+    }
+
+    #[test]
+    fn rms_gate_threshold_is_configurable() {
+        let mut vd = VoiceDetector::new(VadConfig {
+            min_rms: 40000.0, // above any i16 signal
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let segments = vd.process_samples(&make_voice(50));
+        assert!(segments.is_empty());
+        assert!(
+            !vd.is_speaking,
+            "Voice below the gate is treated as silence"
+        );
+    }
+
+    #[test]
+    fn hard_cap_flushes_a_runaway_buffer() {
+        // No force-split below the hard cap: only the safety net can end the segment
+        let mut vd = VoiceDetector::new(VadConfig {
+            max_segment_samples: usize::MAX,
+            max_buffer_samples: FRAME_SIZE * 100,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let segments = vd.process_samples(&make_voice(150));
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].audio.len(), FRAME_SIZE * 100);
+        // The speech that follows starts a fresh segment
+        assert!(vd.buffered_samples() <= FRAME_SIZE * 50);
+    }
+
+    #[test]
+    fn spectral_flatness_tells_clicks_from_voice() {
+        assert!(spectral_flatness(&make_voiced_tone(100), 100.0) < 0.05);
+        assert!(spectral_flatness(&make_clicks(100), 100.0) > 0.45);
+        let mut impulses = make_silence(100);
+        impulses[37] = 25000;
+        assert!(spectral_flatness(&impulses, 100.0) > 0.99);
+        assert_eq!(spectral_flatness(&make_silence(100), 100.0), 0.0);
+    }
+
+    #[test]
+    fn click_filter_is_opt_in() {
+        let clicks = make_clicks(100);
+        let vd = VoiceDetector::new(VadConfig::default()).unwrap();
+        assert!(vd.worth_transcribing(&clicks));
+
+        let vd = VoiceDetector::new(VadConfig {
+            max_flatness: Some(0.35),
+            ..VadConfig::default()
+        })
+        .unwrap();
+        assert!(!vd.worth_transcribing(&clicks));
+        assert!(vd.worth_transcribing(&make_voiced_tone(100)));
+    }
+
+    #[test]
+    fn lookback_speech_starts_the_first_segment() {
+        // Speech captured just before listening started, seeded ahead of the first chunk
+        let mut lookback = crate::audio::Lookback::new(16000, 1, 1000);
+        lookback.push(&make_silence(100));
+        let before_toggle = make_distinct_voice(30);
+        lookback.push(&before_toggle);
+
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
+        let mut segments = vd.process_samples(&lookback.seed(&make_voice(20)));
+        segments.extend(vd.process_samples(&make_silence(SILENCE_THRESHOLD as usize + 5)));
+
+        assert_eq!(segments.len(), 1);
+        let segment = &segments[0];
+        let start = segment
+            .audio
+            .windows(before_toggle.len())
+            .position(|w| w == before_toggle.as_slice());
+        assert!(
+            start.is_some_and(|i| i <= PRE_ROLL_FRAMES * FRAME_SIZE),
+            "Pre-toggle speech opens the segment"
+        );
+    }
+
     #[test]
     fn loud_then_silence_produces_segment() {
-        let mut vd = VoiceDetector::new().unwrap();
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
 
         // Feed voice (50 frames = 500ms)
         let segs = vd.process_samples(&make_voice(50));
@@ -128,16 +598,16 @@ mod tests {
         // Segment should include voice frames + some pre-roll
         let seg = &segs[0];
         assert!(
-            seg.len() >= FRAME_SIZE * 50,
+            seg.audio.len() >= FRAME_SIZE * 50,
             "Segment length {} should be >= {}",
-            seg.len(),
+            seg.audio.len(),
             FRAME_SIZE * 50
         );
     }
 
     #[test]
     fn reset_discards_accumulated_audio() {
-        let mut vd = VoiceDetector::new().unwrap();
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
 
         // Feed voice to start speaking state
         let segs = vd.process_samples(&make_voice(30));
@@ -155,9 +625,22 @@ mod tests {
         assert!(segs.is_empty());
     }
 
+    #[test]
+    fn flush_returns_speech_in_progress() {
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
+        assert_eq!(vd.flush(), None);
+
+        vd.process_samples(&make_voice(30));
+        let segment = vd.flush().expect("speech in progress");
+        assert!(segment.audio.len() >= FRAME_SIZE * 30);
+        assert!(!vd.is_speaking);
+        assert!(vd.audio_buffer.is_empty());
+        assert_eq!(vd.flush(), None, "Flushed audio is not returned twice");
+    }
+
     #[test]
     fn multiple_speech_bursts() {
-        let mut vd = VoiceDetector::new().unwrap();
+        let mut vd = VoiceDetector::new(VadConfig::default()).unwrap();
         let mut total_segments = Vec::new();
 
         for _ in 0..2 {
@@ -168,4 +651,137 @@ mod tests {
 
         assert_eq!(total_segments.len(), 2, "Should emit 2 separate segments");
     }
+
+    #[test]
+    fn long_speech_is_force_split_with_overlap() {
+        let overlap = FRAME_SIZE * 20;
+        let mut vd = VoiceDetector::new(VadConfig {
+            max_segment_samples: FRAME_SIZE * 50,
+            overlap_samples: overlap,
+            ..VadConfig::default()
+        })
+        .unwrap();
+
+        let mut segments = vd.process_samples(&make_distinct_voice(70));
+        assert_eq!(segments.len(), 1, "Should force-split once mid-speech");
+        assert_eq!(segments[0].audio.len(), FRAME_SIZE * 50);
+
+        segments.extend(vd.process_samples(&make_silence(SILENCE_THRESHOLD as usize + 20)));
+        assert_eq!(segments.len(), 2);
+
+        let first_tail = &segments[0].audio[segments[0].audio.len() - overlap..];
+        assert_eq!(
+            &segments[1].audio[..overlap],
+            first_tail,
+            "Next segment should start with the overlap from the previous one"
+        );
+    }
+
+    #[test]
+    fn segments_know_where_they_are_in_the_stream() {
+        let mut vd = VoiceDetector::new(VadConfig {
+            max_segment_samples: FRAME_SIZE * 50,
+            overlap_samples: FRAME_SIZE * 20,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        let mut stream = make_distinct_voice(70);
+        stream.extend(make_silence(SILENCE_THRESHOLD as usize + 20));
+
+        // Resampler output rarely comes in whole frames
+        let segments: Vec<Segment> = stream
+            .chunks(333)
+            .flat_map(|piece| vd.process_samples(piece))
+            .collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].end, FRAME_SIZE as u64 * 50);
+        assert_eq!(segments[1].start(), FRAME_SIZE as u64 * 30);
+        for segment in &segments {
+            let range = segment.start() as usize..segment.end as usize;
+            assert_eq!(segment.audio, stream[range], "No sample lost or moved");
+        }
+    }
+
+    #[test]
+    fn tiny_segments_are_not_worth_transcribing() {
+        let vd = VoiceDetector::new(VadConfig::default()).unwrap();
+        assert!(!vd.worth_transcribing(&[0; 10]));
+        assert!(!vd.worth_transcribing(&[]));
+        assert!(vd.worth_transcribing(&make_voice(10)));
+
+        let vd = VoiceDetector::new(VadConfig {
+            min_segment_samples: 0,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        assert!(vd.worth_transcribing(&[0; 10]));
+    }
+
+    #[test]
+    fn vad_mode_follows_noise_floor() {
+        assert_eq!(
+            Aggressiveness::for_noise_floor(20.0),
+            Aggressiveness::LowBitrate
+        );
+        assert_eq!(
+            Aggressiveness::for_noise_floor(400.0),
+            Aggressiveness::Aggressive
+        );
+        assert_eq!(
+            Aggressiveness::for_noise_floor(3000.0),
+            Aggressiveness::VeryAggressive
+        );
+        assert_eq!(Aggressiveness::parse("auto").unwrap(), None);
+        assert!(Aggressiveness::parse("loud").is_err());
+    }
+
+    #[test]
+    fn auto_mode_calibrates_on_the_first_second_of_silence() {
+        let mut vd = VoiceDetector::new(VadConfig {
+            mode: None,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        vd.process_samples(&make_noise(CALIBRATION_FRAMES));
+        assert_eq!(vd.mode, Aggressiveness::LowBitrate);
+        assert!(vd.calibrated);
+
+        vd.reset();
+        assert!(
+            !vd.calibrated,
+            "Restarting to listen measures the room again"
+        );
+    }
+
+    #[test]
+    fn steady_loud_noise_makes_the_vad_stricter() {
+        // Loud enough for the VAD to keep a segment open, so there is no silence to measure
+        let mut seed: u32 = 1;
+        let noise: Vec<i16> = (0..FRAME_SIZE * 3 * CALIBRATION_FRAMES)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                ((seed >> 16) as i16 % 3000) + if seed & 1 == 0 { 1500 } else { -1500 }
+            })
+            .collect();
+        let mut vd = VoiceDetector::new(VadConfig {
+            max_segment_samples: FRAME_SIZE * CALIBRATION_FRAMES,
+            ..VadConfig::default()
+        })
+        .unwrap();
+        assert_eq!(vd.config.mode, None, "auto is the default");
+        vd.process_samples(&noise);
+        assert_eq!(vd.mode, Aggressiveness::VeryAggressive);
+    }
+
+    #[test]
+    fn fixed_mode_ignores_noise_floor() {
+        let mut vd = VoiceDetector::new(VadConfig {
+            mode: Some(Aggressiveness::Aggressive),
+            ..VadConfig::default()
+        })
+        .unwrap();
+        vd.process_samples(&make_noise(CALIBRATION_FRAMES + 1));
+        assert_eq!(vd.mode, Aggressiveness::Aggressive);
+        assert!(!vd.calibrated);
+    }
 }
@@ -0,0 +1,105 @@
+use anyhow::{Context, Result, bail};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Return the value following `flag`, e.g. `--model small` → `Some("small")`.
+pub fn find_arg_value(args: &[String], flag: &str) -> Option<String> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
+
+pub fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter().any(|a| a == flag)
+}
+
+/// Flag with an optional value: `--flag` alone yields `Some(None)`,
+/// `--flag value` yields `Some(Some(value))`, absence yields `None`.
+pub fn find_optional_arg_value(args: &[String], flag: &str) -> Option<Option<String>> {
+    let i = args.iter().position(|a| a == flag)?;
+    Some(args.get(i + 1).filter(|v| !v.starts_with("--")).cloned())
+}
+
+/// Parse the value following `flag`, failing with a clear message on bad input.
+pub fn parse_arg_value<T>(args: &[String], flag: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    find_arg_value(args, flag)
+        .map(|v| {
+            v.parse::<T>()
+                .with_context(|| format!("Invalid value for {flag}: '{v}'"))
+        })
+        .transpose()
+}
+
+/// A flag's value in seconds: 0, negative, NaN and durations too long to
+/// represent are rejected.
+pub fn parse_positive_secs(args: &[String], flag: &str) -> Result<Option<f64>> {
+    parse_arg_value(args, flag)?
+        .map(|secs| check_positive_secs(flag, secs))
+        .transpose()
+}
+
+/// `secs`, given for `flag`, if it is a positive duration `Duration` can hold.
+pub fn check_positive_secs(flag: &str, secs: f64) -> Result<f64> {
+    if !(secs > 0.0 && Duration::try_from_secs_f64(secs).is_ok()) {
+        bail!("Invalid value for {flag}: '{secs}' (expected a positive number of seconds)");
+    }
+    Ok(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(s: &str) -> Vec<String> {
+        s.split_whitespace().map(String::from).collect()
+    }
+
+    #[test]
+    fn find_value_after_flag() {
+        let a = args("bin --model small --debug");
+        assert_eq!(find_arg_value(&a, "--model"), Some("small".to_string()));
+        assert_eq!(find_arg_value(&a, "--language"), None);
+        assert!(has_flag(&a, "--debug"));
+    }
+
+    #[test]
+    fn parse_value() {
+        let a = args("bin --max-segment 12 --overlap abc");
+        assert_eq!(
+            parse_arg_value::<u32>(&a, "--max-segment").unwrap(),
+            Some(12)
+        );
+        assert_eq!(parse_arg_value::<u32>(&a, "--missing").unwrap(), None);
+        assert!(parse_arg_value::<u32>(&a, "--overlap").is_err());
+    }
+
+    #[test]
+    fn positive_secs() {
+        let a = args("bin --a 1.5 --b 0 --c -1 --d NaN --e inf --f 1e300");
+        assert_eq!(parse_positive_secs(&a, "--a").unwrap(), Some(1.5));
+        assert_eq!(parse_positive_secs(&a, "--missing").unwrap(), None);
+        for flag in ["--b", "--c", "--d", "--e", "--f"] {
+            assert!(parse_positive_secs(&a, flag).is_err(), "{flag}");
+        }
+    }
+
+    #[test]
+    fn optional_value() {
+        let a = args("bin --state-file --debug");
+        assert_eq!(find_optional_arg_value(&a, "--state-file"), Some(None));
+
+        let a = args("bin --state-file /tmp/x");
+        assert_eq!(
+            find_optional_arg_value(&a, "--state-file"),
+            Some(Some("/tmp/x".to_string()))
+        );
+
+        let a = args("bin --debug");
+        assert_eq!(find_optional_arg_value(&a, "--state-file"), None);
+    }
+}
@@ -0,0 +1,193 @@
+use anyhow::{Result, bail};
+
+/// Every language whisper can transcribe: (code, English name), in whisper.cpp's order.
+pub const LANGUAGES: &[(&str, &str)] = &[
+    ("en", "English"),
+    ("zh", "Chinese"),
+    ("de", "German"),
+    ("es", "Spanish"),
+    ("ru", "Russian"),
+    ("ko", "Korean"),
+    ("fr", "French"),
+    ("ja", "Japanese"),
+    ("pt", "Portuguese"),
+    ("tr", "Turkish"),
+    ("pl", "Polish"),
+    ("ca", "Catalan"),
+    ("nl", "Dutch"),
+    ("ar", "Arabic"),
+    ("sv", "Swedish"),
+    ("it", "Italian"),
+    ("id", "Indonesian"),
+    ("hi", "Hindi"),
+    ("fi", "Finnish"),
+    ("vi", "Vietnamese"),
+    ("he", "Hebrew"),
+    ("uk", "Ukrainian"),
+    ("el", "Greek"),
+    ("ms", "Malay"),
+    ("cs", "Czech"),
+    ("ro", "Romanian"),
+    ("da", "Danish"),
+    ("hu", "Hungarian"),
+    ("ta", "Tamil"),
+    ("no", "Norwegian"),
+    ("th", "Thai"),
+    ("ur", "Urdu"),
+    ("hr", "Croatian"),
+    ("bg", "Bulgarian"),
+    ("lt", "Lithuanian"),
+    ("la", "Latin"),
+    ("mi", "Maori"),
+    ("ml", "Malayalam"),
+    ("cy", "Welsh"),
+    ("sk", "Slovak"),
+    ("te", "Telugu"),
+    ("fa", "Persian"),
+    ("lv", "Latvian"),
+    ("bn", "Bengali"),
+    ("sr", "Serbian"),
+    ("az", "Azerbaijani"),
+    ("sl", "Slovenian"),
+    ("kn", "Kannada"),
+    ("et", "Estonian"),
+    ("mk", "Macedonian"),
+    ("br", "Breton"),
+    ("eu", "Basque"),
+    ("is", "Icelandic"),
+    ("hy", "Armenian"),
+    ("ne", "Nepali"),
+    ("mn", "Mongolian"),
+    ("bs", "Bosnian"),
+    ("kk", "Kazakh"),
+    ("sq", "Albanian"),
+    ("sw", "Swahili"),
+    ("gl", "Galician"),
+    ("mr", "Marathi"),
+    ("pa", "Punjabi"),
+    ("si", "Sinhala"),
+    ("km", "Khmer"),
+    ("sn", "Shona"),
+    ("yo", "Yoruba"),
+    ("so", "Somali"),
+    ("af", "Afrikaans"),
+    ("oc", "Occitan"),
+    ("ka", "Georgian"),
+    ("be", "Belarusian"),
+    ("tg", "Tajik"),
+    ("sd", "Sindhi"),
+    ("gu", "Gujarati"),
+    ("am", "Amharic"),
+    ("yi", "Yiddish"),
+    ("lo", "Lao"),
+    ("uz", "Uzbek"),
+    ("fo", "Faroese"),
+    ("ht", "Haitian Creole"),
+    ("ps", "Pashto"),
+    ("tk", "Turkmen"),
+    ("nn", "Nynorsk"),
+    ("mt", "Maltese"),
+    ("sa", "Sanskrit"),
+    ("lb", "Luxembourgish"),
+    ("my", "Myanmar"),
+    ("bo", "Tibetan"),
+    ("tl", "Tagalog"),
+    ("mg", "Malagasy"),
+    ("as", "Assamese"),
+    ("tt", "Tatar"),
+    ("haw", "Hawaiian"),
+    ("ln", "Lingala"),
+    ("ha", "Hausa"),
+    ("ba", "Bashkir"),
+    ("jw", "Javanese"),
+    ("su", "Sundanese"),
+    ("yue", "Cantonese"),
+];
+
+/// `--language auto`: whisper detects the language of each segment.
+pub const AUTO: &str = "auto";
+
+/// Check `code` is a whisper language code or `auto` (case-insensitive) and
+/// return it lowercased.
+pub fn validate(code: &str) -> Result<String> {
+    let code = code.trim().to_lowercase();
+    if code == AUTO || LANGUAGES.iter().any(|&(c, _)| c == code) {
+        Ok(code)
+    } else {
+        bail!("Unknown language code '{code}' (see --list-languages)")
+    }
+}
+
+/// Check a `--language-fallback` code: a language, `auto` being what it replaces.
+pub fn validate_fallback(code: &str) -> Result<String> {
+    let code = validate(code)?;
+    if code == AUTO {
+        bail!("--language-fallback needs a language code, not '{AUTO}'");
+    }
+    Ok(code)
+}
+
+/// Check a `--min-language-confidence`: a probability, from 0 to 1.
+pub fn validate_confidence(p: f32) -> Result<f32> {
+    if !(0.0..=1.0).contains(&p) {
+        bail!("--min-language-confidence must be between 0 and 1, not {p}");
+    }
+    Ok(p)
+}
+
+/// Whether `code` is written without spaces between words (Japanese, Chinese).
+pub fn is_unspaced(code: &str) -> bool {
+    matches!(code, "ja" | "zh")
+}
+
+/// Print the supported codes, one `code  Name` per line (`--list-languages`).
+pub fn print() {
+    println!("{AUTO:<4} Detect (per segment)");
+    for (code, name) in LANGUAGES {
+        println!("{code:<4} {name}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validate_accepts_every_whisper_code() {
+        assert_eq!(LANGUAGES.len(), 100);
+        assert_eq!(validate("nl").unwrap(), "nl");
+        assert_eq!(validate(" KO ").unwrap(), "ko");
+        assert_eq!(validate("yue").unwrap(), "yue");
+        assert_eq!(validate("Auto").unwrap(), AUTO);
+    }
+
+    #[test]
+    fn fallback_must_be_a_language() {
+        assert_eq!(validate_fallback(" FR").unwrap(), "fr");
+        assert!(validate_fallback("auto").is_err());
+        assert!(validate_fallback("xx").is_err());
+    }
+
+    #[test]
+    fn confidence_is_a_probability() {
+        assert_eq!(validate_confidence(0.7).unwrap(), 0.7);
+        assert!(validate_confidence(0.0).is_ok() && validate_confidence(1.0).is_ok());
+        assert!(validate_confidence(1.5).is_err());
+        assert!(validate_confidence(-0.1).is_err());
+        assert!(validate_confidence(f32::NAN).is_err());
+    }
+
+    #[test]
+    fn only_cjk_is_unspaced() {
+        assert!(is_unspaced("ja") && is_unspaced("zh"));
+        assert!(!is_unspaced("fr") && !is_unspaced(AUTO));
+    }
+
+    #[test]
+    fn validate_rejects_unknown_codes() {
+        let err = validate("xx").unwrap_err().to_string();
+        assert!(err.contains("--list-languages"), "{err}");
+        assert!(validate("").is_err());
+        assert!(validate("english").is_err());
+    }
+}
@@ -1,3 +1,7 @@
+pub mod args;
+pub mod languages;
 pub mod log;
 pub mod models;
+pub mod panic;
 pub mod protocol;
+pub mod transcriber;
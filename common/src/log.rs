@@ -1,7 +1,20 @@
+use std::fmt;
+use std::sync::OnceLock;
 use std::sync::atomic::{AtomicBool, Ordering};
 
 static DEBUG: AtomicBool = AtomicBool::new(false);
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Debug,
+    Info,
+    Warn,
+}
+
+type Sink = Box<dyn Fn(Level, &str) + Send + Sync>;
+
+static SINK: OnceLock<Sink> = OnceLock::new();
+
 pub fn set_debug(enabled: bool) {
     DEBUG.store(enabled, Ordering::SeqCst);
 }
@@ -10,10 +23,34 @@ pub fn is_debug() -> bool {
     DEBUG.load(Ordering::SeqCst)
 }
 
+/// Flip debug logging and return the new state. A single atomic operation,
+/// so it is safe to call from a signal handler.
+pub fn toggle_debug() -> bool {
+    !DEBUG.fetch_xor(true, Ordering::SeqCst)
+}
+
+/// Send every log line to `sink` instead of stderr (e.g. to forward it over
+/// the protocol). Can only be set once; later calls are ignored.
+pub fn set_sink(sink: impl Fn(Level, &str) + Send + Sync + 'static) {
+    let _ = SINK.set(Box::new(sink));
+}
+
+#[doc(hidden)]
+pub fn emit(level: Level, args: fmt::Arguments) {
+    if let Some(sink) = SINK.get() {
+        sink(level, &args.to_string());
+        return;
+    }
+    if level == Level::Warn {
+        eprint!("\x1b[33mWARNING:\x1b[0m ");
+    }
+    eprintln!("{args}");
+}
+
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {
-        eprintln!($($arg)*)
+        $crate::log::emit($crate::log::Level::Info, format_args!($($arg)*))
     };
 }
 
@@ -21,15 +58,14 @@ macro_rules! info {
 macro_rules! debug {
     ($($arg:tt)*) => {
         if $crate::log::is_debug() {
-            eprintln!($($arg)*)
+            $crate::log::emit($crate::log::Level::Debug, format_args!($($arg)*))
         }
     };
 }
 
 #[macro_export]
 macro_rules! warn {
-    ($($arg:tt)*) => {{
-        eprint!("\x1b[33mWARNING:\x1b[0m ");
-        eprintln!($($arg)*)
-    }};
+    ($($arg:tt)*) => {
+        $crate::log::emit($crate::log::Level::Warn, format_args!($($arg)*))
+    };
 }
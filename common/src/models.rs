@@ -1,6 +1,11 @@
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
+use std::ffi::OsString;
 use std::path::{Path, PathBuf};
 
+/// The `ggml-*.bin` models in `dir`, as (short name, path), sorted by name.
+/// Symlinks (e.g. to a model on another drive) are listed under the link's
+/// name and path, sizes coming from their target; broken ones are skipped
+/// with a warning.
 pub fn scan_models(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
     if !dir.exists() {
         std::fs::create_dir_all(dir)
@@ -17,6 +22,15 @@ pub fn scan_models(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
         if let Some(name) = path.file_name().and_then(|n| n.to_str())
             && name.starts_with("ggml-") && name.ends_with(".bin")
         {
+            // Follows symlinks: a dangling one fails here
+            match std::fs::metadata(&path) {
+                Ok(meta) if meta.is_file() => {}
+                Ok(_) => continue,
+                Err(e) => {
+                    crate::warn!("Skipping model {}: broken link ({e})", path.display());
+                    continue;
+                }
+            }
             let display_name = name
                 .strip_prefix("ggml-")
                 .unwrap()
@@ -31,13 +45,77 @@ pub fn scan_models(dir: &Path) -> Result<Vec<(String, PathBuf)>> {
     Ok(models)
 }
 
+/// Approximate sizes (MB) of the standard ggml models, to spot interrupted downloads.
+/// `.en` variants share the size of their multilingual counterpart.
+const KNOWN_MODEL_SIZES_MB: &[(&str, u64)] = &[
+    ("tiny", 77),
+    ("base", 147),
+    ("small", 487),
+    ("medium", 1533),
+    ("large-v1", 3094),
+    ("large-v2", 3094),
+    ("large-v3", 3095),
+    ("large-v3-turbo", 1624),
+];
+
+/// True when `name` is a known model and `size` is well short of its full size,
+/// i.e. a partial download that would fail to load. Unknown models (quantized,
+/// fine-tunes) are never flagged.
+pub fn is_incomplete(name: &str, size: u64) -> bool {
+    let base = name.strip_suffix(".en").unwrap_or(name);
+    KNOWN_MODEL_SIZES_MB
+        .iter()
+        .find(|(known, _)| *known == base)
+        .is_some_and(|&(_, mb)| size < mb * 1_000_000 * 9 / 10)
+}
+
+/// Like `is_incomplete`, reading the size from disk (unreadable files count as complete).
+pub fn is_incomplete_file(name: &str, path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|m| is_incomplete(name, m.len()))
+}
+
+/// English-only models (`ggml-base.en.bin`, `small.en`, `ggml-small.en-q5_1.bin`)
+/// only transcribe English; any other language produces garbage.
+/// Accepts a short name, a file name or a full path.
+pub fn is_english_only(model: &str) -> bool {
+    let name = Path::new(model)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(model);
+    let name = name.strip_suffix(".bin").unwrap_or(name);
+    name.ends_with(".en") || name.contains(".en-")
+}
+
+/// The language a model was made for, guessed from its name: the `.en` of
+/// English-only models or a fine-tune's language tag (`ggml-small.fr.bin`,
+/// `whisper-large-v3-fr-q5_0`, `ggml-medium-french.bin`). `None` for the usual
+/// multilingual models. Accepts a short name, a file name or a full path.
+pub fn model_language(model: &str) -> Option<&'static str> {
+    let name = Path::new(model)
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or(model);
+    let name = name.strip_suffix(".bin").unwrap_or(name).to_lowercase();
+    name.split(['-', '_', '.']).find_map(|token| {
+        crate::languages::LANGUAGES
+            .iter()
+            .find(|(code, language)| *code == token || language.eq_ignore_ascii_case(token))
+            .map(|&(code, _)| code)
+    })
+}
+
+/// The models directory: `explicit` (`--models-dir`) when given, else the
+/// first existing one of `default_models_dir`'s search order.
+pub fn models_dir(explicit: Option<&Path>) -> PathBuf {
+    explicit.map_or_else(default_models_dir, Path::to_path_buf)
+}
+
 pub fn default_models_dir() -> PathBuf {
-    // 1. XDG data dir: ~/.local/share/space_tts/models/
-    if let Ok(home) = std::env::var("HOME") {
-        let dir = PathBuf::from(home).join(".local/share/space_tts/models");
-        if dir.exists() {
-            return dir;
-        }
+    // 1. XDG data dir: $XDG_DATA_HOME/space_tts/models/ (~/.local/share by default)
+    if let Some(dir) = xdg_models_dir(std::env::var_os("XDG_DATA_HOME"), std::env::var_os("HOME"))
+        && dir.exists()
+    {
+        return dir;
     }
 
     // 2. Next to executable
@@ -61,9 +139,19 @@ pub fn default_models_dir() -> PathBuf {
     PathBuf::from("models")
 }
 
-/// Resolve a model argument to an absolute path.
+/// `$XDG_DATA_HOME/space_tts/models`, with `XDG_DATA_HOME` defaulting to
+/// `~/.local/share` when unset or not absolute (as the XDG spec asks).
+fn xdg_models_dir(data_home: Option<OsString>, home: Option<OsString>) -> Option<PathBuf> {
+    let data_home = data_home
+        .map(PathBuf::from)
+        .filter(|dir| dir.is_absolute())
+        .or_else(|| home.map(|home| PathBuf::from(home).join(".local/share")))?;
+    Some(data_home.join("space_tts/models"))
+}
+
+/// Resolve a model argument to an absolute path, looking in `models_dir`.
 /// Accepts: "small", "ggml-small.bin", or a full path.
-pub fn resolve_model_path(input: &str) -> PathBuf {
+pub fn resolve_model_path(input: &str, models_dir: &Path) -> PathBuf {
     let path = Path::new(input);
 
     // Already an existing absolute or relative path — use as-is
@@ -71,8 +159,6 @@ pub fn resolve_model_path(input: &str) -> PathBuf {
         return path.to_path_buf();
     }
 
-    let models_dir = default_models_dir();
-
     // Try as filename: "ggml-small.bin"
     let as_file = models_dir.join(input);
     if as_file.exists() {
@@ -89,6 +175,80 @@ pub fn resolve_model_path(input: &str) -> PathBuf {
     as_file
 }
 
+/// Check that a restricted server (`--allowed-models`) may load `path`: it must
+/// lie inside `models_dir` once symlinks and `..` are resolved and, when
+/// `allowed` is non-empty, be one of those models ("small" or "ggml-small.bin").
+pub fn check_allowed(path: &Path, models_dir: &Path, allowed: &[String]) -> Result<()> {
+    let dir = models_dir
+        .canonicalize()
+        .with_context(|| format!("Models directory not found: {}", models_dir.display()))?;
+    let model = path
+        .canonicalize()
+        .with_context(|| format!("Model not found: {}", path.display()))?;
+    if !model.starts_with(&dir) {
+        bail!("Model {} is outside {}", path.display(), dir.display());
+    }
+    if allowed.is_empty() {
+        return Ok(());
+    }
+    let file = model.file_name().and_then(|f| f.to_str()).unwrap_or("");
+    if allowed
+        .iter()
+        .any(|a| file == a || file == format!("ggml-{a}.bin"))
+    {
+        Ok(())
+    } else {
+        bail!("Model {file} is not in this server's allowed list")
+    }
+}
+
+/// Index of the entry of a `(name, path)` model list matching `input`, given in
+/// the same forms `resolve_model_path` accepts: "small", "ggml-small.bin", or a full path.
+pub fn find_model<P: AsRef<Path>>(models: &[(String, P)], input: &str) -> Option<usize> {
+    models.iter().position(|(name, path)| {
+        let path = path.as_ref();
+        name == input || path == Path::new(input) || path.file_name().is_some_and(|f| f == input)
+    })
+}
+
+/// `--list-models --json`: the models as an array of
+/// `{"name", "path", "size_bytes", "valid"}` objects, `valid` being false for
+/// partial downloads (`is_incomplete`). Unreadable files report size 0.
+pub fn models_json<P: AsRef<Path>>(models: &[(String, P)]) -> String {
+    let entries: Vec<String> = models
+        .iter()
+        .map(|(name, path)| {
+            let path = path.as_ref();
+            let size = std::fs::metadata(path).map_or(0, |m| m.len());
+            format!(
+                "{{\"name\":{},\"path\":{},\"size_bytes\":{size},\"valid\":{}}}",
+                json_string(name),
+                json_string(&path.to_string_lossy()),
+                !is_incomplete_file(name, path)
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -112,6 +272,77 @@ mod tests {
         let _ = fs::remove_dir_all(&dir);
     }
 
+    #[test]
+    fn scan_models_follows_symlinks_and_skips_broken_ones() {
+        let root = std::env::temp_dir().join("space-stt-test-symlinks");
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("models");
+        let other_drive = root.join("other-drive");
+        fs::create_dir_all(&dir).unwrap();
+        fs::create_dir_all(&other_drive).unwrap();
+        fs::write(
+            other_drive.join("ggml-large-v3.bin"),
+            b"a bigger fake model",
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            other_drive.join("ggml-large-v3.bin"),
+            dir.join("ggml-large.bin"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(other_drive.join("missing.bin"), dir.join("ggml-gone.bin"))
+            .unwrap();
+        fs::create_dir_all(dir.join("ggml-dir.bin")).unwrap();
+
+        let models = scan_models(&dir).unwrap();
+        assert_eq!(models, [("large".to_string(), dir.join("ggml-large.bin"))]);
+        assert!(
+            models_json(&models).contains("\"size_bytes\":19,"),
+            "Size of the target, not the link"
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn partial_downloads_are_incomplete() {
+        assert!(is_incomplete("small", 0));
+        assert!(is_incomplete("small", 200_000_000));
+        assert!(is_incomplete("base.en", 100_000_000));
+        assert!(!is_incomplete("small", 487_601_967));
+        assert!(!is_incomplete("large-v3-turbo", 1_624_555_275));
+        // Unknown names (quantized, fine-tuned) are never flagged
+        assert!(!is_incomplete("small-q5_1", 1000));
+        assert!(!is_incomplete("fake", 4));
+    }
+
+    #[test]
+    fn english_only_detection() {
+        assert!(is_english_only("base.en"));
+        assert!(is_english_only("ggml-base.en.bin"));
+        assert!(is_english_only("/models/ggml-small.en.bin"));
+        assert!(is_english_only("ggml-small.en-q5_1.bin"));
+        assert!(!is_english_only("base"));
+        assert!(!is_english_only("ggml-large-v3.bin"));
+        assert!(!is_english_only("/home/en/models/ggml-small.bin"));
+        assert!(!is_english_only("ggml-large-v3-turbo-encoder.bin"));
+    }
+
+    #[test]
+    fn model_language_detection() {
+        assert_eq!(model_language("ggml-base.en.bin"), Some("en"));
+        assert_eq!(model_language("small.en"), Some("en"));
+        assert_eq!(model_language("/models/ggml-small.fr.bin"), Some("fr"));
+        assert_eq!(model_language("whisper-large-v3-fr-q5_0.bin"), Some("fr"));
+        assert_eq!(model_language("ggml-medium_de.bin"), Some("de"));
+        assert_eq!(model_language("ggml-medium-French.bin"), Some("fr"));
+        assert_eq!(model_language("ggml-large-v3-turbo-german.bin"), Some("de"));
+        // Multilingual models, and a language-looking directory, don't count
+        assert_eq!(model_language("ggml-large-v3-turbo-q5_0.bin"), None);
+        assert_eq!(model_language("ggml-tiny.bin"), None);
+        assert_eq!(model_language("/home/fr/models/ggml-small.bin"), None);
+    }
+
     #[test]
     fn scan_models_creates_missing_dir() {
         let dir = std::env::temp_dir().join("space-stt-test-missing");
@@ -123,4 +354,110 @@ mod tests {
 
         let _ = fs::remove_dir_all(&dir);
     }
+
+    #[test]
+    fn explicit_models_dir_overrides_the_search_order() {
+        let dir = std::env::temp_dir().join("space-stt-test-models-dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ggml-small.bin"), b"fake").unwrap();
+
+        assert_eq!(models_dir(Some(&dir)), dir);
+        assert_eq!(models_dir(None), default_models_dir());
+        assert_eq!(
+            resolve_model_path("small", &dir),
+            dir.join("ggml-small.bin")
+        );
+        assert_eq!(
+            resolve_model_path("ggml-small.bin", &dir),
+            dir.join("ggml-small.bin")
+        );
+        // Missing models still point into the given dir, for a clear error
+        assert_eq!(resolve_model_path("medium", &dir), dir.join("medium"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn xdg_data_home_replaces_local_share() {
+        let home = Some(OsString::from("/home/me"));
+        assert_eq!(
+            xdg_models_dir(Some("/data".into()), home.clone()),
+            Some(PathBuf::from("/data/space_tts/models"))
+        );
+        assert_eq!(
+            xdg_models_dir(None, home.clone()),
+            Some(PathBuf::from("/home/me/.local/share/space_tts/models"))
+        );
+        // Relative (or empty) values are invalid and ignored
+        assert_eq!(
+            xdg_models_dir(Some("data".into()), home.clone()),
+            Some(PathBuf::from("/home/me/.local/share/space_tts/models"))
+        );
+        assert_eq!(
+            xdg_models_dir(Some("".into()), home),
+            Some(PathBuf::from("/home/me/.local/share/space_tts/models"))
+        );
+        assert_eq!(xdg_models_dir(None, None), None);
+    }
+
+    #[test]
+    fn find_model_accepts_name_file_or_path() {
+        let models = vec![
+            ("base".to_string(), "/m/ggml-base.bin".to_string()),
+            ("small".to_string(), "/m/ggml-small.bin".to_string()),
+        ];
+        assert_eq!(find_model(&models, "small"), Some(1));
+        assert_eq!(find_model(&models, "ggml-small.bin"), Some(1));
+        assert_eq!(find_model(&models, "/m/ggml-base.bin"), Some(0));
+        assert_eq!(find_model(&models, "medium"), None);
+        assert_eq!(find_model(&models, "sma"), None);
+    }
+
+    #[test]
+    fn models_json_lists_size_and_validity() {
+        let dir = std::env::temp_dir().join("space-stt-test-json");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ggml-small.bin"), b"fake").unwrap();
+        fs::write(dir.join("ggml-my\"tune.bin"), b"tuned").unwrap();
+
+        let models = scan_models(&dir).unwrap();
+        let path = |file: &str| dir.join(file).to_string_lossy().replace('"', "\\\"");
+        assert_eq!(
+            models_json(&models),
+            format!(
+                "[{{\"name\":\"my\\\"tune\",\"path\":\"{}\",\"size_bytes\":5,\"valid\":true}},\
+                 {{\"name\":\"small\",\"path\":\"{}\",\"size_bytes\":4,\"valid\":false}}]",
+                path("ggml-my\"tune.bin"),
+                path("ggml-small.bin")
+            )
+        );
+        assert_eq!(models_json::<PathBuf>(&[]), "[]");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn check_allowed_rejects_paths_outside_models_dir() {
+        let root = std::env::temp_dir().join("space-stt-test-allowed");
+        let _ = fs::remove_dir_all(&root);
+        let dir = root.join("models");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("ggml-small.bin"), b"").unwrap();
+        fs::write(dir.join("ggml-base.bin"), b"").unwrap();
+        fs::write(root.join("secret.bin"), b"").unwrap();
+
+        let none: &[String] = &[];
+        assert!(check_allowed(&dir.join("ggml-small.bin"), &dir, none).is_ok());
+        assert!(check_allowed(&root.join("secret.bin"), &dir, none).is_err());
+        assert!(check_allowed(&dir.join("../secret.bin"), &dir, none).is_err());
+        assert!(check_allowed(&dir.join("missing.bin"), &dir, none).is_err());
+
+        let allowed = ["small".to_string()];
+        assert!(check_allowed(&dir.join("ggml-small.bin"), &dir, &allowed).is_ok());
+        assert!(check_allowed(&dir.join("ggml-base.bin"), &dir, &allowed).is_err());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
@@ -0,0 +1,42 @@
+use anyhow::{Result, anyhow};
+use std::any::Any;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+
+/// Run `f`, turning a panic into an error so a worker loop can log it and go on
+/// with its next item instead of dying (e.g. a rare bug inside whisper bindings).
+///
+/// Whatever `f` borrows mutably may be left half-updated; callers only use this
+/// around self-contained units of work.
+pub fn catch<T>(f: impl FnOnce() -> Result<T>) -> Result<T> {
+    catch_unwind(AssertUnwindSafe(f))
+        .unwrap_or_else(|payload| Err(anyhow!("panicked: {}", panic_message(payload.as_ref()))))
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "unknown panic"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn passes_results_through() {
+        assert_eq!(catch(|| Ok(3)).unwrap(), 3);
+        assert!(catch::<()>(|| Err(anyhow!("boom"))).is_err());
+    }
+
+    #[test]
+    fn turns_panic_into_error() {
+        let err = catch::<()>(|| panic!("bad state {}", 7)).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: bad state 7");
+        let err = catch::<()>(|| panic!("static")).unwrap_err();
+        assert_eq!(err.to_string(), "panicked: static");
+    }
+}
@@ -1,34 +1,86 @@
 use anyhow::{Result, bail};
 use std::io::{Read, Write};
 
+use crate::log::Level;
+
+/// Sent in `Ready` and answered with `Hello`; both sides must agree before any
+/// segment is exchanged.
+/// v1 had no version and no sequence numbers (an empty `Ready`), v2 no sample rate,
+/// v3 no `ResetContext`, v4 no `SetLanguage`, v5 no reply to `SetLanguage`,
+/// v6 no `Hello`.
+pub const PROTOCOL_VERSION: u32 = 7;
+
+/// Sample rate the transcribers work at.
+pub const WHISPER_RATE: u32 = 16000;
+
 // --- Client messages ---
 
+#[derive(Debug)]
 pub enum ClientMsg {
-    AudioSegment(Vec<i16>), // tag 0x01, payload = raw i16 LE bytes
+    // tag 0x01, payload = [seq u32 LE][rate u32 LE][raw i16 LE bytes]
+    /// Mono audio at `rate` Hz: `WHISPER_RATE` unless the server is to resample.
+    AudioSegment {
+        seq: u32,
+        rate: u32,
+        samples: Vec<i16>,
+    },
+    // tag 0x02, empty payload
+    /// Listening stopped: forget the text carried over into the next prompt.
+    ResetContext,
+    // tag 0x03, payload = [UTF-8 language code]
+    /// Transcribe the following segments in this language. Answered with
+    /// `Text { seq: 0 }` holding the code, or `Error { seq: 0 }` if refused.
+    SetLanguage { language: String },
+    // tag 0x04, payload = [version u32 LE]
+    /// First message after `Ready`, so the server can turn away a client that
+    /// predates its protocol instead of misreading its segments.
+    Hello { version: u32 },
 }
 
 // --- Server messages ---
 
+/// `seq` echoes the `AudioSegment` a reply belongs to (0 = not tied to a segment).
 #[derive(Debug)]
 pub enum ServerMsg {
-    Ready,        // tag 0x80, length = 0
-    Text(String), // tag 0x81, payload = UTF-8
-    Error(String), // tag 0x82, payload = UTF-8
+    Ready { version: u32 },              // tag 0x80, payload = [version u32 LE]
+    Text { seq: u32, text: String },     // tag 0x81, payload = [seq u32 LE][UTF-8]
+    Error { seq: u32, message: String }, // tag 0x82, payload = [seq u32 LE][UTF-8]
+    Log { level: Level, text: String },  // tag 0x83, payload = [level u8][UTF-8]
 }
 
 // --- Wire format: [tag: u8][length: u32 LE][payload] ---
 
 pub fn write_client_msg(w: &mut impl Write, msg: &ClientMsg) -> Result<()> {
     match msg {
-        ClientMsg::AudioSegment(samples) => {
-            let payload_len = samples.len() * 2; // i16 = 2 bytes
+        ClientMsg::AudioSegment { seq, rate, samples } => {
+            let payload_len = 8 + samples.len() * 2; // seq + rate + i16 = 2 bytes
             w.write_all(&[0x01])?;
             w.write_all(&(payload_len as u32).to_le_bytes())?;
+            w.write_all(&seq.to_le_bytes())?;
+            w.write_all(&rate.to_le_bytes())?;
             for &s in samples {
                 w.write_all(&s.to_le_bytes())?;
             }
             w.flush()?;
         }
+        ClientMsg::ResetContext => {
+            w.write_all(&[0x02])?;
+            w.write_all(&0u32.to_le_bytes())?;
+            w.flush()?;
+        }
+        ClientMsg::SetLanguage { language } => {
+            let payload = language.as_bytes();
+            w.write_all(&[0x03])?;
+            w.write_all(&(payload.len() as u32).to_le_bytes())?;
+            w.write_all(payload)?;
+            w.flush()?;
+        }
+        ClientMsg::Hello { version } => {
+            w.write_all(&[0x04])?;
+            w.write_all(&4u32.to_le_bytes())?;
+            w.write_all(&version.to_le_bytes())?;
+            w.flush()?;
+        }
     }
     Ok(())
 }
@@ -43,16 +95,46 @@ pub fn read_client_msg(r: &mut impl Read) -> Result<ClientMsg> {
 
     match tag[0] {
         0x01 => {
-            if !len.is_multiple_of(2) {
-                bail!("AudioSegment payload length {len} is not a multiple of 2");
+            if len < 8 || !(len - 8).is_multiple_of(2) {
+                bail!(
+                    "AudioSegment payload length {len} is not a sequence number and rate plus i16 samples"
+                );
             }
             let mut payload = vec![0u8; len];
             r.read_exact(&mut payload)?;
-            let samples: Vec<i16> = payload
+            let (seq, rest) = payload.split_at(4);
+            let (rate, samples) = rest.split_at(4);
+            let samples: Vec<i16> = samples
                 .chunks_exact(2)
                 .map(|c| i16::from_le_bytes([c[0], c[1]]))
                 .collect();
-            Ok(ClientMsg::AudioSegment(samples))
+            Ok(ClientMsg::AudioSegment {
+                seq: u32::from_le_bytes(seq.try_into().unwrap()),
+                rate: u32::from_le_bytes(rate.try_into().unwrap()),
+                samples,
+            })
+        }
+        0x02 => {
+            // Skip any payload a later version might add
+            std::io::copy(&mut r.take(len as u64), &mut std::io::sink())?;
+            Ok(ClientMsg::ResetContext)
+        }
+        0x03 => {
+            let mut payload = vec![0u8; len];
+            r.read_exact(&mut payload)?;
+            Ok(ClientMsg::SetLanguage {
+                language: decode_text(payload),
+            })
+        }
+        0x04 => {
+            if len < 4 {
+                bail!("Hello payload length {len} is missing the protocol version");
+            }
+            let mut payload = vec![0u8; len];
+            r.read_exact(&mut payload)?;
+            Ok(ClientMsg::Hello {
+                version: u32::from_le_bytes(payload[..4].try_into().unwrap()),
+            })
         }
         other => bail!("Unknown client message tag: 0x{other:02x}"),
     }
@@ -60,22 +142,24 @@ pub fn read_client_msg(r: &mut impl Read) -> Result<ClientMsg> {
 
 pub fn write_server_msg(w: &mut impl Write, msg: &ServerMsg) -> Result<()> {
     match msg {
-        ServerMsg::Ready => {
+        ServerMsg::Ready { version } => {
             w.write_all(&[0x80])?;
-            w.write_all(&0u32.to_le_bytes())?;
+            w.write_all(&4u32.to_le_bytes())?;
+            w.write_all(&version.to_le_bytes())?;
             w.flush()?;
         }
-        ServerMsg::Text(text) => {
+        ServerMsg::Text { seq, text } => write_seq_text(w, 0x81, *seq, text)?,
+        ServerMsg::Error { seq, message } => write_seq_text(w, 0x82, *seq, message)?,
+        ServerMsg::Log { level, text } => {
             let payload = text.as_bytes();
-            w.write_all(&[0x81])?;
-            w.write_all(&(payload.len() as u32).to_le_bytes())?;
-            w.write_all(payload)?;
-            w.flush()?;
-        }
-        ServerMsg::Error(text) => {
-            let payload = text.as_bytes();
-            w.write_all(&[0x82])?;
-            w.write_all(&(payload.len() as u32).to_le_bytes())?;
+            let level = match level {
+                Level::Debug => 0u8,
+                Level::Info => 1,
+                Level::Warn => 2,
+            };
+            w.write_all(&[0x83])?;
+            w.write_all(&(payload.len() as u32 + 1).to_le_bytes())?;
+            w.write_all(&[level])?;
             w.write_all(payload)?;
             w.flush()?;
         }
@@ -93,104 +177,310 @@ pub fn read_server_msg(r: &mut impl Read) -> Result<ServerMsg> {
 
     match tag[0] {
         0x80 => {
-            if len > 0 {
-                let mut discard = vec![0u8; len];
-                r.read_exact(&mut discard)?;
-            }
-            Ok(ServerMsg::Ready)
-        }
-        0x81 => {
             let mut payload = vec![0u8; len];
             r.read_exact(&mut payload)?;
-            Ok(ServerMsg::Text(String::from_utf8(payload)?))
+            // A v1 server sends an empty Ready
+            let version = match payload.get(..4) {
+                Some(v) => u32::from_le_bytes(v.try_into().unwrap()),
+                None => 1,
+            };
+            Ok(ServerMsg::Ready { version })
+        }
+        0x81 => {
+            let (seq, text) = read_seq_text(r, len)?;
+            Ok(ServerMsg::Text { seq, text })
         }
         0x82 => {
+            let (seq, message) = read_seq_text(r, len)?;
+            Ok(ServerMsg::Error { seq, message })
+        }
+        0x83 => {
+            if len == 0 {
+                bail!("Log message is missing its level byte");
+            }
             let mut payload = vec![0u8; len];
             r.read_exact(&mut payload)?;
-            Ok(ServerMsg::Error(String::from_utf8(payload)?))
+            let level = match payload.remove(0) {
+                0 => Level::Debug,
+                2 => Level::Warn,
+                _ => Level::Info,
+            };
+            Ok(ServerMsg::Log {
+                level,
+                text: decode_text(payload),
+            })
         }
         other => bail!("Unknown server message tag: 0x{other:02x}"),
     }
 }
 
+fn write_seq_text(w: &mut impl Write, tag: u8, seq: u32, text: &str) -> Result<()> {
+    let payload = text.as_bytes();
+    w.write_all(&[tag])?;
+    w.write_all(&(4 + payload.len() as u32).to_le_bytes())?;
+    w.write_all(&seq.to_le_bytes())?;
+    w.write_all(payload)?;
+    w.flush()?;
+    Ok(())
+}
+
+fn read_seq_text(r: &mut impl Read, len: usize) -> Result<(u32, String)> {
+    if len < 4 {
+        bail!("Message payload length {len} is too short for a sequence number");
+    }
+    let mut payload = vec![0u8; len];
+    r.read_exact(&mut payload)?;
+    let text = payload.split_off(4);
+    let seq = u32::from_le_bytes(payload.try_into().unwrap());
+    Ok((seq, decode_text(text)))
+}
+
+/// Decode a text payload, replacing invalid UTF-8 rather than failing:
+/// one malformed message must not tear down the whole session.
+fn decode_text(payload: Vec<u8>) -> String {
+    match String::from_utf8(payload) {
+        Ok(text) => text,
+        Err(e) => {
+            crate::warn!("Server sent invalid UTF-8, replacing malformed bytes");
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Cursor;
+    use std::io::{BufReader, BufWriter, Cursor};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::time::Duration;
 
     #[test]
     fn round_trip_audio_segment() {
         let samples: Vec<i16> = vec![-32768, -1, 0, 1, 32767];
         let mut buf = Vec::new();
-        write_client_msg(&mut buf, &ClientMsg::AudioSegment(samples.clone())).unwrap();
+        let msg = ClientMsg::AudioSegment {
+            seq: 42,
+            rate: 48000,
+            samples: samples.clone(),
+        };
+        write_client_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
         let msg = read_client_msg(&mut cursor).unwrap();
         match msg {
-            ClientMsg::AudioSegment(decoded) => assert_eq!(decoded, samples),
+            ClientMsg::AudioSegment {
+                seq,
+                rate,
+                samples: decoded,
+            } => {
+                assert_eq!(seq, 42);
+                assert_eq!(rate, 48000);
+                assert_eq!(decoded, samples);
+            }
+            other => panic!("Expected AudioSegment, got {other:?}"),
         }
     }
 
     #[test]
     fn round_trip_audio_segment_empty() {
-        let samples: Vec<i16> = vec![];
         let mut buf = Vec::new();
-        write_client_msg(&mut buf, &ClientMsg::AudioSegment(samples.clone())).unwrap();
+        let msg = ClientMsg::AudioSegment {
+            seq: 1,
+            rate: WHISPER_RATE,
+            samples: vec![],
+        };
+        write_client_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
         let msg = read_client_msg(&mut cursor).unwrap();
         match msg {
-            ClientMsg::AudioSegment(decoded) => assert_eq!(decoded, samples),
+            ClientMsg::AudioSegment { seq, samples, .. } => {
+                assert_eq!(seq, 1);
+                assert!(samples.is_empty());
+            }
+            other => panic!("Expected AudioSegment, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip_reset_context() {
+        let mut buf = Vec::new();
+        write_client_msg(&mut buf, &ClientMsg::ResetContext).unwrap();
+        write_client_msg(
+            &mut buf,
+            &ClientMsg::AudioSegment {
+                seq: 2,
+                rate: WHISPER_RATE,
+                samples: vec![5],
+            },
+        )
+        .unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        assert!(matches!(
+            read_client_msg(&mut cursor).unwrap(),
+            ClientMsg::ResetContext
+        ));
+        assert!(matches!(
+            read_client_msg(&mut cursor).unwrap(),
+            ClientMsg::AudioSegment { seq: 2, .. }
+        ));
+    }
+
+    #[test]
+    fn round_trip_set_language() {
+        let mut buf = Vec::new();
+        let msg = ClientMsg::SetLanguage {
+            language: "fr".to_string(),
+        };
+        write_client_msg(&mut buf, &msg).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        match read_client_msg(&mut cursor).unwrap() {
+            ClientMsg::SetLanguage { language } => assert_eq!(language, "fr"),
+            other => panic!("Expected SetLanguage, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn round_trip_hello() {
+        let mut buf = Vec::new();
+        let msg = ClientMsg::Hello {
+            version: PROTOCOL_VERSION,
+        };
+        write_client_msg(&mut buf, &msg).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        match read_client_msg(&mut cursor).unwrap() {
+            ClientMsg::Hello { version } => assert_eq!(version, PROTOCOL_VERSION),
+            other => panic!("Expected Hello, got {other:?}"),
         }
     }
 
+    #[test]
+    fn hello_without_version_errors() {
+        let buf = vec![0x04, 0, 0, 0, 0];
+        let mut cursor = Cursor::new(buf);
+        assert!(read_client_msg(&mut cursor).is_err());
+    }
+
     #[test]
     fn round_trip_ready() {
         let mut buf = Vec::new();
-        write_server_msg(&mut buf, &ServerMsg::Ready).unwrap();
+        let msg = ServerMsg::Ready {
+            version: PROTOCOL_VERSION,
+        };
+        write_server_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
-        let msg = read_server_msg(&mut cursor).unwrap();
-        assert!(matches!(msg, ServerMsg::Ready));
+        match read_server_msg(&mut cursor).unwrap() {
+            ServerMsg::Ready { version } => assert_eq!(version, PROTOCOL_VERSION),
+            other => panic!("Expected Ready, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn empty_ready_is_protocol_v1() {
+        let buf = vec![0x80, 0, 0, 0, 0];
+        let mut cursor = Cursor::new(buf);
+        match read_server_msg(&mut cursor).unwrap() {
+            ServerMsg::Ready { version } => assert_eq!(version, 1),
+            other => panic!("Expected Ready, got {other:?}"),
+        }
     }
 
     #[test]
     fn round_trip_text() {
         let text = "Bonjour, ça va bien !".to_string();
         let mut buf = Vec::new();
-        write_server_msg(&mut buf, &ServerMsg::Text(text.clone())).unwrap();
+        let msg = ServerMsg::Text {
+            seq: 7,
+            text: text.clone(),
+        };
+        write_server_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
         let msg = read_server_msg(&mut cursor).unwrap();
         match msg {
-            ServerMsg::Text(decoded) => assert_eq!(decoded, text),
+            ServerMsg::Text { seq, text: decoded } => {
+                assert_eq!(seq, 7);
+                assert_eq!(decoded, text);
+            }
             other => panic!("Expected Text, got {other:?}"),
         }
     }
 
     #[test]
     fn round_trip_error() {
-        let text = "model not found".to_string();
+        let message = "model not found".to_string();
         let mut buf = Vec::new();
-        write_server_msg(&mut buf, &ServerMsg::Error(text.clone())).unwrap();
+        let msg = ServerMsg::Error {
+            seq: u32::MAX,
+            message: message.clone(),
+        };
+        write_server_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
         let msg = read_server_msg(&mut cursor).unwrap();
         match msg {
-            ServerMsg::Error(decoded) => assert_eq!(decoded, text),
+            ServerMsg::Error {
+                seq,
+                message: decoded,
+            } => {
+                assert_eq!(seq, u32::MAX);
+                assert_eq!(decoded, message);
+            }
             other => panic!("Expected Error, got {other:?}"),
         }
     }
 
+    #[test]
+    fn round_trip_log() {
+        let msg = ServerMsg::Log {
+            level: Level::Warn,
+            text: "Transcription error, retrying once".to_string(),
+        };
+        let mut buf = Vec::new();
+        write_server_msg(&mut buf, &msg).unwrap();
+
+        let mut cursor = Cursor::new(buf);
+        match read_server_msg(&mut cursor).unwrap() {
+            ServerMsg::Log { level, text } => {
+                assert_eq!(level, Level::Warn);
+                assert_eq!(text, "Transcription error, retrying once");
+            }
+            other => panic!("Expected Log, got {other:?}"),
+        }
+    }
+
     #[test]
     fn round_trip_text_empty() {
         let mut buf = Vec::new();
-        write_server_msg(&mut buf, &ServerMsg::Text(String::new())).unwrap();
+        let msg = ServerMsg::Text {
+            seq: 0,
+            text: String::new(),
+        };
+        write_server_msg(&mut buf, &msg).unwrap();
 
         let mut cursor = Cursor::new(buf);
         let msg = read_server_msg(&mut cursor).unwrap();
         match msg {
-            ServerMsg::Text(decoded) => assert_eq!(decoded, ""),
+            ServerMsg::Text { text, .. } => assert_eq!(text, ""),
+            other => panic!("Expected Text, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn invalid_utf8_text_is_lossy() {
+        // seq 0, then "ok" + invalid byte + "!" — must decode, not error
+        let payload = [0, 0, 0, 0, b'o', b'k', 0xFF, b'!'];
+        let mut buf = vec![0x81];
+        buf.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+        buf.extend_from_slice(&payload);
+
+        let mut cursor = Cursor::new(buf);
+        match read_server_msg(&mut cursor).unwrap() {
+            ServerMsg::Text { text, .. } => assert_eq!(text, "ok\u{FFFD}!"),
             other => panic!("Expected Text, got {other:?}"),
         }
     }
@@ -198,22 +488,49 @@ mod tests {
     #[test]
     fn multiple_messages_in_stream() {
         let mut buf = Vec::new();
-        write_server_msg(&mut buf, &ServerMsg::Ready).unwrap();
-        write_server_msg(&mut buf, &ServerMsg::Text("hello".into())).unwrap();
-        write_server_msg(&mut buf, &ServerMsg::Error("oops".into())).unwrap();
+        write_server_msg(&mut buf, &ServerMsg::Ready { version: 2 }).unwrap();
+        let text = ServerMsg::Text {
+            seq: 1,
+            text: "hello".into(),
+        };
+        write_server_msg(&mut buf, &text).unwrap();
+        let error = ServerMsg::Error {
+            seq: 2,
+            message: "oops".into(),
+        };
+        write_server_msg(&mut buf, &error).unwrap();
 
         let mut cursor = Cursor::new(buf);
-        assert!(matches!(read_server_msg(&mut cursor).unwrap(), ServerMsg::Ready));
+        assert!(matches!(
+            read_server_msg(&mut cursor).unwrap(),
+            ServerMsg::Ready { version: 2 }
+        ));
         match read_server_msg(&mut cursor).unwrap() {
-            ServerMsg::Text(t) => assert_eq!(t, "hello"),
+            ServerMsg::Text { seq, text } => assert_eq!((seq, text.as_str()), (1, "hello")),
             other => panic!("Expected Text, got {other:?}"),
         }
         match read_server_msg(&mut cursor).unwrap() {
-            ServerMsg::Error(e) => assert_eq!(e, "oops"),
+            ServerMsg::Error { seq, message } => assert_eq!((seq, message.as_str()), (2, "oops")),
             other => panic!("Expected Error, got {other:?}"),
         }
     }
 
+    #[test]
+    fn audio_segment_without_seq_errors() {
+        // v1 framing: 1 sample, no sequence number
+        let buf = vec![0x01, 2, 0, 0, 0, 0x01, 0x00];
+        let mut cursor = Cursor::new(buf);
+        assert!(read_client_msg(&mut cursor).is_err());
+    }
+
+    #[test]
+    fn audio_segment_without_rate_errors() {
+        // v2 framing: sequence number and 1 sample, no rate
+        let buf = vec![0x01, 6, 0, 0, 0, 7, 0, 0, 0, 0x01, 0x00];
+        let mut cursor = Cursor::new(buf);
+        assert!(read_client_msg(&mut cursor).is_err());
+    }
+
     #[test]
     fn unknown_client_tag_errors() {
         let buf = vec![0xFF, 0, 0, 0, 0]; // unknown tag, length 0
@@ -227,4 +544,69 @@ mod tests {
         let mut cursor = Cursor::new(buf);
         assert!(read_server_msg(&mut cursor).is_err());
     }
+
+    /// Unlike a `Cursor`, a real stream only delivers what the buffered writer
+    /// flushed: a missing flush shows up here as a read timeout, not a pass.
+    #[test]
+    fn round_trip_over_loopback_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let timeout = Some(Duration::from_secs(5));
+
+        // Server side, buffered like server.rs
+        let server = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            stream.set_read_timeout(timeout).unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut writer = BufWriter::new(stream);
+            let ready = ServerMsg::Ready {
+                version: PROTOCOL_VERSION,
+            };
+            write_server_msg(&mut writer, &ready).unwrap();
+
+            let mut received = Vec::new();
+            while let Ok(ClientMsg::AudioSegment { seq, samples, .. }) =
+                read_client_msg(&mut reader)
+            {
+                received.push(seq);
+                let text = format!("{} samples", samples.len());
+                write_server_msg(&mut writer, &ServerMsg::Text { seq, text }).unwrap();
+            }
+            received
+        });
+
+        // Client side, buffered like remote.rs: one segment at a time, reply awaited
+        let stream = TcpStream::connect(addr).unwrap();
+        stream.set_read_timeout(timeout).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut writer = BufWriter::new(stream);
+        assert!(matches!(
+            read_server_msg(&mut reader).unwrap(),
+            ServerMsg::Ready {
+                version: PROTOCOL_VERSION
+            }
+        ));
+
+        // Sizes around and well above BufWriter's 8 KiB buffer
+        for (seq, len) in [(1, 10), (2, 4096), (3, 50_000)] {
+            let samples = vec![seq as i16; len];
+            let msg = ClientMsg::AudioSegment {
+                seq,
+                rate: WHISPER_RATE,
+                samples,
+            };
+            write_client_msg(&mut writer, &msg).unwrap();
+            match read_server_msg(&mut reader).unwrap() {
+                ServerMsg::Text { seq: reply, text } => {
+                    assert_eq!(reply, seq);
+                    assert_eq!(text, format!("{len} samples"));
+                }
+                other => panic!("Expected Text, got {other:?}"),
+            }
+        }
+
+        // EOF ends the server loop
+        writer.get_ref().shutdown(Shutdown::Write).unwrap();
+        assert_eq!(server.join().unwrap(), [1, 2, 3]);
+    }
 }
@@ -0,0 +1,176 @@
+use anyhow::{Context, Result, bail};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// A speech-to-text backend: whisper in-process on the server, the SSH link on
+/// the client, or any external engine through `SubprocessTranscriber`.
+pub trait Transcriber: Send {
+    /// Transcribe a segment. Returns the texts of every segment completed by this
+    /// call, in order: usually just this one, none while a remote link is down,
+    /// several once it is back and buffered segments are flushed.
+    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>>;
+
+    /// Retry work left over from earlier calls (e.g. buffered segments); called when idle.
+    fn flush_pending(&mut self) -> Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Forget text carried over from earlier segments (`--context-carryover`);
+    /// called when listening stops.
+    fn reset_context(&mut self) {}
+
+    /// Transcribe the following segments in `language` (a validated code).
+    fn set_language(&mut self, language: &str) -> Result<()> {
+        bail!("This backend can't change language to '{language}' mid-session")
+    }
+
+    /// Segments accepted but not transcribed yet (e.g. buffered while a remote
+    /// link is down), handed over when this transcriber is replaced.
+    fn take_pending(&mut self) -> Vec<Vec<i16>> {
+        Vec::new()
+    }
+}
+
+/// Runs an external command once per segment: the audio (16 kHz mono, signed
+/// 16-bit little-endian PCM) is written to its stdin and its stdout, trimmed,
+/// is the transcription.
+///
+/// The command goes through `sh -c`, with `{model}` and `{language}` replaced by
+/// the (shell-quoted) model and language chosen for the session, e.g.
+/// `my-stt --model {model} --lang {language} -`.
+pub struct SubprocessTranscriber {
+    template: String,
+    model: String,
+    command: String,
+}
+
+impl SubprocessTranscriber {
+    pub fn new(template: &str, model: &str, language: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            model: model.to_string(),
+            command: command_line(template, model, language),
+        }
+    }
+}
+
+fn command_line(template: &str, model: &str, language: &str) -> String {
+    template
+        .replace("{model}", &shell_quote(model))
+        .replace("{language}", &shell_quote(language))
+}
+
+impl Transcriber for SubprocessTranscriber {
+    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>> {
+        let mut child = Command::new("sh")
+            .args(["-c", &self.command])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to run backend command: {}", self.command))?;
+
+        let pcm: Vec<u8> = audio_i16.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let mut stdin = child.stdin.take().context("backend stdin not available")?;
+        // Feed stdin from another thread so a chatty command can't deadlock on a full pipe
+        let output = std::thread::scope(|scope| {
+            scope.spawn(move || {
+                // The command may legitimately stop reading early (broken pipe)
+                let _ = stdin.write_all(&pcm);
+            });
+            child.wait_with_output()
+        })?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            bail!(
+                "Backend command failed ({}): {}",
+                output.status,
+                stderr.trim()
+            );
+        }
+        let text = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        Ok(vec![text])
+    }
+
+    fn set_language(&mut self, language: &str) -> Result<()> {
+        self.command = command_line(&self.template, &self.model, language);
+        Ok(())
+    }
+}
+
+/// How aggressively the server strips Whisper hallucinations from the output
+/// (`--filter-level`, parsed on the client too).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FilterLevel {
+    /// Keep the raw Whisper output.
+    Off,
+    /// Only drop outputs that are entirely a known hallucination.
+    Conservative,
+    /// Also strip trailing artifacts and repetitive output.
+    #[default]
+    Aggressive,
+}
+
+impl FilterLevel {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "conservative" => Ok(Self::Conservative),
+            "aggressive" => Ok(Self::Aggressive),
+            other => {
+                bail!("Unknown filter level '{other}' (expected off, conservative or aggressive)")
+            }
+        }
+    }
+}
+
+/// Quote `s` as a single `sh` word.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_level_parse() {
+        assert_eq!(FilterLevel::parse("off").unwrap(), FilterLevel::Off);
+        assert_eq!(
+            FilterLevel::parse("conservative").unwrap(),
+            FilterLevel::Conservative
+        );
+        assert_eq!(
+            FilterLevel::parse("aggressive").unwrap(),
+            FilterLevel::Aggressive
+        );
+        assert!(FilterLevel::parse("max").is_err());
+    }
+
+    #[test]
+    fn fills_template_placeholders() {
+        let t = SubprocessTranscriber::new("stt -m {model} -l {language}", "/m/it's.bin", "fr");
+        assert_eq!(t.command, r"stt -m '/m/it'\''s.bin' -l 'fr'");
+    }
+
+    #[test]
+    fn pipes_pcm_in_and_reads_text_back() {
+        let mut t = SubprocessTranscriber::new("echo {language} $(wc -c)", "", "de");
+        assert_eq!(t.transcribe(&[1, 2, 3]).unwrap(), vec!["de 6"]);
+    }
+
+    #[test]
+    fn set_language_rewrites_the_command() {
+        let mut t = SubprocessTranscriber::new("stt -m {model} -l {language}", "m.bin", "en");
+        t.set_language("fr").unwrap();
+        assert_eq!(t.command, "stt -m 'm.bin' -l 'fr'");
+    }
+
+    #[test]
+    fn failing_command_is_an_error() {
+        let mut t = SubprocessTranscriber::new("echo oops >&2; exit 3", "", "en");
+        let err = t.transcribe(&[0; 10]).unwrap_err().to_string();
+        assert!(err.contains("oops"), "{err}");
+    }
+}
@@ -0,0 +1,94 @@
+use anyhow::{Context, Result, bail};
+
+/// Parse a `--cpu-affinity` list such as `2,3` or `0-3,6`, checking every
+/// core exists (`cpu_count` cores, numbered from 0).
+pub fn parse_cores(list: &str, cpu_count: usize) -> Result<Vec<usize>> {
+    let mut cores = Vec::new();
+    for part in list.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (first.trim(), last.trim()),
+            None => (part, part),
+        };
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .with_context(|| format!("Invalid core '{s}' in --cpu-affinity"))
+        };
+        let (first, last) = (parse(first)?, parse(last)?);
+        if first > last {
+            bail!("Invalid core range '{part}' in --cpu-affinity");
+        }
+        if last >= cpu_count {
+            bail!(
+                "--cpu-affinity: core {last} does not exist (cores 0-{})",
+                cpu_count - 1
+            );
+        }
+        cores.extend(first..=last);
+    }
+    cores.sort_unstable();
+    cores.dedup();
+    if cores.is_empty() {
+        bail!("--cpu-affinity needs at least one core");
+    }
+    Ok(cores)
+}
+
+/// Pin the process to `list`'s cores. Called before the model loads, so every
+/// thread started afterwards (whisper's workers included) inherits the mask.
+#[cfg(target_os = "linux")]
+pub fn pin(list: &str) -> Result<()> {
+    // SAFETY: sysconf has no preconditions
+    let cpu_count = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    let cpu_count = usize::try_from(cpu_count)
+        .unwrap_or(1)
+        .clamp(1, libc::CPU_SETSIZE as usize);
+    let cores = parse_cores(list, cpu_count)?;
+
+    // SAFETY: cpu_set_t is plain data, zeroed is an empty set; CPU_SET is
+    // bounds-checked by parse_cores (cores < cpu_count <= CPU_SETSIZE)
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for &core in &cores {
+            libc::CPU_SET(core, &mut set);
+        }
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        bail!(
+            "Failed to set CPU affinity: {}",
+            std::io::Error::last_os_error()
+        );
+    }
+    space_tts_common::info!("Pinned to cores {cores:?}");
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin(list: &str) -> Result<()> {
+    // Still reject a bad list, so it fails the same way everywhere
+    let cpu_count = std::thread::available_parallelism().map_or(1, |n| n.get());
+    parse_cores(list, cpu_count)?;
+    space_tts_common::warn!("--cpu-affinity is only supported on Linux, ignored.");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_lists_and_ranges() {
+        assert_eq!(parse_cores("2,3", 8).unwrap(), vec![2, 3]);
+        assert_eq!(parse_cores("0-3, 6", 8).unwrap(), vec![0, 1, 2, 3, 6]);
+        assert_eq!(parse_cores("5,4-5", 8).unwrap(), vec![4, 5]);
+    }
+
+    #[test]
+    fn rejects_missing_cores_and_bad_input() {
+        let err = parse_cores("6-8", 8).unwrap_err().to_string();
+        assert!(err.contains("core 8"), "{err}");
+        assert!(parse_cores("", 8).is_err());
+        assert!(parse_cores("3-1", 8).is_err());
+        assert!(parse_cores("a", 8).is_err());
+    }
+}
@@ -1,42 +1,73 @@
+mod affinity;
+mod resample;
 mod server;
 mod transcribe;
 
 use anyhow::Result;
-
-fn find_arg_value(args: &[String], flag: &str) -> Option<String> {
-    args.iter()
-        .position(|a| a == flag)
-        .and_then(|i| args.get(i + 1))
-        .cloned()
-}
+use space_tts_common::args::{
+    find_arg_value, find_optional_arg_value, has_flag, parse_arg_value, parse_positive_secs,
+};
+use std::path::Path;
+use std::time::Duration;
 
 fn main() -> Result<()> {
     let args: Vec<String> = std::env::args().collect();
 
     // Parse --debug flag
-    if args.iter().any(|a| a == "--debug") {
+    if has_flag(&args, "--debug") {
         space_tts_common::log::set_debug(true);
     }
 
-    // --list-models: print local models and exit
-    if args.iter().any(|a| a == "--list-models") {
+    // --allowed-models [a,b]: only serve models from the models dir (and this list)
+    let allowed_models = find_optional_arg_value(&args, "--allowed-models").map(|list| {
+        list.unwrap_or_default()
+            .split(',')
+            .map(|m| m.trim().to_string())
+            .filter(|m| !m.is_empty())
+            .collect::<Vec<_>>()
+    });
+
+    // --models-dir <path>: look for models there instead of the default locations
+    let models_dir = space_tts_common::models::models_dir(
+        find_arg_value(&args, "--models-dir")
+            .as_deref()
+            .map(Path::new),
+    );
+
+    // --list-models [--json]: print local models and exit
+    if has_flag(&args, "--list-models") {
         use std::io::IsTerminal;
-        let models_dir = space_tts_common::models::default_models_dir();
-        let models = space_tts_common::models::scan_models(&models_dir)?;
-        if std::io::stdout().is_terminal() {
+        let mut models = space_tts_common::models::scan_models(&models_dir)?;
+        if let Some(allowed) = &allowed_models {
+            models.retain(|(_, path)| {
+                space_tts_common::models::check_allowed(path, &models_dir, allowed).is_ok()
+            });
+        }
+        if has_flag(&args, "--json") {
+            // Scripting: richer details, one JSON array
+            println!("{}", space_tts_common::models::models_json(&models));
+        } else if std::io::stdout().is_terminal() {
             // Interactive: human-friendly output
             if models.is_empty() {
                 println!("No models found in {}", models_dir.display());
             } else {
                 println!("Available models ({}):\n", models_dir.display());
-                for (name, _) in &models {
-                    println!("  space_tts_server --model {name} --language fr");
+                for (name, path) in &models {
+                    if space_tts_common::models::is_incomplete_file(name, path) {
+                        println!("  {name}: (incomplete) partial download, re-download it");
+                    } else {
+                        println!("  space_tts_server --model {name} --language fr");
+                    }
                 }
             }
         } else {
-            // Piped (e.g. SSH): machine-parseable name\tpath
+            // Piped (e.g. SSH): machine-parseable name\tpath; the name is display-only
             for (name, path) in &models {
-                println!("{name}\t{}", path.display());
+                if space_tts_common::models::is_incomplete_file(name, path) {
+                    println!("{name} (incomplete)\t{}", path.display());
+                } else {
+                    println!("{name}\t{}", path.display());
+                }
             }
         }
         return Ok(());
@@ -44,8 +75,71 @@ fn main() -> Result<()> {
 
     // Default: run as server (requires --model)
     let model_arg = find_arg_value(&args, "--model")
-        .ok_or_else(|| anyhow::anyhow!("Usage: space_tts_server --model <name> --language <lang>\n       space_tts_server --list-models"))?;
-    let model = space_tts_common::models::resolve_model_path(&model_arg);
-    let language = find_arg_value(&args, "--language").unwrap_or_else(|| "en".to_string());
-    server::run(&model.to_string_lossy(), &language)
+        .ok_or_else(|| anyhow::anyhow!("Usage: space_tts_server --model <name> --language <lang> [--no-warmup] [--warmup-ms <ms>] [--warmup-audio silence|speech] [--forward-logs] [--filter-level off|conservative|aggressive] [--max-len <n>] [--split-on-word] [--token-timestamps] [--temperature <t>] [--temperature-inc <t>] [--logprob-thold <p>] [--decode-preset strict|loose] [--entropy-thold <e>] [--min-segment-ms <ms>] [--pad-to-ms <ms>] [--transcribe-timeout <s>] [--context-carryover] [--language-fallback <lang>] [--min-language-confidence <p>] [--cpu-affinity <cores>] [--allowed-models [a,b]] [--backend-cmd <cmd>] [--models-dir <path>]\n       space_tts_server --list-models [--json] [--models-dir <path>]"))?;
+    let model = space_tts_common::models::resolve_model_path(&model_arg, &models_dir);
+    // Any code whisper knows, not just the client menu's
+    let language = space_tts_common::languages::validate(
+        &find_arg_value(&args, "--language").unwrap_or_else(|| "en".to_string()),
+    )?;
+    if has_flag(&args, "--min-language-confidence") && !has_flag(&args, "--language-fallback") {
+        anyhow::bail!("--min-language-confidence only applies with --language-fallback");
+    }
+    let mut transcribe = transcribe::TranscribeParams {
+        filter_level: find_arg_value(&args, "--filter-level")
+            .map(|v| transcribe::FilterLevel::parse(&v))
+            .transpose()?
+            .unwrap_or_default(),
+        token_timestamps: has_flag(&args, "--token-timestamps"),
+        max_len: parse_arg_value(&args, "--max-len")?.unwrap_or(0),
+        split_on_word: has_flag(&args, "--split-on-word"),
+        temperature: parse_arg_value(&args, "--temperature")?,
+        temperature_inc: parse_arg_value(&args, "--temperature-inc")?,
+        logprob_thold: parse_arg_value(&args, "--logprob-thold")?,
+        entropy_thold: parse_arg_value(&args, "--entropy-thold")?,
+        timeout: parse_positive_secs(&args, "--transcribe-timeout")?
+            .map(Duration::try_from_secs_f64)
+            .transpose()?,
+        context_carryover: has_flag(&args, "--context-carryover"),
+        language_fallback: find_arg_value(&args, "--language-fallback")
+            .map(|code| -> Result<_> {
+                Ok(transcribe::LanguageFallback {
+                    language: space_tts_common::languages::validate_fallback(&code)?,
+                    min_confidence: parse_arg_value(&args, "--min-language-confidence")?
+                        .map(space_tts_common::languages::validate_confidence)
+                        .transpose()?
+                        .unwrap_or(transcribe::DEFAULT_MIN_LANGUAGE_CONFIDENCE),
+                })
+            })
+            .transpose()?,
+    };
+    if let Some(preset) = find_arg_value(&args, "--decode-preset") {
+        transcribe::DecodePreset::parse(&preset)?.fill(&mut transcribe);
+    }
+    // Before any thread starts, so whisper's workers inherit the mask
+    if let Some(cores) = find_arg_value(&args, "--cpu-affinity") {
+        affinity::pin(&cores)?;
+    }
+    let options = server::ServerOptions {
+        warmup: !has_flag(&args, "--no-warmup"),
+        warmup_samples: ms_to_samples(&args, "--warmup-ms", 1000)?,
+        warmup_audio: find_arg_value(&args, "--warmup-audio")
+            .map(|v| server::WarmupAudio::parse(&v))
+            .transpose()?
+            .unwrap_or_default(),
+        forward_logs: has_flag(&args, "--forward-logs"),
+        backend_cmd: find_arg_value(&args, "--backend-cmd"),
+        allowed_models,
+        models_dir,
+        min_segment_samples: ms_to_samples(&args, "--min-segment-ms", 100)?,
+        pad_samples: ms_to_samples(&args, "--pad-to-ms", 0)?,
+        transcribe,
+    };
+    server::run(&model.to_string_lossy(), &language, &options)
+}
+
+/// A duration flag in milliseconds (`default_ms` if absent), as 16 kHz samples.
+fn ms_to_samples(args: &[String], flag: &str, default_ms: usize) -> Result<usize> {
+    let ms = parse_arg_value::<usize>(args, flag)?.unwrap_or(default_ms);
+    ms.checked_mul(16)
+        .ok_or_else(|| anyhow::anyhow!("Value for {flag} is too large: {ms}"))
 }
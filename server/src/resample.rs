@@ -0,0 +1,110 @@
+use anyhow::Result;
+use audioadapter_buffers::direct::SequentialSliceOfVecs;
+use rubato::{
+    Async, FixedAsync, Resampler, SincInterpolationParameters, SincInterpolationType,
+    WindowFunction,
+};
+
+/// Resample a whole mono segment from `from` Hz to `to` Hz (the server side of
+/// `--server-resample`), with the filter the client streams its audio through.
+pub fn resample(samples: &[i16], from: u32, to: u32) -> Result<Vec<i16>> {
+    if from == to || samples.is_empty() {
+        return Ok(samples.to_vec());
+    }
+
+    let params = SincInterpolationParameters {
+        sinc_len: 128,
+        f_cutoff: 0.95,
+        interpolation: SincInterpolationType::Quadratic,
+        oversampling_factor: 256,
+        window: WindowFunction::Blackman2,
+    };
+    let mut resampler = Async::<f64>::new_sinc(
+        to as f64 / from as f64,
+        1.1,
+        &params,
+        1024,
+        1,
+        FixedAsync::Input,
+    )
+    .map_err(|e| anyhow::anyhow!("Failed to create resampler: {e}"))?;
+
+    let input_data = vec![
+        samples
+            .iter()
+            .map(|&s| s as f64 / 32768.0)
+            .collect::<Vec<f64>>(),
+    ];
+    let output_len = resampler.process_all_needed_output_len(samples.len());
+    let mut output_data = vec![vec![0.0f64; output_len]];
+    let input = SequentialSliceOfVecs::new(&input_data, 1, samples.len()).unwrap();
+    let mut output = SequentialSliceOfVecs::new_mut(&mut output_data, 1, output_len).unwrap();
+    // Processes the whole segment and trims the filter's delay off the start
+    let (_, produced) = resampler
+        .process_all_into_buffer(&input, &mut output, samples.len(), None)
+        .map_err(|e| anyhow::anyhow!("Resample error: {e}"))?;
+
+    Ok(output_data[0][..produced]
+        .iter()
+        .map(|&s| (s.clamp(-1.0, 1.0) * 32767.0) as i16)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    fn sine(freq: f64, rate: u32, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| (10000.0 * (2.0 * PI * freq * i as f64 / rate as f64).sin()) as i16)
+            .collect()
+    }
+
+    /// Peak amplitude away from the edges.
+    fn peak(samples: &[i16]) -> i16 {
+        let margin = samples.len() / 4;
+        samples[margin..samples.len() - margin]
+            .iter()
+            .map(|s| s.saturating_abs())
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn same_rate_is_a_copy() {
+        let input: Vec<i16> = (0..100).collect();
+        assert_eq!(resample(&input, 16000, 16000).unwrap(), input);
+    }
+
+    #[test]
+    fn output_length_follows_ratio() {
+        assert_eq!(resample(&[0; 48000], 48000, 16000).unwrap().len(), 16000);
+        assert_eq!(resample(&[0; 44100], 44100, 16000).unwrap().len(), 16000);
+        assert_eq!(resample(&[0; 8000], 8000, 16000).unwrap().len(), 16000);
+    }
+
+    #[test]
+    fn speech_band_passes_through() {
+        let output = resample(&sine(1000.0, 48000, 48000), 48000, 16000).unwrap();
+        let amplitude = peak(&output);
+        assert!((9500..=10500).contains(&amplitude), "{amplitude}");
+    }
+
+    #[test]
+    fn frequencies_above_target_nyquist_are_filtered() {
+        // 12 kHz can't exist at 16 kHz: it must not alias back in
+        let output = resample(&sine(12000.0, 48000, 48000), 48000, 16000).unwrap();
+        assert!(peak(&output) < 100, "{}", peak(&output));
+    }
+
+    #[test]
+    fn segment_is_not_shifted_by_the_filter_delay() {
+        // A step at the middle of the input stays at the middle of the output
+        let mut input = vec![0; 24000];
+        input.extend([10000; 24000]);
+        let output = resample(&input, 48000, 16000).unwrap();
+        assert!(output[7950].abs() < 500, "{}", output[7950]);
+        assert!(output[8050] > 9500, "{}", output[8050]);
+    }
+}
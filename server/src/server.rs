@@ -1,26 +1,155 @@
 use anyhow::Result;
 use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
 
-use space_tts_common::protocol::{ClientMsg, ServerMsg, read_client_msg, write_server_msg};
-use space_tts_common::{debug, info};
+use space_tts_common::models;
+use space_tts_common::protocol::{
+    ClientMsg, PROTOCOL_VERSION, ServerMsg, WHISPER_RATE, read_client_msg, write_server_msg,
+};
+use space_tts_common::transcriber::{SubprocessTranscriber, Transcriber};
+use space_tts_common::{debug, info, warn};
 
-use crate::transcribe::{LocalTranscriber, Transcriber};
+use crate::resample::resample;
+use crate::transcribe::{LocalTranscriber, PaddedTranscriber, TranscribeParams};
 
-pub fn run(model_path: &str, language: &str) -> Result<()> {
-    info!("Server mode: loading model {model_path}...");
+/// What the warm-up transcription runs on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WarmupAudio {
+    #[default]
+    Silence,
+    /// Synthetic voiced syllables: the decoder produces tokens, which primes
+    /// more of the graph than silence on some GPU backends.
+    Speech,
+}
+
+impl WarmupAudio {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "silence" => Ok(Self::Silence),
+            "speech" => Ok(Self::Speech),
+            other => anyhow::bail!("Unknown warm-up audio '{other}' (expected silence or speech)"),
+        }
+    }
+
+    /// `samples` of this audio at 16 kHz.
+    fn generate(self, samples: usize) -> Vec<i16> {
+        match self {
+            Self::Silence => vec![0; samples],
+            Self::Speech => synthetic_speech(samples),
+        }
+    }
+}
+
+/// Vowel-like audio: a 120 Hz voice whose harmonics are shaped by the formants
+/// of /a/, /i/ and /u/ in turn, in 250 ms syllables.
+fn synthetic_speech(samples: usize) -> Vec<i16> {
+    use std::f64::consts::PI;
+    const RATE: f64 = WHISPER_RATE as f64;
+    const PITCH: f64 = 120.0;
+    const SYLLABLE: f64 = 0.25;
+    const VOWELS: [(f64, f64); 3] = [(700.0, 1200.0), (300.0, 2300.0), (300.0, 870.0)];
+
+    (0..samples)
+        .map(|i| {
+            let t = i as f64 / RATE;
+            let (f1, f2) = VOWELS[(t / SYLLABLE) as usize % VOWELS.len()];
+            let formant = |f: f64, center: f64| (-((f - center) / 150.0).powi(2)).exp();
+            let voice: f64 = (1..=30)
+                .map(|h| {
+                    let f = PITCH * h as f64;
+                    (formant(f, f1) + 0.5 * formant(f, f2)) * (2.0 * PI * f * t).sin()
+                })
+                .sum();
+            let envelope = (PI * (t % SYLLABLE) / SYLLABLE).sin().powi(2);
+            (6000.0 * envelope * voice).clamp(i16::MIN as f64, i16::MAX as f64) as i16
+        })
+        .collect()
+}
+
+pub struct ServerOptions {
+    /// Transcribe `warmup_samples` of `warmup_audio` before sending Ready (primes the GPU graph).
+    pub warmup: bool,
+    pub warmup_samples: usize,
+    pub warmup_audio: WarmupAudio,
+    pub transcribe: TranscribeParams,
+    /// Send our own log lines to the client as `ServerMsg::Log` instead of stderr.
+    pub forward_logs: bool,
+    /// Segments shorter than this are answered with empty text, without transcribing.
+    pub min_segment_samples: usize,
+    /// Pad shorter segments with leading silence up to this length (0 = off).
+    pub pad_samples: usize,
+    /// Refuse models outside the models directory, and not in this list when non-empty.
+    pub allowed_models: Option<Vec<String>>,
+    /// Where models are looked for (`--models-dir`, or the default search).
+    pub models_dir: PathBuf,
+    /// Transcribe with this external command instead of whisper (see `SubprocessTranscriber`).
+    pub backend_cmd: Option<String>,
+}
+
+pub fn run(model_path: &str, language: &str, options: &ServerOptions) -> Result<()> {
+    if options.forward_logs {
+        space_tts_common::log::set_sink(|level, text| {
+            // Whole message under one lock so it can't interleave with a reply
+            let mut out = std::io::stdout().lock();
+            let msg = ServerMsg::Log {
+                level,
+                text: text.to_string(),
+            };
+            let _ = write_server_msg(&mut out, &msg);
+        });
+    }
+
+    if let Some(allowed) = &options.allowed_models {
+        if let Err(e) = models::check_allowed(Path::new(model_path), &options.models_dir, allowed) {
+            // Tell the client why before exiting, instead of just closing the link
+            let msg = ServerMsg::Error {
+                seq: 0,
+                message: format!("{e:#}"),
+            };
+            let _ = write_server_msg(&mut std::io::stdout().lock(), &msg);
+            return Err(e);
+        }
+    }
+
+    let mut transcriber: Box<dyn Transcriber> = match &options.backend_cmd {
+        Some(cmd) => {
+            info!("Server mode: transcribing with backend command: {cmd}");
+            Box::new(SubprocessTranscriber::new(cmd, model_path, language))
+        }
+        None => {
+            info!("Server mode: loading model {model_path}...");
+            Box::new(LocalTranscriber::new(
+                model_path,
+                language,
+                options.transcribe.clone(),
+            )?)
+        }
+    };
 
-    let mut transcriber = LocalTranscriber::new(model_path, language)?;
+    if options.pad_samples > 0 {
+        transcriber = Box::new(PaddedTranscriber::new(transcriber, options.pad_samples));
+    }
 
-    // Warm-up: transcribe 1s of silence to init GPU graph
-    debug!("Warming up whisper...");
-    let silence = vec![0i16; 16000];
-    let _ = transcriber.transcribe(&silence);
-    debug!("Warm-up complete.");
+    // Warm-up: transcribe 1s of silence (by default) to init GPU graph.
+    // Only affects first-utterance latency, never the results.
+    if options.warmup && options.warmup_samples > 0 {
+        debug!("Warming up whisper...");
+        let audio = options.warmup_audio.generate(options.warmup_samples);
+        let _ = transcriber.transcribe(&audio);
+        // Whatever the warm-up decoded mustn't prime the first real prompt
+        transcriber.reset_context();
+        debug!("Warm-up complete.");
+    } else {
+        debug!("Warm-up skipped (--no-warmup).");
+    }
 
     // Send Ready on stdout
     let stdout = std::io::stdout();
     let mut writer = BufWriter::new(stdout.lock());
-    write_server_msg(&mut writer, &ServerMsg::Ready)?;
+    let ready = ServerMsg::Ready {
+        version: PROTOCOL_VERSION,
+    };
+    write_server_msg(&mut writer, &ready)?;
     writer.flush()?;
 
     info!("Server ready, waiting for audio segments...");
@@ -29,43 +158,141 @@ pub fn run(model_path: &str, language: &str) -> Result<()> {
     let stdin = std::io::stdin();
     let mut reader = BufReader::new(stdin.lock());
 
+    // A client from before Hello sends its first segment straight away, in a
+    // layout this server would misread: turn it away with an explanation.
+    let rejection = match read_client_msg(&mut reader) {
+        Ok(ClientMsg::Hello { version }) if version == PROTOCOL_VERSION => None,
+        Ok(ClientMsg::Hello { version }) => Some(format!(
+            "Client speaks protocol v{version}, this server v{PROTOCOL_VERSION}"
+        )),
+        Err(e) if is_disconnect(&e) => {
+            info!("Client disconnected, shutting down.");
+            return Ok(());
+        }
+        Ok(_) | Err(_) => Some(format!(
+            "Client predates protocol v{PROTOCOL_VERSION} (no Hello), this server v{PROTOCOL_VERSION}"
+        )),
+    };
+    if let Some(reason) = rejection {
+        let message = format!("{reason}: install the same space_tts version on both sides");
+        warn!("{message}");
+        write_server_msg(&mut writer, &ServerMsg::Error { seq: 0, message })?;
+        writer.flush()?;
+        return Ok(());
+    }
+
     loop {
         let msg = match read_client_msg(&mut reader) {
             Ok(msg) => msg,
+            Err(e) if is_disconnect(&e) => {
+                info!("Client disconnected, shutting down.");
+                break;
+            }
             Err(e) => {
-                // EOF or broken pipe = client disconnected
-                let msg = format!("{e}");
-                if msg.contains("unexpected end of file")
-                    || msg.contains("UnexpectedEof")
-                    || msg.contains("broken pipe")
-                {
-                    info!("Client disconnected, shutting down.");
-                    break;
-                }
-                info!("Protocol error: {e}");
+                warn!("Protocol error: {e}");
                 break;
             }
         };
 
         match msg {
-            ClientMsg::AudioSegment(samples) => {
+            ClientMsg::AudioSegment { seq, rate, samples } => {
+                if rate == 0 {
+                    let msg = ServerMsg::Error {
+                        seq,
+                        message: "Audio segment with a sample rate of 0".to_string(),
+                    };
+                    write_server_msg(&mut writer, &msg)?;
+                    writer.flush()?;
+                    continue;
+                }
                 debug!(
-                    "Received audio segment: {} samples ({:.0}ms)",
+                    "Received audio segment #{seq}: {} samples at {rate} Hz ({:.0}ms)",
                     samples.len(),
-                    samples.len() as f64 / 16.0
+                    samples.len() as f64 * 1000.0 / rate as f64
                 );
-
-                let response = match transcriber.transcribe(&samples) {
-                    Ok(text) => ServerMsg::Text(text),
-                    Err(e) => ServerMsg::Error(format!("{e}")),
+                // --server-resample clients send their capture rate
+                let samples = if rate == WHISPER_RATE {
+                    samples
+                } else {
+                    match resample(&samples, rate, WHISPER_RATE) {
+                        Ok(samples) => samples,
+                        Err(e) => {
+                            let msg = ServerMsg::Error {
+                                seq,
+                                message: format!("{e:#}"),
+                            };
+                            write_server_msg(&mut writer, &msg)?;
+                            writer.flush()?;
+                            continue;
+                        }
+                    }
                 };
 
+                if samples.len() < options.min_segment_samples {
+                    debug!("Segment #{seq} too short, not transcribed.");
+                    write_server_msg(
+                        &mut writer,
+                        &ServerMsg::Text {
+                            seq,
+                            text: String::new(),
+                        },
+                    )?;
+                    writer.flush()?;
+                    continue;
+                }
+
+                // A panic (e.g. in whisper-rs) fails this segment, not the whole server
+                let response =
+                    match space_tts_common::panic::catch(|| transcriber.transcribe(&samples)) {
+                        Ok(texts) => ServerMsg::Text {
+                            seq,
+                            text: texts.concat(),
+                        },
+                        Err(e) => ServerMsg::Error {
+                            seq,
+                            message: format!("{e}"),
+                        },
+                    };
+
                 write_server_msg(&mut writer, &response)?;
                 writer.flush()?;
             }
+            ClientMsg::ResetContext => {
+                debug!("Context reset.");
+                transcriber.reset_context();
+            }
+            ClientMsg::SetLanguage { language } => {
+                // Answered either way, so the client only follows a switch that happened
+                let reply = match space_tts_common::languages::validate(&language)
+                    .and_then(|code| transcriber.set_language(&code).map(|()| code))
+                {
+                    Ok(code) => {
+                        info!("Language switched to {code}.");
+                        ServerMsg::Text { seq: 0, text: code }
+                    }
+                    Err(e) => {
+                        warn!("Could not switch language: {e}");
+                        ServerMsg::Error {
+                            seq: 0,
+                            message: format!("{e}"),
+                        }
+                    }
+                };
+                write_server_msg(&mut writer, &reply)?;
+                writer.flush()?;
+            }
+            ClientMsg::Hello { .. } => debug!("Ignoring a repeated Hello."),
         }
     }
 
     info!("Server shutdown complete.");
     Ok(())
 }
+
+/// EOF or broken pipe = client disconnected
+fn is_disconnect(e: &anyhow::Error) -> bool {
+    let msg = format!("{e}");
+    msg.contains("unexpected end of file")
+        || msg.contains("UnexpectedEof")
+        || msg.contains("broken pipe")
+}
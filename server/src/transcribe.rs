@@ -1,58 +1,378 @@
 use anyhow::Result;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-use space_tts_common::warn;
+use space_tts_common::languages;
+pub use space_tts_common::transcriber::FilterLevel;
+use space_tts_common::transcriber::Transcriber;
+use space_tts_common::{debug, warn};
 use whisper_rs::{
-    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperState,
-    convert_integer_to_float_audio,
+    FullParams, SamplingStrategy, WhisperContext, WhisperContextParameters, WhisperError,
+    WhisperState, convert_integer_to_float_audio,
 };
 
-pub trait Transcriber: Send {
-    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<String>;
+/// Ready-made decoding thresholds (`--decode-preset`); explicit flags override them.
+///
+/// - `entropy_thold`: a decode whose token entropy is below this (repetitive,
+///   looping text) counts as failed. Higher is stricter. whisper.cpp default: 2.4.
+/// - `logprob_thold`: a decode whose average token log-probability is below this
+///   counts as failed. Higher is stricter. Default: -1.0.
+///
+/// A failed decode is retried at a higher temperature (see `TranscribeParams`),
+/// so strict thresholds trade latency for fewer hallucinations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodePreset {
+    Strict,
+    Loose,
+}
+
+impl DecodePreset {
+    pub fn parse(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "loose" => Ok(Self::Loose),
+            other => anyhow::bail!("Unknown decode preset '{other}' (expected strict or loose)"),
+        }
+    }
+
+    /// Fill the thresholds `params` doesn't set explicitly.
+    pub fn fill(self, params: &mut TranscribeParams) {
+        let (entropy, logprob) = match self {
+            Self::Strict => (2.8, -0.5),
+            Self::Loose => (2.0, -1.5),
+        };
+        params.entropy_thold.get_or_insert(entropy);
+        params.logprob_thold.get_or_insert(logprob);
+    }
+}
+
+/// Tunables for a whisper run, fixed for the lifetime of a transcriber.
+///
+/// Segment shaping (mostly useful to structured-output consumers, the joined
+/// text is the same either way):
+/// - `max_len` caps each output segment to that many characters (0 = no limit).
+///   whisper.cpp needs token timestamps for this, so a non-zero `max_len`
+///   turns `token_timestamps` on.
+/// - `split_on_word` makes `max_len` break at word boundaries instead of
+///   mid-token; it has no effect when `max_len` is 0.
+///
+/// Temperature fallback (`None` keeps the whisper.cpp default): decoding starts
+/// at `temperature` (0.0) and a result whose average token log-probability is
+/// under `logprob_thold` (-1.0) is decoded again `temperature_inc` (0.2) hotter.
+/// Retries help on hard audio but each one costs a full decoding pass;
+/// a `temperature_inc` of 0 disables them for steady latency.
+///
+/// `timeout` bounds each segment: whisper's abort callback stops a run that
+/// takes longer (e.g. a pathological segment, a wedged GPU driver), the state
+/// is recreated and the segment is answered with empty text.
+///
+/// `context_carryover` appends the last few results to the initial prompt, so
+/// names and style carry over from one segment to the next until the client
+/// resets the context (listening stopped).
+///
+/// `language_fallback` gates `--language auto`: see `LanguageFallback`.
+#[derive(Debug, Clone, Default)]
+pub struct TranscribeParams {
+    pub filter_level: FilterLevel,
+    pub token_timestamps: bool,
+    pub max_len: i32,
+    pub split_on_word: bool,
+    pub temperature: Option<f32>,
+    pub temperature_inc: Option<f32>,
+    pub logprob_thold: Option<f32>,
+    /// Hallucination thresholds, see `DecodePreset` (`None` = whisper.cpp default).
+    pub entropy_thold: Option<f32>,
+    pub timeout: Option<Duration>,
+    pub context_carryover: bool,
+    pub language_fallback: Option<LanguageFallback>,
+}
+
+/// `--language-fallback`: with `--language auto`, segments whose detected
+/// language whisper gives less than `min_confidence` are transcribed in
+/// `language` instead. Short or noisy clips are easily mis-detected.
+#[derive(Debug, Clone)]
+pub struct LanguageFallback {
+    pub language: String,
+    pub min_confidence: f32,
+}
+
+/// Default `--min-language-confidence`.
+pub const DEFAULT_MIN_LANGUAGE_CONFIDENCE: f32 = 0.5;
+
+impl TranscribeParams {
+    fn apply(&self, params: &mut FullParams) {
+        params.set_token_timestamps(self.token_timestamps || self.max_len > 0);
+        params.set_max_len(self.max_len);
+        params.set_split_on_word(self.split_on_word);
+        if let Some(temperature) = self.temperature {
+            params.set_temperature(temperature);
+        }
+        if let Some(inc) = self.temperature_inc {
+            params.set_temperature_inc(inc);
+        }
+        if let Some(thold) = self.logprob_thold {
+            params.set_logprob_thold(thold);
+        }
+        if let Some(thold) = self.entropy_thold {
+            params.set_entropy_thold(thold);
+        }
+    }
 }
 
 pub struct LocalTranscriber {
+    ctx: WhisperContext,
     state: WhisperState,
     language: String,
+    english_only: bool,
+    params: TranscribeParams,
+    /// Recent results, oldest first (with `context_carryover`).
+    context: VecDeque<String>,
+    /// With "auto" and no fallback, the language whisper found in the last
+    /// segment: the next one is prompted in it.
+    detected: Option<&'static str>,
 }
 
 impl LocalTranscriber {
-    pub fn new(model_path: &str, language: &str) -> Result<Self> {
+    pub fn new(model_path: &str, language: &str, params: TranscribeParams) -> Result<Self> {
         let ctx = WhisperContext::new_with_params(model_path, WhisperContextParameters::new())
             .map_err(|e| anyhow::anyhow!("Failed to load whisper model: {e}"))?;
         let state = ctx
             .create_state()
             .map_err(|e| anyhow::anyhow!("Failed to create whisper state: {e}"))?;
         Ok(Self {
+            ctx,
             state,
-            language: language.to_string(),
+            language: model_language(model_path, language).to_string(),
+            english_only: space_tts_common::models::is_english_only(model_path),
+            params,
+            context: VecDeque::new(),
+            detected: None,
         })
     }
+
+    /// Whisper's guess at the language spoken in `audio` (16 kHz mono), with
+    /// its probability. A full encoder pass, which can't be aborted.
+    pub fn detect_language(&mut self, audio: &[f32]) -> Result<(&'static str, f32)> {
+        // Same thread count as whisper.cpp's default for a full run
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get().min(4));
+        self.state
+            .pcm_to_mel(audio, threads)
+            .map_err(|e| anyhow::anyhow!("Spectrogram failed: {e}"))?;
+        let (id, probs) = self
+            .state
+            .lang_detect(0, threads)
+            .map_err(|e| anyhow::anyhow!("Language detection failed: {e}"))?;
+        let code = whisper_rs::get_lang_str(id)
+            .ok_or_else(|| anyhow::anyhow!("Unknown language id {id}"))?;
+        let probability = usize::try_from(id)
+            .ok()
+            .and_then(|i| probs.get(i).copied())
+            .unwrap_or(0.0);
+        Ok((code, probability))
+    }
+
+    /// Replace the whisper state with a fresh one after a failed run.
+    fn reset_state(&mut self) {
+        match self.ctx.create_state() {
+            Ok(state) => self.state = state,
+            Err(e) => warn!("Failed to recreate whisper state: {e}"),
+        }
+    }
+}
+
+/// English-only models can't transcribe anything else: force "en" for them.
+/// Fine-tunes for one language still work with others, only worse: warn.
+fn model_language<'a>(model_path: &str, language: &'a str) -> &'a str {
+    if space_tts_common::models::is_english_only(model_path) && language != "en" {
+        warn!("{model_path} is an English-only model, ignoring --language {language}");
+        return "en";
+    }
+    if let Some(intended) = space_tts_common::models::model_language(model_path)
+        && intended != language
+        && language != languages::AUTO
+    {
+        warn!(
+            "{model_path} looks made for '{intended}' but --language is {language}: expect poor results"
+        );
+    }
+    language
+}
+
+/// The language to transcribe a segment in with `--language auto` and a
+/// fallback: the `detect`ed one when whisper is sure enough, else the fallback.
+fn gated_language(
+    detect: impl FnOnce() -> Result<(&'static str, f32)>,
+    fallback: &LanguageFallback,
+) -> String {
+    match detect() {
+        Ok((code, probability)) if probability >= fallback.min_confidence => {
+            debug!("Detected language: {code} (p={probability:.2})");
+            code.to_string()
+        }
+        Ok((code, probability)) => {
+            debug!(
+                "Detected language {code} only at p={probability:.2}, using {}",
+                fallback.language
+            );
+            fallback.language.clone()
+        }
+        Err(e) => {
+            warn!("{e:#}, using {}", fallback.language);
+            fallback.language.clone()
+        }
+    }
+}
+
+/// `language`, or for "auto" the one whisper `detected` last, if any.
+fn known_language<'a>(language: &'a str, detected: Option<&'static str>) -> &'a str {
+    match detected {
+        Some(code) if language == languages::AUTO => code,
+        _ => language,
+    }
+}
+
+fn full_params<'a>(
+    language: &'a str,
+    prompt: &str,
+    transcribe_params: &TranscribeParams,
+) -> FullParams<'a, 'a> {
+    // whisper.cpp ignores beam-search patience (still a TODO there)
+    let mut params = FullParams::new(SamplingStrategy::BeamSearch {
+        beam_size: 5,
+        patience: -1.0,
+    });
+    params.set_language(Some(language));
+    params.set_print_special(false);
+    params.set_print_progress(false);
+    params.set_print_realtime(false);
+    params.set_print_timestamps(false);
+    params.set_suppress_nst(true);
+    params.set_no_speech_thold(0.6);
+    // Initial prompt helps Whisper stay in the target language and use proper vocabulary
+    params.set_initial_prompt(prompt);
+    transcribe_params.apply(&mut params);
+    params
+}
+
+/// Pads segments shorter than `min_samples` with leading silence before handing
+/// them to `inner`: whisper expects about a second of context and tends to
+/// return nothing (or a hallucination) for a lone short word.
+pub struct PaddedTranscriber {
+    inner: Box<dyn Transcriber>,
+    min_samples: usize,
+}
+
+impl PaddedTranscriber {
+    pub fn new(inner: Box<dyn Transcriber>, min_samples: usize) -> Self {
+        Self { inner, min_samples }
+    }
+}
+
+impl Transcriber for PaddedTranscriber {
+    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>> {
+        self.inner
+            .transcribe(&pad_leading(audio_i16, self.min_samples))
+    }
+
+    fn reset_context(&mut self) {
+        self.inner.reset_context();
+    }
+
+    fn set_language(&mut self, language: &str) -> Result<()> {
+        self.inner.set_language(language)
+    }
+}
+
+/// `audio` preceded by enough silence to be `min_samples` long.
+fn pad_leading(audio: &[i16], min_samples: usize) -> Cow<'_, [i16]> {
+    if audio.len() >= min_samples {
+        return Cow::Borrowed(audio);
+    }
+    let mut padded = vec![0; min_samples - audio.len()];
+    padded.extend_from_slice(audio);
+    Cow::Owned(padded)
+}
+
+/// Encode/decode failures are typically transient (GPU hiccups) and worth one retry;
+/// anything else (no samples, bad parameters) will fail the same way again.
+fn is_recoverable(e: &WhisperError) -> bool {
+    matches!(
+        e,
+        WhisperError::FailedToEncode
+            | WhisperError::FailedToDecode
+            | WhisperError::UnableToCalculateSpectrogram
+            | WhisperError::GenericError(_)
+    )
+}
+
+/// Run `run` on `target`, and on a recoverable error `reset` it and try once more,
+/// unless `timed_out`: a run aborted by the deadline looks recoverable too.
+fn run_with_retry<S, T>(
+    target: &mut S,
+    mut run: impl FnMut(&mut S) -> Result<T, WhisperError>,
+    reset: impl FnOnce(&mut S),
+    timed_out: impl Fn() -> bool,
+) -> Result<T, WhisperError> {
+    match run(target) {
+        Err(e) if is_recoverable(&e) && !timed_out() => {
+            warn!("Transcription error: {e}, retrying once");
+            reset(target);
+            run(target)
+        }
+        result => result,
+    }
 }
 
 impl Transcriber for LocalTranscriber {
-    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<String> {
+    fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>> {
         // Convert i16 to f32
         let mut audio_f32 = vec![0.0f32; audio_i16.len()];
         convert_integer_to_float_audio(audio_i16, &mut audio_f32)
             .map_err(|e| anyhow::anyhow!("Audio conversion failed: {e}"))?;
 
-        let mut params = FullParams::new(SamplingStrategy::BeamSearch {
-            beam_size: 5,
-            patience: -1.0,
-        });
-        params.set_language(Some(&self.language));
-        params.set_print_special(false);
-        params.set_print_progress(false);
-        params.set_print_realtime(false);
-        params.set_print_timestamps(false);
-        params.set_suppress_nst(true);
-        params.set_no_speech_thold(0.6);
-        // Initial prompt helps Whisper stay in the target language and use proper vocabulary
-        params.set_initial_prompt(initial_prompt(&self.language));
-
-        if let Err(e) = self.state.full(params, &audio_f32) {
+        // Set before the fallback's detection, which can't be aborted but
+        // uses up the deadline (whisper then gives up as soon as it starts)
+        let deadline = self.params.timeout.map(|timeout| Instant::now() + timeout);
+        let timed_out = || deadline.is_some_and(|d| Instant::now() >= d);
+        // "auto" with a fallback: detected first, to pick the language to run in.
+        // Without, whisper detects during the run itself, and the prompt
+        // follows the language of the last segment
+        let language = match self.params.language_fallback.clone() {
+            Some(fallback) if self.language == languages::AUTO => {
+                gated_language(|| self.detect_language(&audio_f32), &fallback)
+            }
+            _ => self.language.clone(),
+        };
+        let prompt_language = known_language(&language, self.detected);
+        let prompt = context_prompt(
+            initial_prompt(prompt_language),
+            &self.context,
+            prompt_language,
+        );
+        let result = run_with_retry(
+            self,
+            |t| {
+                let mut params = full_params(&language, &prompt, &t.params);
+                if let Some(deadline) = deadline {
+                    params.set_abort_callback_safe(move || Instant::now() >= deadline);
+                }
+                t.state.full(params, &audio_f32)
+            },
+            |t| t.reset_state(),
+            timed_out,
+        );
+        if result.is_err() && timed_out() {
+            warn!(
+                "Transcription took over {:?}, segment skipped.",
+                self.params.timeout.unwrap_or_default()
+            );
+            self.reset_state();
+            return Ok(vec![String::new()]);
+        }
+        if let Err(e) = result {
             warn!("Transcription error: {e}");
-            return Ok(String::new());
+            return Ok(vec![String::new()]);
         }
 
         let mut text = String::new();
@@ -63,64 +383,214 @@ impl Transcriber for LocalTranscriber {
             }
         }
 
-        let text = text.trim().to_string();
-        Ok(filter_hallucinations(&text))
+        if language == languages::AUTO {
+            self.detected = self
+                .state
+                .full_lang_id_from_state()
+                .ok()
+                .and_then(whisper_rs::get_lang_str);
+            debug!("Detected language: {}", self.detected.unwrap_or("unknown"));
+        }
+        let text = apply_filter(
+            text.trim(),
+            self.params.filter_level,
+            known_language(&language, self.detected),
+        );
+        if self.params.context_carryover && !text.is_empty() {
+            if self.context.len() == CONTEXT_SEGMENTS {
+                self.context.pop_front();
+            }
+            self.context.push_back(text.clone());
+        }
+        Ok(vec![text])
+    }
+
+    fn reset_context(&mut self) {
+        self.context.clear();
+    }
+
+    fn set_language(&mut self, language: &str) -> Result<()> {
+        if self.english_only && language != "en" {
+            anyhow::bail!("English-only model, can't switch to '{language}'");
+        }
+        self.language = language.to_string();
+        // Earlier text would prompt whisper towards the old language
+        self.context.clear();
+        self.detected = None;
+        Ok(())
     }
 }
 
-/// Filter out common Whisper hallucinations (YouTube subtitle artifacts).
-/// Returns empty string if the entire text is a hallucination.
-fn filter_hallucinations(text: &str) -> String {
-    // Long, specific patterns — safe to match anywhere (trailing match)
-    const TRAILING_HALLUCINATIONS: &[&str] = &[
+fn apply_filter(text: &str, level: FilterLevel, language: &str) -> String {
+    match level {
+        FilterLevel::Off => text.to_string(),
+        FilterLevel::Conservative => {
+            if is_full_hallucination(&text.to_lowercase(), language) {
+                String::new()
+            } else {
+                text.to_string()
+            }
+        }
+        FilterLevel::Aggressive => filter_hallucinations(text, language),
+    }
+}
+
+/// Subtitle artifacts Whisper hallucinates for one language.
+struct Hallucinations {
+    /// Long, specific patterns — safe to match anywhere (trailing match)
+    trailing: &'static [&'static str],
+    /// Short/generic patterns — only discard if they are the ENTIRE output
+    fullmatch: &'static [&'static str],
+    /// A lone "thanks" often appended at the end of the output
+    lone_thanks: Option<&'static str>,
+}
+
+// Whisper produces these English artifacts whatever the language
+const COMMON_HALLUCINATIONS: Hallucinations = Hallucinations {
+    trailing: &[
+        "like and subscribe",
+        "please subscribe",
+        "thanks for watching",
+        "thank you for watching",
+    ],
+    fullmatch: &["subscribe"],
+    lone_thanks: None,
+};
+
+const FRENCH_HALLUCINATIONS: Hallucinations = Hallucinations {
+    trailing: &[
         "merci d'avoir regardé",
         "merci d'avoir regardé la vidéo",
         "merci d'avoir regardé cette vidéo",
         "merci de votre attention",
         "sous-titres réalisés par",
         "sous-titrage société radio-canada",
-        "like and subscribe",
-        "please subscribe",
-        "thanks for watching",
-        "thank you for watching",
-    ];
-
-    // Short/generic patterns — only discard if they are the ENTIRE output
-    const FULLMATCH_HALLUCINATIONS: &[&str] = &[
+    ],
+    fullmatch: &[
         "sous-titres par",
         "sous-titrage st'",
         "sous-titrage",
         "société radio-canada",
-        "subscribe",
         "merci",
-    ];
+    ],
+    lone_thanks: Some("merci"),
+};
+
+const GERMAN_HALLUCINATIONS: Hallucinations = Hallucinations {
+    trailing: &[
+        "vielen dank fürs zuschauen",
+        "danke fürs zuschauen",
+        "untertitel im auftrag des zdf",
+        "untertitel der amara.org-community",
+        "untertitelung aufgrund der amara.org-community",
+        "untertitel von stephanie geiges",
+    ],
+    fullmatch: &["untertitel", "vielen dank", "danke", "bis zum nächsten mal"],
+    lone_thanks: None,
+};
+
+const SPANISH_HALLUCINATIONS: Hallucinations = Hallucinations {
+    trailing: &[
+        "gracias por ver el video",
+        "gracias por ver el vídeo",
+        "subtítulos realizados por la comunidad de amara.org",
+        "suscríbete al canal",
+        "no olvides suscribirte",
+    ],
+    fullmatch: &[
+        "subtítulos",
+        "gracias",
+        "gracias por ver",
+        "suscríbete",
+        "muchas gracias",
+    ],
+    lone_thanks: None,
+};
+
+const NO_HALLUCINATIONS: Hallucinations = Hallucinations {
+    trailing: &[],
+    fullmatch: &[],
+    lone_thanks: None,
+};
+
+/// Patterns checked for `language`: the common set plus the language's own.
+fn hallucinations(language: &str) -> [&'static Hallucinations; 2] {
+    let specific = match language {
+        "fr" => &FRENCH_HALLUCINATIONS,
+        "de" => &GERMAN_HALLUCINATIONS,
+        "es" => &SPANISH_HALLUCINATIONS,
+        _ => &NO_HALLUCINATIONS,
+    };
+    [&COMMON_HALLUCINATIONS, specific]
+}
+
+/// Drop surrounding punctuation so "¡Gracias!" compares equal to "gracias".
+fn strip_punctuation(lower: &str) -> &str {
+    lower
+        .trim()
+        .trim_start_matches(['¡', '¿'])
+        .trim_end_matches(['.', '!', '?', ' ', ','])
+}
 
+/// True when the whole (lowercased) output is a known hallucination.
+fn is_full_hallucination(lower: &str, language: &str) -> bool {
+    let stripped = strip_punctuation(lower);
+    hallucinations(language).iter().any(|set| {
+        set.trailing
+            .iter()
+            .chain(set.fullmatch.iter())
+            .any(|pattern| stripped == *pattern)
+    })
+}
+
+/// Lowercase `text`, recording for each byte of the result the offset of the
+/// original char it came from. Lowercasing can change byte lengths ('İ' becomes
+/// "i̇"), so a position found in the lowercased text is not one in `text`.
+fn lowercase_with_offsets(text: &str) -> (String, Vec<usize>) {
+    let mut lower = String::with_capacity(text.len());
+    let mut offsets = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        for l in c.to_lowercase() {
+            lower.push(l);
+        }
+        offsets.resize(lower.len(), i);
+    }
+    (lower, offsets)
+}
+
+/// Filter out common Whisper hallucinations (YouTube subtitle artifacts) for `language`.
+/// Returns empty string if the entire text is a hallucination.
+fn filter_hallucinations(text: &str, language: &str) -> String {
     if is_repetitive(&text.to_lowercase()) {
         return String::new();
     }
 
     let lower = text.to_lowercase();
-    let stripped = lower.trim_end_matches(['.', '!', '?', ' ', ',']);
 
     // Full-match check: both lists
-    for pattern in TRAILING_HALLUCINATIONS.iter().chain(FULLMATCH_HALLUCINATIONS.iter()) {
-        if stripped == *pattern {
-            return String::new();
-        }
+    if is_full_hallucination(&lower, language) {
+        return String::new();
     }
 
+    let sets = hallucinations(language);
+
     // Trailing match: only long specific patterns
     let mut result = text.to_string();
-    for pattern in TRAILING_HALLUCINATIONS {
-        if let Some(pos) = lower.find(pattern) {
-            result.truncate(pos);
+    let (lower_mapped, offsets) = lowercase_with_offsets(text);
+    for pattern in sets.iter().flat_map(|set| set.trailing) {
+        if let Some(pos) = lower_mapped.find(pattern) {
+            result.truncate(offsets[pos]);
         }
     }
 
-    // Strip trailing lone "Merci !" / "Merci!" often appended
-    let trimmed = result.trim().trim_end_matches('!').trim();
-    if trimmed.ends_with("Merci") || trimmed.ends_with("merci") {
-        if let Some(pos) = result.to_lowercase().rfind("merci") {
+    // Strip a trailing lone "Merci !" / "Merci!" often appended
+    for thanks in sets.iter().filter_map(|set| set.lone_thanks) {
+        let trimmed = result.trim().trim_end_matches('!').trim().to_lowercase();
+        if !trimmed.ends_with(thanks) {
+            continue;
+        }
+        let (lower_result, offsets) = lowercase_with_offsets(&result);
+        if let Some(pos) = lower_result.rfind(thanks).map(|p| offsets[p]) {
             let before = &result[..pos];
             if before.is_empty()
                 || before.ends_with(' ')
@@ -141,11 +611,13 @@ fn filter_hallucinations(text: &str) -> String {
         return String::new();
     }
     let remaining = result.to_lowercase();
-    let remaining_stripped = remaining.trim_end_matches(['.', '!', '?', ' ', ',']);
-    for pattern in FULLMATCH_HALLUCINATIONS {
-        if remaining_stripped == *pattern {
-            return String::new();
-        }
+    let remaining_stripped = strip_punctuation(&remaining);
+    if sets
+        .iter()
+        .flat_map(|set| set.fullmatch)
+        .any(|pattern| remaining_stripped == *pattern)
+    {
+        return String::new();
     }
     if is_repetitive(&remaining) {
         return String::new();
@@ -210,8 +682,49 @@ fn is_repetitive(text: &str) -> bool {
     false
 }
 
+/// Prompt for languages without their own: it mustn't name English, or whisper
+/// would lean towards translating.
+const NEUTRAL_PROMPT: &str = "Hello, this is a transcription.";
+
+/// Results carried over into the prompt with `context_carryover`.
+const CONTEXT_SEGMENTS: usize = 3;
+/// Cap on the carried-over text: whisper only keeps the end of a prompt longer
+/// than half its text context (224 tokens), and `base` must survive.
+const MAX_CONTEXT_CHARS: usize = 300;
+
+/// `base` followed by the recent results, keeping only their last
+/// `MAX_CONTEXT_CHARS` characters (from a word start in `language`, if it has spaces).
+fn context_prompt(base: &str, recent: &VecDeque<String>, language: &str) -> String {
+    let context = recent
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<_>>()
+        .join(" ");
+    let mut kept = context.as_str();
+    let excess = kept.chars().count().saturating_sub(MAX_CONTEXT_CHARS);
+    if excess > 0 {
+        let cut = kept
+            .char_indices()
+            .nth(excess)
+            .map_or(kept.len(), |(i, _)| i);
+        kept = &kept[cut..];
+        // Don't start mid-word: without spaces, any character will do
+        if !languages::is_unspaced(language) {
+            kept = kept
+                .split_once(char::is_whitespace)
+                .map_or(kept, |(_, rest)| rest);
+        }
+    }
+    if kept.is_empty() {
+        base.to_string()
+    } else {
+        format!("{base} {kept}")
+    }
+}
+
 fn initial_prompt(language: &str) -> &'static str {
     match language {
+        "en" => "Hello, this is an English transcription.",
         "fr" => "Bonjour, ceci est une transcription en français.",
         "de" => "Hallo, dies ist eine Transkription auf Deutsch.",
         "es" => "Hola, esta es una transcripción en español.",
@@ -219,7 +732,7 @@ fn initial_prompt(language: &str) -> &'static str {
         "pt" => "Olá, esta é uma transcrição em português.",
         "ja" => "こんにちは、これは日本語の文字起こしです。",
         "zh" => "你好，这是中文转录。",
-        _ => "Hello, this is an English transcription.",
+        _ => NEUTRAL_PROMPT,
     }
 }
 
@@ -227,66 +740,366 @@ fn initial_prompt(language: &str) -> &'static str {
 mod tests {
     use super::*;
 
+    /// Stand-in for a whisper state that fails a given number of times.
+    struct FlakyState {
+        failures_left: u32,
+        resets: u32,
+    }
+
+    impl FlakyState {
+        fn run(&mut self) -> Result<String, WhisperError> {
+            if self.failures_left > 0 {
+                self.failures_left -= 1;
+                Err(WhisperError::FailedToDecode)
+            } else {
+                Ok("bonjour".to_string())
+            }
+        }
+    }
+
+    /// Panics on empty segments, like an upstream bug on malformed input.
+    struct PanickyTranscriber;
+
+    impl Transcriber for PanickyTranscriber {
+        fn transcribe(&mut self, audio_i16: &[i16]) -> Result<Vec<String>> {
+            assert!(!audio_i16.is_empty(), "empty segment");
+            Ok(vec![format!("{} samples", audio_i16.len())])
+        }
+    }
+
+    #[test]
+    fn auto_follows_the_detected_language() {
+        assert_eq!(known_language(languages::AUTO, Some("ja")), "ja");
+        assert_eq!(known_language(languages::AUTO, None), languages::AUTO);
+        assert_eq!(known_language("fr", Some("ja")), "fr");
+    }
+
+    #[test]
+    fn unsure_detection_falls_back() {
+        let fallback = LanguageFallback {
+            language: "fr".to_string(),
+            min_confidence: DEFAULT_MIN_LANGUAGE_CONFIDENCE,
+        };
+        // Stand-ins for whisper's detection on a clear and a short, noisy clip
+        assert_eq!(gated_language(|| Ok(("de", 0.92)), &fallback), "de");
+        assert_eq!(gated_language(|| Ok(("de", 0.31)), &fallback), "fr");
+        assert_eq!(
+            gated_language(|| Err(anyhow::anyhow!("no samples")), &fallback),
+            "fr"
+        );
+    }
+
+    #[test]
+    fn panic_on_one_segment_spares_the_next() {
+        let mut transcriber = PanickyTranscriber;
+        let results: Vec<_> = [&[][..], &[1, 2][..]]
+            .iter()
+            .map(|audio| space_tts_common::panic::catch(|| transcriber.transcribe(audio)))
+            .collect();
+        assert!(
+            results[0]
+                .as_ref()
+                .unwrap_err()
+                .to_string()
+                .contains("empty segment")
+        );
+        assert_eq!(results[1].as_ref().unwrap(), &["2 samples"]);
+    }
+
+    #[test]
+    fn short_segments_are_padded_before_transcription() {
+        let mut transcriber = PaddedTranscriber::new(Box::new(PanickyTranscriber), 16000);
+        assert_eq!(
+            transcriber.transcribe(&[7; 3200]).unwrap(),
+            ["16000 samples"]
+        );
+        assert_eq!(
+            transcriber.transcribe(&[7; 20000]).unwrap(),
+            ["20000 samples"]
+        );
+
+        let padded = pad_leading(&[7, 7], 5);
+        assert_eq!(&*padded, &[0, 0, 0, 7, 7], "Silence goes before the speech");
+    }
+
+    #[test]
+    fn unlisted_language_gets_neutral_prompt() {
+        let code = space_tts_common::languages::validate("nl").unwrap();
+        assert_eq!(initial_prompt(&code), NEUTRAL_PROMPT);
+        assert_ne!(initial_prompt("en"), NEUTRAL_PROMPT);
+        assert!(initial_prompt("fr").contains("français"));
+    }
+
+    #[test]
+    fn context_prompt_includes_recent_text() {
+        let base = initial_prompt("en");
+        assert_eq!(context_prompt(base, &VecDeque::new(), "en"), base);
+
+        let recent = VecDeque::from([
+            "Ask Siobhan.".to_string(),
+            "She knows Kubernetes.".to_string(),
+        ]);
+        assert_eq!(
+            context_prompt(base, &recent, "en"),
+            format!("{base} Ask Siobhan. She knows Kubernetes.")
+        );
+
+        // Too long: the oldest words go, the base and the latest text stay
+        let recent = VecDeque::from([["word"; 100].join(" "), "latest".to_string()]);
+        let prompt = context_prompt(base, &recent, "en");
+        assert!(
+            prompt.starts_with(base) && prompt.ends_with("word latest"),
+            "{prompt}"
+        );
+        assert!(prompt.len() - base.len() <= MAX_CONTEXT_CHARS + 1);
+
+        // Japanese has no word to wait for: the cut keeps exactly the last characters
+        let base = initial_prompt("ja");
+        let recent = VecDeque::from(["あ".repeat(MAX_CONTEXT_CHARS), "いう えお".repeat(10)]);
+        let prompt = context_prompt(base, &recent, "ja");
+        let kept = &prompt[base.len() + 1..];
+        assert_eq!(kept.chars().count(), MAX_CONTEXT_CHARS);
+        assert!(kept.starts_with('あ'), "{kept}");
+    }
+
+    #[test]
+    fn retry_recovers_from_one_transient_failure() {
+        let mut state = FlakyState {
+            failures_left: 1,
+            resets: 0,
+        };
+        let result = run_with_retry(&mut state, |s| s.run(), |s| s.resets += 1, || false);
+        assert_eq!(result.unwrap(), "bonjour");
+        assert_eq!(state.resets, 1);
+    }
+
+    #[test]
+    fn retry_gives_up_after_second_failure() {
+        let mut state = FlakyState {
+            failures_left: 2,
+            resets: 0,
+        };
+        let result = run_with_retry(&mut state, |s| s.run(), |s| s.resets += 1, || false);
+        assert!(result.is_err());
+        assert_eq!(state.resets, 1);
+    }
+
+    #[test]
+    fn timed_out_run_is_not_retried() {
+        let mut state = FlakyState {
+            failures_left: 1,
+            resets: 0,
+        };
+        let result = run_with_retry(&mut state, |s| s.run(), |s| s.resets += 1, || true);
+        assert!(result.is_err());
+        assert_eq!(state.resets, 0);
+    }
+
+    #[test]
+    fn fatal_error_is_not_retried() {
+        let mut resets = 0;
+        let result: Result<(), WhisperError> = run_with_retry(
+            &mut resets,
+            |_| Err(WhisperError::NoSamples),
+            |r| *r += 1,
+            || false,
+        );
+        assert!(result.is_err());
+        assert_eq!(resets, 0);
+    }
+
     #[test]
     fn filter_full_hallucination() {
-        assert_eq!(filter_hallucinations("Merci d'avoir regardé la vidéo!"), "");
-        assert_eq!(filter_hallucinations("Merci d'avoir regardé."), "");
-        assert_eq!(filter_hallucinations("Thanks for watching"), "");
-        assert_eq!(filter_hallucinations("Sous-titrage Société Radio-Canada"), "");
-        assert_eq!(filter_hallucinations("Sous-titrage"), "");
-        assert_eq!(filter_hallucinations("Subscribe"), "");
+        assert_eq!(
+            filter_hallucinations("Merci d'avoir regardé la vidéo!", "fr"),
+            ""
+        );
+        assert_eq!(filter_hallucinations("Merci d'avoir regardé.", "fr"), "");
+        assert_eq!(filter_hallucinations("Thanks for watching", "fr"), "");
+        assert_eq!(
+            filter_hallucinations("Sous-titrage Société Radio-Canada", "fr"),
+            ""
+        );
+        assert_eq!(filter_hallucinations("Sous-titrage", "fr"), "");
+        assert_eq!(filter_hallucinations("Subscribe", "fr"), "");
     }
 
     #[test]
     fn filter_repetitive_hallucination() {
-        assert_eq!(filter_hallucinations("MerciMerciMerci"), "");
-        assert_eq!(filter_hallucinations("merci merci merci"), "");
-        assert_eq!(filter_hallucinations("Thank you. Thank you. Thank you."), "");
-        assert_eq!(filter_hallucinations("you you you you"), "");
+        assert_eq!(filter_hallucinations("MerciMerciMerci", "fr"), "");
+        assert_eq!(filter_hallucinations("merci merci merci", "fr"), "");
+        assert_eq!(
+            filter_hallucinations("Thank you. Thank you. Thank you.", "fr"),
+            ""
+        );
+        assert_eq!(filter_hallucinations("you you you you", "fr"), "");
     }
 
     #[test]
     fn filter_trailing_hallucination() {
         assert_eq!(
-            filter_hallucinations("Bonjour tout le monde. Merci d'avoir regardé la vidéo!"),
+            filter_hallucinations(
+                "Bonjour tout le monde. Merci d'avoir regardé la vidéo!",
+                "fr"
+            ),
             "Bonjour tout le monde"
         );
         assert_eq!(
-            filter_hallucinations("Bonjour. Sous-titrage Société Radio-Canada"),
+            filter_hallucinations("Bonjour. Sous-titrage Société Radio-Canada", "fr"),
             "Bonjour"
         );
     }
 
+    #[test]
+    fn filter_trailing_after_multibyte_lowercasing() {
+        // 'İ' lowercases to two chars (3 bytes instead of 2): offsets in the
+        // lowercased text no longer line up with the original
+        assert_eq!(
+            filter_hallucinations(
+                "İİİ İstanbul'a gittim. Merci d'avoir regardé la vidéo!",
+                "fr"
+            ),
+            "İİİ İstanbul'a gittim"
+        );
+        assert_eq!(
+            filter_hallucinations("İzmir güzel bir şehir. Merci !", "fr"),
+            "İzmir güzel bir şehir"
+        );
+    }
+
     #[test]
     fn filter_trailing_merci() {
         assert_eq!(
-            filter_hallucinations("Il fait beau aujourd'hui. Merci!"),
+            filter_hallucinations("Il fait beau aujourd'hui. Merci!", "fr"),
             "Il fait beau aujourd'hui"
         );
         assert_eq!(
-            filter_hallucinations("Il fait beau aujourd'hui. Merci !"),
+            filter_hallucinations("Il fait beau aujourd'hui. Merci !", "fr"),
             "Il fait beau aujourd'hui"
         );
     }
 
+    #[test]
+    fn filter_levels_on_same_inputs() {
+        let full = "Merci d'avoir regardé la vidéo!";
+        let trailing = "Bonjour tout le monde. Merci d'avoir regardé la vidéo!";
+        let repetitive = "merci merci merci";
+
+        assert_eq!(apply_filter(full, FilterLevel::Off, "fr"), full);
+        assert_eq!(apply_filter(trailing, FilterLevel::Off, "fr"), trailing);
+        assert_eq!(apply_filter(repetitive, FilterLevel::Off, "fr"), repetitive);
+
+        assert_eq!(apply_filter(full, FilterLevel::Conservative, "fr"), "");
+        assert_eq!(
+            apply_filter(trailing, FilterLevel::Conservative, "fr"),
+            trailing
+        );
+        assert_eq!(
+            apply_filter(repetitive, FilterLevel::Conservative, "fr"),
+            repetitive
+        );
+
+        assert_eq!(apply_filter(full, FilterLevel::Aggressive, "fr"), "");
+        assert_eq!(
+            apply_filter(trailing, FilterLevel::Aggressive, "fr"),
+            "Bonjour tout le monde"
+        );
+        assert_eq!(apply_filter(repetitive, FilterLevel::Aggressive, "fr"), "");
+    }
+
+    #[test]
+    fn filter_german_artifacts() {
+        assert_eq!(
+            filter_hallucinations("Vielen Dank fürs Zuschauen!", "de"),
+            ""
+        );
+        assert_eq!(
+            filter_hallucinations("Untertitel im Auftrag des ZDF, 2021", "de"),
+            ""
+        );
+        assert_eq!(
+            filter_hallucinations(
+                "Das Meeting ist um drei. Untertitel im Auftrag des ZDF",
+                "de"
+            ),
+            "Das Meeting ist um drei"
+        );
+        assert_eq!(
+            filter_hallucinations("Ich habe die Untertitel eingeschaltet", "de"),
+            "Ich habe die Untertitel eingeschaltet"
+        );
+        // Common English artifacts apply to every language
+        assert_eq!(filter_hallucinations("Thanks for watching!", "de"), "");
+    }
+
+    #[test]
+    fn filter_spanish_artifacts() {
+        assert_eq!(
+            filter_hallucinations("¡Gracias por ver el video!", "es"),
+            ""
+        );
+        assert_eq!(filter_hallucinations("¡Gracias!", "es"), "");
+        assert_eq!(filter_hallucinations("Gracias por ver.", "es"), "");
+        assert_eq!(
+            filter_hallucinations("Fue genial y gracias por ver esto conmigo", "es"),
+            "Fue genial y gracias por ver esto conmigo"
+        );
+        assert_eq!(
+            filter_hallucinations(
+                "Nos vemos mañana. Subtítulos realizados por la comunidad de Amara.org",
+                "es"
+            ),
+            "Nos vemos mañana"
+        );
+        assert_eq!(
+            filter_hallucinations("Muchas gracias por tu ayuda con el proyecto", "es"),
+            "Muchas gracias por tu ayuda con el proyecto"
+        );
+    }
+
+    #[test]
+    fn filter_patterns_are_language_specific() {
+        // French artifacts are left alone outside French...
+        assert_eq!(filter_hallucinations("Merci", "en"), "Merci");
+        assert_eq!(
+            filter_hallucinations("It went well. Merci!", "en"),
+            "It went well. Merci"
+        );
+        // ...and German ones outside German
+        assert_eq!(filter_hallucinations("Danke", "fr"), "Danke");
+        assert_eq!(filter_hallucinations("Danke", "de"), "");
+    }
+
+    #[test]
+    fn decode_preset_fills_only_unset_thresholds() {
+        let mut params = TranscribeParams {
+            logprob_thold: Some(-0.8),
+            ..Default::default()
+        };
+        DecodePreset::parse("strict").unwrap().fill(&mut params);
+        assert_eq!(params.logprob_thold, Some(-0.8));
+        assert_eq!(params.entropy_thold, Some(2.8));
+        assert!(DecodePreset::parse("tight").is_err());
+    }
+
     #[test]
     fn filter_keeps_real_text() {
         assert_eq!(
-            filter_hallucinations("Bonjour, je suis Matthieu"),
+            filter_hallucinations("Bonjour, je suis Matthieu", "fr"),
             "Bonjour, je suis Matthieu"
         );
         // "merci" as part of real speech should be kept
         assert_eq!(
-            filter_hallucinations("Je te remercie pour ton aide"),
+            filter_hallucinations("Je te remercie pour ton aide", "fr"),
             "Je te remercie pour ton aide"
         );
         // Short patterns used in real speech must NOT be stripped mid-sentence
         assert_eq!(
-            filter_hallucinations("Je veux activer le sous-titrage automatique"),
+            filter_hallucinations("Je veux activer le sous-titrage automatique", "fr"),
             "Je veux activer le sous-titrage automatique"
         );
         assert_eq!(
-            filter_hallucinations("I need to subscribe to the service"),
+            filter_hallucinations("I need to subscribe to the service", "fr"),
             "I need to subscribe to the service"
         );
     }
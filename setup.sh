@@ -1,7 +1,7 @@
 #!/usr/bin/env bash
 set -euo pipefail
 
-MODELS_DIR="$HOME/.local/share/space_tts/models"
+MODELS_DIR="${XDG_DATA_HOME:-$HOME/.local/share}/space_tts/models"
 DOTOOL_REPO="https://git.sr.ht/~geb/dotool"
 HF_BASE="https://huggingface.co/ggerganov/whisper.cpp/resolve/main"
 
@@ -317,7 +317,7 @@ do_uninstall() {
         if [[ "$REPLY" =~ ^[yY]$ ]]; then
             rm -rf "$MODELS_DIR"
             # Remove parent dirs if empty
-            rmdir --ignore-fail-on-non-empty "$HOME/.local/share/space_tts" 2>/dev/null || true
+            rmdir --ignore-fail-on-non-empty "$(dirname "$MODELS_DIR")" 2>/dev/null || true
             info "Models removed."
         fi
     fi